@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use tracing::instrument;
+
+use crate::ast::{
+    Comparison, Condition, ConditionKind, Expression, ExpressionKind, Primary, PrimaryKind,
+    Statement, StatementKind, Target, Term, TermKind, Unary, UnaryKind,
+};
+use crate::backend::{escape_bytes, fn_name, supported, unsupported};
+use crate::error::CompileError;
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::types::{ProcedureTypes, Scope};
+
+/// Runtime for printing, input, GOSUB and call depth, appended to every
+/// program.
+const RUNTIME: &str = include_str!("runtime/x86_64.s");
+
+/// Name of the backend in errors.
+const BACKEND: &str = "x86-64 assembly";
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+/// Compiles a checked program to x86-64 assembly for Linux, in GAS syntax,
+/// to be assembled with `as` and linked with `ld`.
+///
+/// Only integers are supported: programs using floats, string values,
+/// built-in functions or arrays are rejected, PRINT of a string literal
+/// being the one use of strings allowed.
+#[instrument(skip_all)]
+pub fn compile(parser: &Parser) -> Result<String> {
+    let mut compiler = Compiler {
+        code: String::new(),
+        procedures: &parser.procedures,
+        types: &parser.types,
+        frame: None,
+        prefix: ".Ltb".to_string(),
+        globals: Vec::new(),
+        strings: HashMap::new(),
+        rodata: String::new(),
+        labels: 0,
+    };
+
+    let mut variables: Vec<&String> = parser.types.variables.keys().collect();
+    variables.sort();
+    compiler.globals = variables.into_iter().map(|var| global_name(var)).collect();
+
+    compiler.line("# Generated by basic_compiler");
+    compiler.line("\t.text");
+    compiler.line("\t.globl _start");
+    compiler.line("_start:");
+    for statement in &parser.ast.program {
+        compiler.statement(statement)?;
+    }
+    compiler.line("\txor %edi, %edi");
+    compiler.line("\tjmp tb_exit");
+
+    for procedure in &parser.ast.procedures {
+        compiler.procedure(
+            &procedure.name,
+            &procedure.params,
+            &procedure.body,
+            procedure.span,
+        )?;
+    }
+
+    let mut out = compiler.code;
+    out.push('\n');
+    out.push_str(RUNTIME);
+    if !compiler.rodata.is_empty() {
+        out.push_str("\n\t.section .rodata\n");
+        out.push_str(&compiler.rodata);
+    }
+    if !compiler.globals.is_empty() {
+        out.push_str("\n\t.bss\n\t.balign 8\n");
+        for global in &compiler.globals {
+            let _ = writeln!(out, "{global}:\t.skip 8");
+        }
+    }
+    out.push_str("\n\t.section .note.GNU-stack,\"\",@progbits\n");
+    Ok(out)
+}
+
+/// Variables of the procedure being compiled, in its stack frame.
+struct Frame {
+    /// Offset from `%rbp` of every variable. Parameters are above it, where
+    /// the caller pushed them, the other variables below it.
+    offsets: HashMap<String, i64>,
+    /// Number of variables below `%rbp`.
+    locals: i64,
+    /// Label RETURN jumps to, to leave the procedure.
+    exit: String,
+}
+
+struct Compiler<'a> {
+    code: String,
+    procedures: &'a HashMap<String, ProcedureTypes>,
+    /// Types of the body being compiled.
+    types: &'a Scope,
+    /// `None` while compiling the main program.
+    frame: Option<Frame>,
+    /// Start of the labels of the body being compiled, labels of different
+    /// procedures may have the same name.
+    prefix: String,
+    /// Symbols of the variables of the main program, including hidden FOR
+    /// loop variables.
+    globals: Vec<String>,
+    /// Label of every string literal and error message.
+    strings: HashMap<String, String>,
+    rodata: String,
+    labels: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn line(&mut self, line: &str) {
+        self.code.push_str(line);
+        self.code.push('\n');
+    }
+
+    fn new_label(&mut self) -> String {
+        self.labels += 1;
+        format!(".L{}", self.labels)
+    }
+
+    fn place(&mut self, label: &str) {
+        let _ = writeln!(self.code, "{label}:");
+    }
+
+    /// Each procedure is a function taking its arguments on the stack, the
+    /// first pushed first, and giving its result in `%rax`. Its variables
+    /// start out as zero.
+    fn procedure(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &'a Statement,
+        span: Span,
+    ) -> Result<()> {
+        let procedures = self.procedures;
+        let types = &procedures[name];
+        for ty in types.params.iter().chain(&types.result) {
+            supported(BACKEND, *ty, span)?;
+        }
+
+        let mut offsets = HashMap::new();
+        for (i, param) in params.iter().enumerate() {
+            offsets.insert(param.clone(), 16 + 8 * (params.len() - 1 - i) as i64);
+        }
+        let mut variables: Vec<&String> = types
+            .scope
+            .variables
+            .keys()
+            .filter(|var| !params.contains(var))
+            .collect();
+        variables.sort();
+        for (i, var) in variables.iter().enumerate() {
+            offsets.insert(var.to_string(), -8 * (i as i64 + 1));
+        }
+
+        let globals = std::mem::replace(&mut self.types, &types.scope);
+        let exit = self.new_label();
+        self.frame = Some(Frame {
+            offsets,
+            locals: variables.len() as i64,
+            exit: exit.clone(),
+        });
+        self.prefix = format!(".Lfn_{name}");
+        let code = std::mem::take(&mut self.code);
+
+        self.statement(body)?;
+
+        let body = std::mem::replace(&mut self.code, code);
+        let frame = self.frame.take().expect("the procedure has a frame");
+        let mut locals: Vec<(i64, &String)> = frame
+            .offsets
+            .iter()
+            .filter(|(_, offset)| **offset < 0)
+            .map(|(var, offset)| (-offset, var))
+            .collect();
+        locals.sort();
+        let _ = writeln!(self.code, "\n{}:", fn_name(name));
+        self.line("\tcall tb_call");
+        self.line("\tpush %rbp");
+        self.line("\tmov %rsp, %rbp");
+        for (_, var) in locals {
+            self.line(&format!("\tpushq $0\t\t\t# {var}"));
+        }
+        self.code.push_str(&body);
+        // Running off the end of a FUNCTION gives zero.
+        self.line("\txor %eax, %eax");
+        self.place(&exit);
+        self.line("\tleave");
+        self.line("\tdecq tb_call_depth(%rip)");
+        self.line("\tret");
+
+        self.types = globals;
+        self.prefix = ".Ltb".to_string();
+        Ok(())
+    }
+
+    /// Operand holding a variable, in the frame of a procedure or among the
+    /// globals of the main program.
+    fn variable(&self, var: &str, span: Span) -> Result<String> {
+        if let Some(ty) = self.types.variables.get(var) {
+            supported(BACKEND, *ty, span)?;
+        }
+        Ok(match &self.frame {
+            Some(frame) => format!("{}(%rbp)", frame.offsets[var]),
+            None => format!("{}(%rip)", global_name(var)),
+        })
+    }
+
+    /// Hidden variable holding the limit or step of a FOR loop, evaluated
+    /// once before the loop starts.
+    fn loop_variable(&mut self, part: &str) -> String {
+        self.labels += 1;
+        let name = format!("<{part} {}>", self.labels);
+        match &mut self.frame {
+            Some(frame) => {
+                frame.locals += 1;
+                frame.offsets.insert(name.clone(), -8 * frame.locals);
+                format!("{}(%rbp)", -8 * frame.locals)
+            }
+            None => {
+                let symbol = format!("tb_{part}_{}", self.labels);
+                self.globals.push(symbol.clone());
+                format!("{symbol}(%rip)")
+            }
+        }
+    }
+
+    /// Label of a string in the read-only data, added the first time.
+    fn string(&mut self, s: &str) -> String {
+        if let Some(label) = self.strings.get(s) {
+            return label.clone();
+        }
+        let label = format!(".Lstr{}", self.strings.len());
+        let _ = writeln!(self.rodata, "{label}:\n\t.ascii {}", gas_string(s));
+        self.strings.insert(s.to_string(), label.clone());
+        label
+    }
+
+    fn print_string(&mut self, s: &str) {
+        let label = self.string(s);
+        self.line(&format!("\tlea {label}(%rip), %rsi"));
+        self.line(&format!("\tmov ${}, %edx", s.len()));
+    }
+
+    fn statement(&mut self, s: &'a Statement) -> Result<()> {
+        match &s.kind {
+            StatementKind::Print(expr) => {
+                self.expression(expr)?;
+                self.line("\tmov %rax, %rdi");
+                self.line("\tcall tb_print_int");
+            }
+            StatementKind::PrintStr(string) => {
+                self.print_string(&format!("{string}\n"));
+                self.line("\tcall tb_print_str");
+            }
+            StatementKind::Dim(..) => return Err(unsupported(BACKEND, "arrays", s.span)),
+            StatementKind::Let(target, expr) => {
+                self.expression(expr)?;
+                let var = self.target(target, s.span)?;
+                self.line(&format!("\tmov %rax, {var}"));
+            }
+            StatementKind::Input(target) => {
+                let var = self.target(target, s.span)?;
+                self.print_string(&format!(" on line {}\n", s.span.line));
+                self.line("\tcall tb_input_int");
+                self.line(&format!("\tmov %rax, {var}"));
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                let skip = self.new_label();
+                self.condition(condition, &skip, false)?;
+                self.statement(then)?;
+                match otherwise {
+                    Some(otherwise) => {
+                        let end = self.new_label();
+                        self.line(&format!("\tjmp {end}"));
+                        self.place(&skip);
+                        self.statement(otherwise)?;
+                        self.place(&end);
+                    }
+                    None => self.place(&skip),
+                }
+            }
+            StatementKind::While(condition, body) => {
+                let (start, end) = (self.new_label(), self.new_label());
+                self.place(&start);
+                self.condition(condition, &end, false)?;
+                self.statement(body)?;
+                self.line(&format!("\tjmp {start}"));
+                self.place(&end);
+            }
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => self.for_loop(var, from, to, step.as_deref(), body, s.span)?,
+            StatementKind::Label(label) => {
+                let label = format!("{}.{label}", self.prefix);
+                self.place(&label);
+            }
+            StatementKind::Goto(label) => {
+                self.line(&format!("\tjmp {}.{label}", self.prefix));
+            }
+            StatementKind::Gosub(label) => {
+                let back = self.new_label();
+                self.line(&format!("\tlea {back}(%rip), %rax"));
+                self.line("\tcall tb_gosub");
+                self.line(&format!("\tjmp {}.{label}", self.prefix));
+                self.place(&back);
+            }
+            StatementKind::Return(value) => {
+                let exit = self.frame.as_ref().map(|frame| frame.exit.clone());
+                match (value, exit) {
+                    (_, None) => {
+                        self.line("\tcall tb_return");
+                        self.line("\tjmp *%rax");
+                    }
+                    (Some(expr), Some(exit)) => {
+                        self.expression(expr)?;
+                        self.line(&format!("\tjmp {exit}"));
+                    }
+                    (None, Some(exit)) => self.line(&format!("\tjmp {exit}")),
+                }
+            }
+            StatementKind::Call(name, args) => self.call(name, args)?,
+            StatementKind::Statement(first, rest) => {
+                self.statement(first)?;
+                self.statement(rest)?;
+            }
+            StatementKind::End => (),
+        }
+        Ok(())
+    }
+
+    fn target(&self, target: &Target, span: Span) -> Result<String> {
+        match target {
+            Target::Variable(var) => self.variable(var, span),
+            Target::Element(..) => Err(unsupported(BACKEND, "arrays", span)),
+        }
+    }
+
+    /// Evaluates in the same order as the C backend, see [`crate::backend`].
+    fn for_loop(
+        &mut self,
+        var: &str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+        body: &'a Statement,
+        span: Span,
+    ) -> Result<()> {
+        let var = self.variable(var, span)?;
+        self.expression(from)?;
+        self.line(&format!("\tmov %rax, {var}"));
+        let limit = self.loop_variable("to");
+        self.expression(to)?;
+        self.line(&format!("\tmov %rax, {limit}"));
+        let step = match step {
+            Some(step) => {
+                let name = self.loop_variable("step");
+                self.expression(step)?;
+                self.line(&format!("\tmov %rax, {name}"));
+                Some(name)
+            }
+            None => None,
+        };
+
+        let (start, end) = (self.new_label(), self.new_label());
+        self.place(&start);
+        self.line(&format!("\tmov {var}, %rax"));
+        match &step {
+            Some(step) => {
+                let (down, test_done) = (self.new_label(), self.new_label());
+                self.line(&format!("\tcmpq $0, {step}"));
+                self.line(&format!("\tjl {down}"));
+                self.line(&format!("\tcmp {limit}, %rax"));
+                self.line(&format!("\tjg {end}"));
+                self.line(&format!("\tjmp {test_done}"));
+                self.place(&down);
+                self.line(&format!("\tcmp {limit}, %rax"));
+                self.line(&format!("\tjl {end}"));
+                self.place(&test_done);
+            }
+            None => {
+                self.line(&format!("\tcmp {limit}, %rax"));
+                self.line(&format!("\tjg {end}"));
+            }
+        }
+
+        self.statement(body)?;
+        self.line(&format!("\tmov {var}, %rax"));
+        match &step {
+            Some(step) => self.line(&format!("\tadd {step}, %rax")),
+            None => self.line("\tadd $1, %rax"),
+        }
+        // A step that overflows is past the limit.
+        self.line(&format!("\tjo {end}"));
+        self.line(&format!("\tmov %rax, {var}"));
+        self.line(&format!("\tjmp {start}"));
+        self.place(&end);
+        Ok(())
+    }
+
+    /// Jumps to `target` if the condition is `when`, falls through otherwise.
+    /// AND and OR only evaluate their right side when it decides the result.
+    fn condition(&mut self, c: &'a Condition, target: &str, when: bool) -> Result<()> {
+        match &c.kind {
+            ConditionKind::Comparison(comparison) => {
+                self.comparison(comparison)?;
+                self.line("\ttest %rax, %rax");
+                let jump = if when { "jnz" } else { "jz" };
+                self.line(&format!("\t{jump} {target}"));
+            }
+            ConditionKind::Not(condition) => self.condition(condition, target, !when)?,
+            ConditionKind::And(left, right) | ConditionKind::Or(left, right) => {
+                // Whether the left side alone decides the condition.
+                let decides = matches!(c.kind, ConditionKind::Or(..));
+                if decides == when {
+                    self.condition(left, target, when)?;
+                    self.condition(right, target, when)?;
+                } else {
+                    let skip = self.new_label();
+                    self.condition(left, &skip, decides)?;
+                    self.condition(right, target, when)?;
+                    self.place(&skip);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves 1 in `%rax` if the comparison holds, 0 otherwise. A chain
+    /// compares the result of each comparison with the next operand, like C.
+    fn comparison(&mut self, c: &'a Comparison) -> Result<()> {
+        let (first, rest) = c.chain();
+        self.expression(first)?;
+        for (op, expr) in rest {
+            self.operands(expr)?;
+            let set = match op {
+                "==" => "sete",
+                "!=" => "setne",
+                "<" => "setl",
+                "<=" => "setle",
+                ">" => "setg",
+                ">=" => "setge",
+                _ => unreachable!("unknown comparison operator `{op}`"),
+            };
+            self.line("\tcmp %rcx, %rax");
+            self.line(&format!("\t{set} %al"));
+            self.line("\tmovzbl %al, %eax");
+        }
+        Ok(())
+    }
+
+    /// With the left operand in `%rax`, evaluates the right one, leaving it
+    /// in `%rcx` and the left one in `%rax`.
+    fn operands(&mut self, right: &'a Expression) -> Result<()> {
+        self.line("\tpush %rax");
+        self.expression(right)?;
+        self.line("\tmov %rax, %rcx");
+        self.line("\tpop %rax");
+        Ok(())
+    }
+
+    /// Leaves the value of the expression in `%rax`, spilling intermediate
+    /// values on the stack.
+    fn expression(&mut self, e: &'a Expression) -> Result<()> {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.term(term),
+            ExpressionKind::Add(expr, term) | ExpressionKind::Sub(expr, term) => {
+                self.expression(expr)?;
+                self.line("\tpush %rax");
+                self.term(term)?;
+                self.line("\tmov %rax, %rcx");
+                self.line("\tpop %rax");
+                match e.kind {
+                    ExpressionKind::Add(..) => self.line("\tadd %rcx, %rax"),
+                    _ => self.line("\tsub %rcx, %rax"),
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Division stops the program with an error on a zero divisor, and
+    /// dividing the most negative value by -1 wraps around like the
+    /// interpreter rather than trapping.
+    fn term(&mut self, t: &'a Term) -> Result<()> {
+        let (left, right) = match &t.kind {
+            TermKind::Unary(unary) => return self.unary(unary),
+            TermKind::Mul(left, right) | TermKind::Div(left, right) => (left, right),
+        };
+        self.term(left)?;
+        self.line("\tpush %rax");
+        self.unary(right)?;
+        self.line("\tmov %rax, %rcx");
+        self.line("\tpop %rax");
+
+        if let TermKind::Mul(..) = t.kind {
+            self.line("\timul %rcx, %rax");
+            return Ok(());
+        }
+        let (divide, negate, done) = (self.new_label(), self.new_label(), self.new_label());
+        let message = format!("error: attempt to divide by zero on line {}\n", t.span.line);
+        self.line("\ttest %rcx, %rcx");
+        self.line(&format!("\tjnz {divide}"));
+        self.print_string(&message);
+        self.line("\tjmp tb_error");
+        self.place(&divide);
+        self.line("\tcmp $-1, %rcx");
+        self.line(&format!("\tje {negate}"));
+        self.line("\tcqto");
+        self.line("\tidiv %rcx");
+        self.line(&format!("\tjmp {done}"));
+        self.place(&negate);
+        self.line("\tneg %rax");
+        self.place(&done);
+        Ok(())
+    }
+
+    fn unary(&mut self, u: &'a Unary) -> Result<()> {
+        match &u.kind {
+            UnaryKind::Primary(primary) => self.primary(primary),
+            UnaryKind::Positive(unary) => self.unary(unary),
+            UnaryKind::Negative(unary) => {
+                self.unary(unary)?;
+                self.line("\tneg %rax");
+                Ok(())
+            }
+        }
+    }
+
+    fn primary(&mut self, p: &'a Primary) -> Result<()> {
+        match &p.kind {
+            PrimaryKind::Integer(v) => self.integer(*v),
+            PrimaryKind::Number(v) => self.integer(*v as i64),
+            PrimaryKind::Float(_) => return Err(unsupported(BACKEND, "floats", p.span)),
+            PrimaryKind::String(_) => return Err(unsupported(BACKEND, "string values", p.span)),
+            PrimaryKind::Variable(var) => {
+                let var = self.variable(var, p.span)?;
+                self.line(&format!("\tmov {var}, %rax"));
+            }
+            PrimaryKind::Call(..) => {
+                return Err(unsupported(BACKEND, "built-in functions", p.span))
+            }
+            PrimaryKind::FunctionCall(name, args) => self.call(name, args)?,
+            PrimaryKind::Index(..) => return Err(unsupported(BACKEND, "arrays", p.span)),
+            PrimaryKind::Grouped(expr) => self.expression(expr)?,
+        }
+        Ok(())
+    }
+
+    fn integer(&mut self, v: i64) {
+        match i32::try_from(v) {
+            Ok(0) => self.line("\txor %eax, %eax"),
+            Ok(v) => self.line(&format!("\tmov ${v}, %rax")),
+            Err(_) => self.line(&format!("\tmovabs ${v}, %rax")),
+        }
+    }
+
+    /// Pushes the arguments in order and pops them once the procedure has
+    /// returned.
+    fn call(&mut self, name: &str, args: &'a [Expression]) -> Result<()> {
+        for arg in args {
+            self.expression(arg)?;
+            self.line("\tpush %rax");
+        }
+        self.line(&format!("\tcall {}", fn_name(name)));
+        if !args.is_empty() {
+            self.line(&format!("\tadd ${}, %rsp", 8 * args.len()));
+        }
+        Ok(())
+    }
+}
+
+/// Symbol of a variable of the main program, kept apart from the runtime's.
+fn global_name(var: &str) -> String {
+    format!("tb_var_{var}")
+}
+
+/// GAS string literal holding `s`, everything but printable ASCII written as
+/// an octal escape.
+fn gas_string(s: &str) -> String {
+    format!("\"{}\"", escape_bytes(s, |byte| format!("\\{byte:03o}")))
+}
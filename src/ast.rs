@@ -5,73 +5,377 @@
 // () - grouping / or
 // +  - 1 or more
 
-// program ::= {statement}
+// program ::= {statement | procedure}
 
-// statement ::= PRINT (expression | string) nl
-//               IF comparison "THEN" nl {statement} "ENDIF" nl
-//               WHILE comparison "REPEAT" nl {statement} "ENDWHILE" nl
+// procedure ::= "SUB" var "(" [var {"," var}] ")" nl {statement} "ENDSUB" nl
+//               "FUNCTION" var "(" [var {"," var}] ")" nl {statement} "ENDFUNCTION" nl
+
+// statement ::= PRINT expression nl
+//               IF condition "THEN" nl {statement}
+//                  {"ELSEIF" condition "THEN" nl {statement}}
+//                  ["ELSE" nl {statement}] "ENDIF" nl
+//               WHILE condition "REPEAT" nl {statement} "ENDWHILE" nl
+//               FOR var "=" expression "TO" expression ["STEP" expression] nl
+//                  {statement} "NEXT" [var] nl
 //               LABEL var nl
 //               GOTO var nl
-//               LET var "=" expression nl
-//               INPUT var nl
+//               GOSUB var nl
+//               RETURN [expression] nl
+//               CALL var arguments nl
+//               DIM var "(" expression {"," expression} ")" nl
+//               LET target "=" expression nl
+//               INPUT target nl
+
+// target ::= var | index
 
+// condition ::= and_condition {"OR" and_condition}
+// and_condition ::= not_condition {"AND" not_condition}
+// not_condition ::= "NOT" not_condition | comparison
 // comparison ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
 // expression ::= term {( "-" | "+" ) term}
 // term ::= unary {( "/" | "*" ) unary}
 // unary ::= ["+" | "-"] primary
-// primary ::= number | var
+// primary ::= number | string | var | call | index | "(" expression ")"
+// call ::= ("LEN" | "LEFT$" | "MID$" | "STR$" | "VAL" | var) arguments
+// index ::= var arguments    (var declared with DIM)
+// arguments ::= "(" [expression {"," expression}] ")"
+
+use std::ops::RangeInclusive;
+
+use crate::span::Span;
 
 #[derive(Default, Debug)]
 pub struct Ast {
     pub program: Vec<Statement>,
+    pub procedures: Vec<Procedure>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcedureKind {
+    Sub,
+    Function,
+}
+
+impl ProcedureKind {
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            ProcedureKind::Sub => "SUB",
+            ProcedureKind::Function => "FUNCTION",
+        }
+    }
 }
 
+/// A SUB or FUNCTION definition. `span` covers the line declaring its name
+/// and parameters.
 #[derive(Debug)]
-pub enum Statement {
+pub struct Procedure {
+    pub kind: ProcedureKind,
+    pub name: String,
+    pub params: Vec<String>,
+    pub body: Box<Statement>,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum StatementKind {
     Print(Expression),
     PrintStr(String),
-    Let(String, Expression),
-    If(Comparison, Box<Statement>),
-    While(Comparison, Box<Statement>),
+    /// Creates an array, the highest index of every dimension is given.
+    Dim(String, Vec<Expression>),
+    Let(Target, Expression),
+    /// The ELSE branch of an ELSEIF chain holds the next `If`.
+    If(Condition, Box<Statement>, Option<Box<Statement>>),
+    While(Condition, Box<Statement>),
+    /// The body runs while `var` has not passed `to`, counting up for a
+    /// positive or missing step and down for a negative one.
+    For {
+        var: String,
+        from: Box<Expression>,
+        to: Box<Expression>,
+        step: Option<Box<Expression>>,
+        body: Box<Statement>,
+    },
     Label(String),
     Goto(String),
-    Input(String),
+    Gosub(String),
+    /// Ends a subroutine entered with GOSUB, or a SUB or FUNCTION.
+    Return(Option<Expression>),
+    Call(String, Vec<Expression>),
+    Input(Target),
     Statement(Box<Statement>, Box<Statement>),
     End,
 }
 
+/// What LET and INPUT assign to.
+#[derive(Debug)]
+pub enum Target {
+    Variable(String),
+    Element(String, Vec<Expression>),
+}
+
+impl Target {
+    pub fn name(&self) -> &str {
+        match self {
+            Target::Variable(name) | Target::Element(name, _) => name,
+        }
+    }
+}
+
+impl Statement {
+    /// Statements of a block, flattening the `Statement`/`End` chain that
+    /// WHILE, FOR and IF bodies are built from.
+    pub fn block(&self) -> Vec<&Statement> {
+        let mut statements = Vec::new();
+        let mut current = self;
+        loop {
+            match &current.kind {
+                StatementKind::Statement(first, rest) => {
+                    statements.push(first.as_ref());
+                    current = rest;
+                }
+                StatementKind::End => return statements,
+                _ => {
+                    statements.push(current);
+                    return statements;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Condition {
+    pub kind: ConditionKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ConditionKind {
+    Comparison(Box<Comparison>),
+    Not(Box<Condition>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
 #[derive(Debug)]
-pub enum Comparison {
+pub struct Comparison {
+    pub kind: ComparisonKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum ComparisonKind {
     Left(Expression),
     Right(Expression, Box<Comparison>),
     Compare(String, Box<Comparison>),
 }
 
+impl Comparison {
+    /// First operand and the `(operator, operand)` pairs that follow it,
+    /// `a < b < c` gives `(a, [("<", b), ("<", c)])`.
+    pub fn chain(&self) -> (&Expression, Vec<(&str, &Expression)>) {
+        let mut rest = Vec::new();
+        let (first, mut next) = match &self.kind {
+            ComparisonKind::Left(expr) => return (expr, rest),
+            ComparisonKind::Right(expr, next) => (expr, next.as_ref()),
+            ComparisonKind::Compare(..) => unreachable!("comparison starts with an operator"),
+        };
+
+        while let ComparisonKind::Compare(op, operand) = &next.kind {
+            match &operand.kind {
+                ComparisonKind::Left(expr) => {
+                    rest.push((op.as_str(), expr));
+                    break;
+                }
+                ComparisonKind::Right(expr, following) => {
+                    rest.push((op.as_str(), expr));
+                    next = following;
+                }
+                ComparisonKind::Compare(..) => unreachable!("operator follows an operator"),
+            }
+        }
+
+        (first, rest)
+    }
+}
+
 #[derive(Debug)]
-pub enum Expression {
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    /// The string if the whole expression is a single string literal.
+    pub fn as_string_literal(&self) -> Option<&str> {
+        match self.as_primary()? {
+            PrimaryKind::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    /// The value if the whole expression is a single integer literal.
+    pub fn as_integer_literal(&self) -> Option<i64> {
+        match self.as_primary()? {
+            PrimaryKind::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn as_primary(&self) -> Option<&PrimaryKind> {
+        let ExpressionKind::Term(term) = &self.kind else {
+            return None;
+        };
+        let TermKind::Unary(unary) = &term.kind else {
+            return None;
+        };
+        match &unary.kind {
+            UnaryKind::Primary(primary) => Some(&primary.kind),
+            _ => None,
+        }
+    }
+}
+
+impl Expression {
+    /// Calls of user-defined functions in the expression, calls made in the
+    /// arguments of another call first.
+    pub fn function_calls(&self) -> Vec<(&str, &[Expression])> {
+        let mut calls = Vec::new();
+        self.collect_calls(&mut calls);
+        calls
+    }
+
+    fn collect_calls<'a>(&'a self, calls: &mut Vec<(&'a str, &'a [Expression])>) {
+        match &self.kind {
+            ExpressionKind::Term(term) => term.collect_calls(calls),
+            ExpressionKind::Add(expr, term) | ExpressionKind::Sub(expr, term) => {
+                expr.collect_calls(calls);
+                term.collect_calls(calls);
+            }
+        }
+    }
+}
+
+impl Term {
+    fn collect_calls<'a>(&'a self, calls: &mut Vec<(&'a str, &'a [Expression])>) {
+        match &self.kind {
+            TermKind::Unary(unary) => unary.collect_calls(calls),
+            TermKind::Mul(term, unary) | TermKind::Div(term, unary) => {
+                term.collect_calls(calls);
+                unary.collect_calls(calls);
+            }
+        }
+    }
+}
+
+impl Unary {
+    fn collect_calls<'a>(&'a self, calls: &mut Vec<(&'a str, &'a [Expression])>) {
+        match &self.kind {
+            UnaryKind::Primary(primary) => match &primary.kind {
+                PrimaryKind::Call(_, args) | PrimaryKind::Index(_, args) => {
+                    for arg in args {
+                        arg.collect_calls(calls);
+                    }
+                }
+                PrimaryKind::FunctionCall(name, args) => {
+                    for arg in args {
+                        arg.collect_calls(calls);
+                    }
+                    calls.push((name, args));
+                }
+                PrimaryKind::Grouped(expr) => expr.collect_calls(calls),
+                _ => (),
+            },
+            UnaryKind::Positive(unary) | UnaryKind::Negative(unary) => unary.collect_calls(calls),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExpressionKind {
     Term(Term),
-    Add(Box<Term>, Box<Expression>),
-    Sub(Box<Term>, Box<Expression>),
+    Add(Box<Expression>, Box<Term>),
+    Sub(Box<Expression>, Box<Term>),
+}
+
+#[derive(Debug)]
+pub struct Term {
+    pub kind: TermKind,
+    pub span: Span,
 }
 
 #[derive(Debug)]
-pub enum Term {
+pub enum TermKind {
     Unary(Unary),
-    Mul(Box<Unary>, Box<Term>),
-    Div(Box<Unary>, Box<Term>),
+    Mul(Box<Term>, Box<Unary>),
+    Div(Box<Term>, Box<Unary>),
 }
 
 #[derive(Debug)]
-pub enum Unary {
+pub struct Unary {
+    pub kind: UnaryKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum UnaryKind {
     Primary(Primary),
     Positive(Box<Unary>),
     Negative(Box<Unary>),
 }
 
 #[derive(Debug)]
-pub enum Primary {
+pub struct Primary {
+    pub kind: PrimaryKind,
+    pub span: Span,
+}
+
+#[derive(Debug)]
+pub enum PrimaryKind {
     Integer(i64),
     Float(f64),
     Number(usize),
+    String(String),
     Variable(String),
+    Call(Builtin, Vec<Expression>),
+    FunctionCall(String, Vec<Expression>),
+    /// Element of an array.
+    Index(String, Vec<Expression>),
+    Grouped(Box<Expression>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    Len,
+    Left,
+    Mid,
+    Str,
+    Val,
+}
+
+impl Builtin {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Builtin::Len => "LEN",
+            Builtin::Left => "LEFT$",
+            Builtin::Mid => "MID$",
+            Builtin::Str => "STR$",
+            Builtin::Val => "VAL",
+        }
+    }
+
+    /// Number of arguments the function takes, the length of MID$ is optional.
+    pub fn arity(&self) -> RangeInclusive<usize> {
+        match self {
+            Builtin::Len | Builtin::Str | Builtin::Val => 1..=1,
+            Builtin::Left => 2..=2,
+            Builtin::Mid => 2..=3,
+        }
+    }
 }
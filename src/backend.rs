@@ -0,0 +1,58 @@
+//! Helpers shared by the backends compiling straight to a target language
+//! rather than through C: x86-64 assembly, LLVM IR and WebAssembly text.
+//!
+//! They all compile FOR loops like the C backend: the variable, limit and
+//! step are set in turn, and the loop runs while the variable has not passed
+//! the limit, counting down for a negative step. A step that would overflow
+//! ends the loop and leaves the variable as it was.
+
+use crate::error::CompileError;
+use crate::span::Span;
+use crate::types::Type;
+
+/// Error for a feature that `backend`, named as in errors, cannot compile.
+pub fn unsupported(backend: &'static str, feature: &'static str, span: Span) -> CompileError {
+    CompileError::UnsupportedFeature {
+        feature,
+        backend,
+        span,
+    }
+}
+
+/// Rejects values of types a backend handling only integers cannot handle.
+pub fn supported(backend: &'static str, ty: Type, span: Span) -> Result<(), CompileError> {
+    match ty {
+        Type::Int => Ok(()),
+        Type::Float => Err(unsupported(backend, "floats", span)),
+        Type::Str => Err(unsupported(backend, "string values", span)),
+    }
+}
+
+/// Symbol of a SUB or FUNCTION, kept apart from the variables.
+pub fn fn_name(name: &str) -> String {
+    format!("fn_{name}")
+}
+
+/// Contents of a string literal holding `s`, everything but printable ASCII
+/// as well as quotes and backslashes written by `escape`.
+pub fn escape_bytes(s: &str, escape: impl Fn(u8) -> String) -> String {
+    let mut literal = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' => literal.push(byte as char),
+            _ => literal.push_str(&escape(byte)),
+        }
+    }
+    literal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_backslashes_and_non_ascii_are_escaped() {
+        let escaped = escape_bytes("a\"b\\c\né", |byte| format!("<{byte:02x}>"));
+        assert_eq!(escaped, "a<22>b<5c>c<0a><c3><a9>");
+    }
+}
@@ -0,0 +1,643 @@
+use std::collections::HashMap;
+
+use tracing::instrument;
+
+use crate::ast::{
+    self, Builtin, Condition, ConditionKind, Expression, ExpressionKind, Primary, PrimaryKind,
+    Statement, StatementKind, Target, Term, TermKind, Unary, UnaryKind,
+};
+use crate::interpreter::Value;
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::types::{expression_type, ProcedureTypes, Scope, Type};
+
+/// Instruction of the stack machine run by [`crate::vm`]. Operands index the
+/// constants of the program, the variables, arrays and procedures, or are
+/// addresses in its code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// Pushes a constant.
+    Const(u32),
+    /// Pushes a variable of the running procedure, or of the main program.
+    Load(u32),
+    /// Pops a value into a variable, converting it to the variable's type.
+    Store(u32),
+    /// Pops the highest index of each dimension and creates the array.
+    Dim {
+        array: u32,
+        dimensions: u32,
+    },
+    /// Pops the indices and pushes the element at them.
+    LoadElement {
+        array: u32,
+        indices: u32,
+    },
+    /// Pops the indices, then the value to store at them.
+    StoreElement {
+        array: u32,
+        indices: u32,
+    },
+    /// Pops a value and discards it.
+    Pop,
+    /// Adds numbers or joins strings.
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Neg,
+    /// Pops a step and adds it to a FOR loop variable, then pushes 1. When an
+    /// integer sum would wrap around, pushes 0 and leaves the variable.
+    Step(u32),
+    /// Pops two values and pushes 1 if the comparison holds, 0 otherwise.
+    Compare(Relation),
+    /// Pops the arguments and pushes the result.
+    Builtin {
+        builtin: Builtin,
+        args: u32,
+    },
+    Jump(u32),
+    /// Pops a value and jumps if it is zero.
+    JumpIfFalse(u32),
+    /// Pops a value and jumps if it is not zero.
+    JumpIfTrue(u32),
+    /// Jumps to a subroutine, the next `ReturnSub` comes back after it.
+    Gosub(u32),
+    ReturnSub,
+    /// Pops the arguments of a procedure and runs it. A FUNCTION pushes its
+    /// result when it returns.
+    Call(u32),
+    /// Leaves a SUB.
+    Return,
+    /// Pops the result and leaves a FUNCTION.
+    ReturnValue,
+    /// Pops a value and prints it on a line of its own.
+    Print,
+    /// Reads a line of input and pushes it as a value of the type.
+    Input(Type),
+    Halt,
+}
+
+/// Comparison operators of [`Instruction::Compare`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Relation {
+    fn from_operator(op: &str) -> Relation {
+        match op {
+            "==" => Relation::Eq,
+            "!=" => Relation::Ne,
+            "<" => Relation::Lt,
+            "<=" => Relation::Le,
+            ">" => Relation::Gt,
+            ">=" => Relation::Ge,
+            _ => unreachable!("unknown comparison operator `{op}`"),
+        }
+    }
+
+    pub fn operator(&self) -> &'static str {
+        match self {
+            Relation::Eq => "==",
+            Relation::Ne => "!=",
+            Relation::Lt => "<",
+            Relation::Le => "<=",
+            Relation::Gt => ">",
+            Relation::Ge => ">=",
+        }
+    }
+}
+
+/// Program compiled to bytecode. The main program starts at address 0 and
+/// ends with `Halt`, the procedures follow it.
+#[derive(Debug, Default)]
+pub struct Program {
+    pub code: Vec<Instruction>,
+    /// Source location of every instruction, for runtime errors.
+    pub spans: Vec<Span>,
+    pub constants: Vec<Value>,
+    pub main: Locals,
+    pub procedures: Vec<Procedure>,
+}
+
+/// Variables and arrays of the main program or of a procedure, with the type
+/// of their values. Variables start out as zero, arrays without elements.
+#[derive(Debug, Default)]
+pub struct Locals {
+    pub variables: Vec<(String, Type)>,
+    pub arrays: Vec<(String, Type)>,
+}
+
+#[derive(Debug)]
+pub struct Procedure {
+    pub name: String,
+    pub entry: u32,
+    /// Number of parameters, which are the first variables.
+    pub params: u32,
+    /// `None` for a SUB.
+    pub result: Option<Type>,
+    pub locals: Locals,
+}
+
+/// Compiles a checked program to bytecode.
+#[instrument(skip_all)]
+pub fn compile(parser: &Parser) -> Program {
+    let mut compiler = Compiler {
+        program: Program::default(),
+        procedures: &parser.procedures,
+        indices: parser
+            .ast
+            .procedures
+            .iter()
+            .enumerate()
+            .map(|(index, procedure)| (procedure.name.as_str(), index as u32))
+            .collect(),
+        types: &parser.types,
+        locals: Locals::default(),
+        variables: HashMap::new(),
+        arrays: HashMap::new(),
+        labels: HashMap::new(),
+        jumps: Vec::new(),
+        in_procedure: false,
+    };
+
+    compiler.program.main = compiler.body(&parser.types, &[], &parser.ast.program);
+    compiler.emit(Instruction::Halt, Span::default());
+
+    compiler.in_procedure = true;
+    for procedure in &parser.ast.procedures {
+        let types = &parser.procedures[&procedure.name];
+        let entry = compiler.address();
+        let locals = compiler.body(
+            &types.scope,
+            &procedure.params,
+            std::slice::from_ref(procedure.body.as_ref()),
+        );
+
+        // Running off the end of a FUNCTION gives zero.
+        match types.result {
+            Some(ty) => {
+                let zero = compiler.constant(Value::zero(ty));
+                compiler.emit(Instruction::Const(zero), procedure.span);
+                compiler.emit(Instruction::ReturnValue, procedure.span);
+            }
+            None => {
+                compiler.emit(Instruction::Return, procedure.span);
+            }
+        }
+
+        compiler.program.procedures.push(Procedure {
+            name: procedure.name.clone(),
+            entry,
+            params: procedure.params.len() as u32,
+            result: types.result,
+            locals,
+        });
+    }
+
+    compiler.program
+}
+
+struct Compiler<'a> {
+    program: Program,
+    procedures: &'a HashMap<String, ProcedureTypes>,
+    /// Position of every procedure in `program.procedures`.
+    indices: HashMap<&'a str, u32>,
+    /// Types of the body being compiled.
+    types: &'a Scope,
+    locals: Locals,
+    variables: HashMap<String, u32>,
+    arrays: HashMap<String, u32>,
+    labels: HashMap<String, u32>,
+    /// GOTOs and GOSUBs of the body, patched once all its labels are known.
+    jumps: Vec<(usize, &'a str)>,
+    in_procedure: bool,
+}
+
+impl<'a> Compiler<'a> {
+    /// Compiles the main program or the body of a procedure, whose
+    /// parameters are its first variables.
+    fn body(&mut self, types: &'a Scope, params: &[String], statements: &'a [Statement]) -> Locals {
+        self.types = types;
+        self.locals = Locals::default();
+        self.variables.clear();
+        self.arrays.clear();
+        self.labels.clear();
+
+        let mut variables: Vec<&String> = types
+            .variables
+            .keys()
+            .filter(|var| !params.contains(var))
+            .collect();
+        variables.sort();
+        for var in params.iter().chain(variables) {
+            self.variable(var, types.variables[var]);
+        }
+        let mut arrays: Vec<(&String, &Type)> = types.arrays.iter().collect();
+        arrays.sort_by_key(|(array, _)| *array);
+        for (array, ty) in arrays {
+            self.arrays
+                .insert(array.clone(), self.locals.arrays.len() as u32);
+            self.locals.arrays.push((array.clone(), *ty));
+        }
+
+        for statement in statements {
+            self.statement(statement);
+        }
+
+        // The resolver rejects jumps to undeclared labels.
+        for (at, label) in std::mem::take(&mut self.jumps) {
+            self.patch(at, self.labels[label]);
+        }
+
+        std::mem::take(&mut self.locals)
+    }
+
+    fn statement(&mut self, s: &'a Statement) {
+        let span = s.span;
+        match &s.kind {
+            StatementKind::Print(expr) => {
+                self.expression(expr);
+                self.emit(Instruction::Print, span);
+            }
+            StatementKind::PrintStr(string) => {
+                let string = self.constant(Value::Str(string.clone().into_bytes()));
+                self.emit(Instruction::Const(string), span);
+                self.emit(Instruction::Print, span);
+            }
+            StatementKind::Dim(array, sizes) => {
+                for size in sizes {
+                    self.expression(size);
+                }
+                let instruction = Instruction::Dim {
+                    array: self.arrays[array],
+                    dimensions: sizes.len() as u32,
+                };
+                self.emit(instruction, span);
+            }
+            StatementKind::Let(target, expr) => {
+                self.expression(expr);
+                self.store(target, span);
+            }
+            StatementKind::Input(target) => {
+                let ty = match target {
+                    Target::Variable(var) => self.types.variables[var],
+                    Target::Element(array, _) => self.types.arrays[array],
+                };
+                self.emit(Instruction::Input(ty), span);
+                self.store(target, span);
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                let skip = self.condition(condition, false);
+                self.statement(then);
+                match otherwise {
+                    Some(otherwise) => {
+                        let end = self.emit(Instruction::Jump(0), span);
+                        self.patch_here(skip);
+                        self.statement(otherwise);
+                        self.patch_here(vec![end]);
+                    }
+                    None => self.patch_here(skip),
+                }
+            }
+            StatementKind::While(condition, body) => {
+                let start = self.address();
+                let exit = self.condition(condition, false);
+                self.statement(body);
+                self.emit(Instruction::Jump(start), span);
+                self.patch_here(exit);
+            }
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => self.for_loop(var, from, to, step.as_deref(), body, span),
+            StatementKind::Label(label) => {
+                self.labels.insert(label.clone(), self.address());
+            }
+            StatementKind::Goto(label) => {
+                let at = self.emit(Instruction::Jump(0), span);
+                self.jumps.push((at, label));
+            }
+            StatementKind::Gosub(label) => {
+                let at = self.emit(Instruction::Gosub(0), span);
+                self.jumps.push((at, label));
+            }
+            StatementKind::Return(None) if !self.in_procedure => {
+                self.emit(Instruction::ReturnSub, span);
+            }
+            StatementKind::Return(None) => {
+                self.emit(Instruction::Return, span);
+            }
+            StatementKind::Return(Some(expr)) => {
+                self.expression(expr);
+                self.emit(Instruction::ReturnValue, span);
+            }
+            StatementKind::Call(name, args) => {
+                self.call(name, args, span);
+                if self.procedures[name].result.is_some() {
+                    self.emit(Instruction::Pop, span);
+                }
+            }
+            StatementKind::Statement(first, rest) => {
+                self.statement(first);
+                self.statement(rest);
+            }
+            StatementKind::End => (),
+        }
+    }
+
+    /// Like the interpreter, the limit and step are evaluated once into
+    /// hidden variables, after the loop variable has been set. Whether the
+    /// loop counts up or down is decided by the sign of the step.
+    fn for_loop(
+        &mut self,
+        var: &str,
+        from: &Expression,
+        to: &Expression,
+        step: Option<&Expression>,
+        body: &'a Statement,
+        span: Span,
+    ) {
+        let var = self.variables[var];
+        self.expression(from);
+        self.emit(Instruction::Store(var), span);
+        let limit = self.hidden("to", to);
+        self.expression(to);
+        self.emit(Instruction::Store(limit), span);
+
+        let exit = match step {
+            Some(step) => {
+                let step = {
+                    let hidden = self.hidden("step", step);
+                    self.expression(step);
+                    self.emit(Instruction::Store(hidden), span);
+                    hidden
+                };
+                let zero = self.constant(Value::Int(0));
+
+                let start = self.address();
+                self.emit(Instruction::Load(step), span);
+                self.emit(Instruction::Const(zero), span);
+                self.emit(Instruction::Compare(Relation::Ge), span);
+                let down = self.emit(Instruction::JumpIfFalse(0), span);
+                let mut exit = self.loop_check(var, limit, Relation::Le, span);
+                let body_start = self.emit(Instruction::Jump(0), span);
+                self.patch_here(vec![down]);
+                exit.extend(self.loop_check(var, limit, Relation::Ge, span));
+                self.patch_here(vec![body_start]);
+
+                self.statement(body);
+                self.emit(Instruction::Load(step), span);
+                self.emit(Instruction::Step(var), span);
+                self.emit(Instruction::JumpIfTrue(start), span);
+                exit
+            }
+            None => {
+                let one = self.constant(Value::Int(1));
+
+                let start = self.address();
+                let exit = self.loop_check(var, limit, Relation::Le, span);
+                self.statement(body);
+                self.emit(Instruction::Const(one), span);
+                self.emit(Instruction::Step(var), span);
+                self.emit(Instruction::JumpIfTrue(start), span);
+                exit
+            }
+        };
+        self.patch_here(exit);
+    }
+
+    /// Jumps out of a FOR loop unless `var relation limit` holds.
+    fn loop_check(&mut self, var: u32, limit: u32, relation: Relation, span: Span) -> Vec<usize> {
+        self.emit(Instruction::Load(var), span);
+        self.emit(Instruction::Load(limit), span);
+        self.emit(Instruction::Compare(relation), span);
+        vec![self.emit(Instruction::JumpIfFalse(0), span)]
+    }
+
+    /// Stores the value on top of the stack, the value of `expr` is pushed
+    /// before the indices of an element.
+    fn store(&mut self, target: &Target, span: Span) {
+        match target {
+            Target::Variable(var) => {
+                self.emit(Instruction::Store(self.variables[var]), span);
+            }
+            Target::Element(array, indices) => {
+                for index in indices {
+                    self.expression(index);
+                }
+                let instruction = Instruction::StoreElement {
+                    array: self.arrays[array],
+                    indices: indices.len() as u32,
+                };
+                self.emit(instruction, span);
+            }
+        }
+    }
+
+    /// Compiles `c` into jumps taken when it is `jump_if`, falling through
+    /// otherwise. `AND` and `OR` short-circuit. Returns the jumps, to be
+    /// patched with their target.
+    fn condition(&mut self, c: &Condition, jump_if: bool) -> Vec<usize> {
+        match &c.kind {
+            ConditionKind::Comparison(comp) => {
+                self.comparison(comp);
+                let jump = match jump_if {
+                    true => Instruction::JumpIfTrue(0),
+                    false => Instruction::JumpIfFalse(0),
+                };
+                vec![self.emit(jump, c.span)]
+            }
+            ConditionKind::Not(cond) => self.condition(cond, !jump_if),
+            ConditionKind::And(left, right) | ConditionKind::Or(left, right) => {
+                // Jumping on the left operand alone is enough when it takes
+                // the same jump as the whole condition.
+                let decides = matches!(c.kind, ConditionKind::Or(..));
+                if jump_if == decides {
+                    let mut jumps = self.condition(left, jump_if);
+                    jumps.extend(self.condition(right, jump_if));
+                    jumps
+                } else {
+                    let skip = self.condition(left, decides);
+                    let jumps = self.condition(right, jump_if);
+                    self.patch_here(skip);
+                    jumps
+                }
+            }
+        }
+    }
+
+    fn comparison(&mut self, c: &ast::Comparison) {
+        let (first, rest) = c.chain();
+
+        self.expression(first);
+        for (op, expr) in rest {
+            self.expression(expr);
+            self.emit(Instruction::Compare(Relation::from_operator(op)), c.span);
+        }
+    }
+
+    fn expression(&mut self, e: &Expression) {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.term(term),
+            ExpressionKind::Add(expr, term) => {
+                self.expression(expr);
+                self.term(term);
+                self.emit(Instruction::Add, e.span);
+            }
+            ExpressionKind::Sub(expr, term) => {
+                self.expression(expr);
+                self.term(term);
+                self.emit(Instruction::Sub, e.span);
+            }
+        }
+    }
+
+    fn term(&mut self, t: &Term) {
+        match &t.kind {
+            TermKind::Unary(unary) => self.unary(unary),
+            TermKind::Mul(term, unary) => {
+                self.term(term);
+                self.unary(unary);
+                self.emit(Instruction::Mul, t.span);
+            }
+            TermKind::Div(term, unary) => {
+                self.term(term);
+                self.unary(unary);
+                self.emit(Instruction::Div, t.span);
+            }
+        }
+    }
+
+    fn unary(&mut self, u: &Unary) {
+        match &u.kind {
+            UnaryKind::Primary(primary) => self.primary(primary),
+            UnaryKind::Positive(unary) => self.unary(unary),
+            UnaryKind::Negative(unary) => {
+                self.unary(unary);
+                self.emit(Instruction::Neg, u.span);
+            }
+        }
+    }
+
+    fn primary(&mut self, p: &Primary) {
+        let value = match &p.kind {
+            PrimaryKind::Integer(v) => Value::Int(*v),
+            PrimaryKind::Float(v) => Value::Float(*v),
+            PrimaryKind::Number(v) => Value::Int(*v as i64),
+            PrimaryKind::String(v) => Value::Str(v.clone().into_bytes()),
+            PrimaryKind::Variable(var) => {
+                self.emit(Instruction::Load(self.variables[var]), p.span);
+                return;
+            }
+            PrimaryKind::Call(builtin, args) => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                let instruction = Instruction::Builtin {
+                    builtin: *builtin,
+                    args: args.len() as u32,
+                };
+                self.emit(instruction, p.span);
+                return;
+            }
+            PrimaryKind::FunctionCall(name, args) => {
+                self.call(name, args, p.span);
+                return;
+            }
+            PrimaryKind::Index(array, indices) => {
+                for index in indices {
+                    self.expression(index);
+                }
+                let instruction = Instruction::LoadElement {
+                    array: self.arrays[array],
+                    indices: indices.len() as u32,
+                };
+                self.emit(instruction, p.span);
+                return;
+            }
+            PrimaryKind::Grouped(expr) => {
+                self.expression(expr);
+                return;
+            }
+        };
+
+        let constant = self.constant(value);
+        self.emit(Instruction::Const(constant), p.span);
+    }
+
+    fn call(&mut self, name: &str, args: &[Expression], span: Span) {
+        for arg in args {
+            self.expression(arg);
+        }
+        self.emit(Instruction::Call(self.indices[name]), span);
+    }
+
+    fn variable(&mut self, name: &str, ty: Type) -> u32 {
+        let index = self.locals.variables.len() as u32;
+        self.variables.insert(name.to_string(), index);
+        self.locals.variables.push((name.to_string(), ty));
+        index
+    }
+
+    /// Hidden variable holding the `part` of a FOR loop, named so that it
+    /// cannot clash with a variable of the program.
+    fn hidden(&mut self, part: &str, e: &Expression) -> u32 {
+        let name = format!("<{part} {}>", self.locals.variables.len());
+        let ty = expression_type(e, self.types, self.procedures);
+        self.variable(&name, ty)
+    }
+
+    /// Index of `value` in the constants, adding it if it is not there yet.
+    fn constant(&mut self, value: Value) -> u32 {
+        let same = |constant: &Value| match (constant, &value) {
+            (Value::Float(a), Value::Float(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        };
+        match self.program.constants.iter().position(same) {
+            Some(index) => index as u32,
+            None => {
+                self.program.constants.push(value);
+                self.program.constants.len() as u32 - 1
+            }
+        }
+    }
+
+    fn address(&self) -> u32 {
+        self.program.code.len() as u32
+    }
+
+    /// Appends an instruction, returning where it is for `patch`.
+    fn emit(&mut self, instruction: Instruction, span: Span) -> usize {
+        self.program.code.push(instruction);
+        self.program.spans.push(span);
+        self.program.code.len() - 1
+    }
+
+    /// Points the jump at `at` to `target`.
+    fn patch(&mut self, at: usize, target: u32) {
+        self.program.code[at] = match self.program.code[at] {
+            Instruction::Jump(_) => Instruction::Jump(target),
+            Instruction::JumpIfFalse(_) => Instruction::JumpIfFalse(target),
+            Instruction::JumpIfTrue(_) => Instruction::JumpIfTrue(target),
+            Instruction::Gosub(_) => Instruction::Gosub(target),
+            instruction => unreachable!("{instruction:?} is not a jump"),
+        };
+    }
+
+    /// Points `jumps` to the next instruction.
+    fn patch_here(&mut self, jumps: Vec<usize>) {
+        let target = self.address();
+        for at in jumps {
+            self.patch(at, target);
+        }
+    }
+}
@@ -0,0 +1,851 @@
+use std::collections::HashMap;
+
+use tracing::instrument;
+
+use crate::ast::Builtin;
+use crate::bytecode::{Instruction, Locals, Procedure, Program, Relation};
+use crate::error::CompileError;
+use crate::interpreter::Value;
+use crate::span::Span;
+use crate::types::{signature, Type};
+
+/// Bytes every bytecode file starts with.
+pub const MAGIC: &[u8; 4] = b"TBC\0";
+
+/// Version of the format written, and the only one loaded.
+pub const VERSION: u16 = 2;
+
+/// Flag set in the header when the file has a line table.
+const HAS_LINES: u16 = 1;
+
+/// Position of a type, relation or built-in function in these tables is its
+/// code in the file.
+const TYPES: [Type; 3] = [Type::Int, Type::Float, Type::Str];
+const RELATIONS: [Relation; 6] = [
+    Relation::Eq,
+    Relation::Ne,
+    Relation::Lt,
+    Relation::Le,
+    Relation::Gt,
+    Relation::Ge,
+];
+const BUILTINS: [Builtin; 5] = [
+    Builtin::Len,
+    Builtin::Left,
+    Builtin::Mid,
+    Builtin::Str,
+    Builtin::Val,
+];
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+/// Whether `bytes` look like a bytecode file rather than source.
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Serializes a program. Without `lines`, the file leaves out the source
+/// location of the instructions and runtime errors cannot point at them.
+///
+/// All numbers are little-endian. After the magic, the version and the flags
+/// come the string table, the constant pool, the variables and arrays of the
+/// main program, the procedures, the code and the optional line table.
+#[instrument(skip_all)]
+pub fn write(program: &Program, lines: bool) -> Vec<u8> {
+    let mut writer = Writer::default();
+
+    writer.u32(program.constants.len() as u32);
+    for constant in &program.constants {
+        match constant {
+            Value::Int(v) => {
+                writer.u8(0);
+                writer.bytes.extend(v.to_le_bytes());
+            }
+            Value::Float(v) => {
+                writer.u8(1);
+                writer.bytes.extend(v.to_bits().to_le_bytes());
+            }
+            // String constants are literals of the source, so valid UTF-8.
+            Value::Str(s) => {
+                writer.u8(2);
+                writer.string(&String::from_utf8_lossy(s));
+            }
+        }
+    }
+
+    writer.locals(&program.main);
+    writer.u32(program.procedures.len() as u32);
+    for procedure in &program.procedures {
+        writer.string(&procedure.name);
+        writer.u32(procedure.entry);
+        writer.u32(procedure.params);
+        writer.u8(procedure.result.map_or(0, |ty| type_code(ty) + 1));
+        writer.locals(&procedure.locals);
+    }
+
+    writer.u32(program.code.len() as u32);
+    for instruction in &program.code {
+        writer.instruction(*instruction);
+    }
+
+    if lines {
+        let mut entries = Vec::new();
+        let mut last = None;
+        for (address, span) in program.spans.iter().enumerate() {
+            if last != Some((span.line, span.column)) {
+                last = Some((span.line, span.column));
+                entries.push((address as u32, span.line as u32, span.column as u32));
+            }
+        }
+        writer.u32(entries.len() as u32);
+        for (address, line, column) in entries {
+            writer.u32(address);
+            writer.u32(line);
+            writer.u32(column);
+        }
+    }
+
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(VERSION.to_le_bytes());
+    bytes.extend((if lines { HAS_LINES } else { 0 }).to_le_bytes());
+    bytes.extend((writer.strings.len() as u32).to_le_bytes());
+    for s in &writer.strings {
+        bytes.extend((s.len() as u32).to_le_bytes());
+        bytes.extend(s.as_bytes());
+    }
+    bytes.extend(writer.bytes);
+    bytes
+}
+
+/// Loads a file written by [`write`], rejecting it if it is truncated,
+/// corrupted or could make the VM misbehave.
+#[instrument(skip_all)]
+pub fn read(bytes: &[u8]) -> Result<Program> {
+    let mut reader = Reader {
+        bytes,
+        position: 0,
+        section: "header",
+        strings: Vec::new(),
+    };
+
+    if !is_bytecode(bytes) {
+        return Err(invalid("not a bytecode file"));
+    }
+    reader.take(MAGIC.len())?;
+    let version = reader.u16()?;
+    if version != VERSION {
+        return Err(invalid(format!(
+            "unsupported version {version}, expected {VERSION}"
+        )));
+    }
+    let flags = reader.u16()?;
+    if flags & !HAS_LINES != 0 {
+        return Err(invalid(format!("unknown flags {flags:#06x}")));
+    }
+
+    reader.section = "string table";
+    for _ in 0..reader.count()? {
+        let len = reader.u32()? as usize;
+        let s = std::str::from_utf8(reader.take(len)?)
+            .map_err(|_| invalid("string table holds invalid UTF-8"))?;
+        reader.strings.push(s.to_string());
+    }
+
+    reader.section = "constant pool";
+    let mut program = Program::default();
+    for _ in 0..reader.count()? {
+        let constant = match reader.u8()? {
+            0 => Value::Int(i64::from_le_bytes(reader.array()?)),
+            1 => Value::Float(f64::from_bits(u64::from_le_bytes(reader.array()?))),
+            2 => Value::Str(reader.string()?.into_bytes()),
+            tag => return Err(invalid(format!("unknown constant tag {tag}"))),
+        };
+        program.constants.push(constant);
+    }
+
+    reader.section = "variables";
+    program.main = reader.locals()?;
+
+    reader.section = "procedures";
+    for _ in 0..reader.count()? {
+        let name = reader.string()?;
+        let entry = reader.u32()?;
+        let params = reader.u32()?;
+        let result = match reader.u8()? {
+            0 => None,
+            code => Some(reader.type_of(code - 1)?),
+        };
+        let locals = reader.locals()?;
+        program.procedures.push(Procedure {
+            name,
+            entry,
+            params,
+            result,
+            locals,
+        });
+    }
+
+    reader.section = "code";
+    for address in 0..reader.count()? {
+        let instruction = reader.instruction(address)?;
+        program.code.push(instruction);
+    }
+
+    program.spans = vec![Span::default(); program.code.len()];
+    if flags & HAS_LINES != 0 {
+        reader.section = "line table";
+        let mut entries = Vec::new();
+        for _ in 0..reader.count()? {
+            let address = reader.u32()? as usize;
+            let line = reader.u32()? as usize;
+            let column = reader.u32()? as usize;
+            if address >= program.code.len()
+                || entries.last().is_some_and(|(last, _)| *last >= address)
+            {
+                return Err(invalid(format!(
+                    "line table entry for address {address} is out of order"
+                )));
+            }
+            entries.push((address, Span::new(0, 0, line, column)));
+        }
+        for (i, (address, span)) in entries.iter().enumerate() {
+            let end = entries.get(i + 1).map_or(program.code.len(), |e| e.0);
+            program.spans[*address..end].fill(*span);
+        }
+    }
+
+    if reader.position != bytes.len() {
+        return Err(invalid(format!(
+            "{} unexpected bytes after the {}",
+            bytes.len() - reader.position,
+            reader.section
+        )));
+    }
+
+    verify(&program).map_err(invalid)?;
+    Ok(program)
+}
+
+fn invalid(reason: impl Into<String>) -> CompileError {
+    CompileError::InvalidBytecode {
+        reason: reason.into(),
+    }
+}
+
+fn type_code(ty: Type) -> u8 {
+    TYPES
+        .iter()
+        .position(|t| *t == ty)
+        .expect("every type has a code") as u8
+}
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+    strings: Vec<String>,
+    indices: HashMap<String, u32>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend(value.to_le_bytes());
+    }
+
+    /// Writes the index of `s` in the string table, adding it if needed.
+    fn string(&mut self, s: &str) {
+        let index = match self.indices.get(s) {
+            Some(index) => *index,
+            None => {
+                let index = self.strings.len() as u32;
+                self.strings.push(s.to_string());
+                self.indices.insert(s.to_string(), index);
+                index
+            }
+        };
+        self.u32(index);
+    }
+
+    fn locals(&mut self, locals: &Locals) {
+        for list in [&locals.variables, &locals.arrays] {
+            self.u32(list.len() as u32);
+            for (name, ty) in list {
+                self.string(name);
+                self.u8(type_code(*ty));
+            }
+        }
+    }
+
+    fn instruction(&mut self, instruction: Instruction) {
+        let (opcode, operands): (u8, &[u32]) = match instruction {
+            Instruction::Const(index) => (0, &[index]),
+            Instruction::Load(var) => (1, &[var]),
+            Instruction::Store(var) => (2, &[var]),
+            Instruction::Dim { array, dimensions } => (3, &[array, dimensions]),
+            Instruction::LoadElement { array, indices } => (4, &[array, indices]),
+            Instruction::StoreElement { array, indices } => (5, &[array, indices]),
+            Instruction::Pop => (6, &[]),
+            Instruction::Add => (7, &[]),
+            Instruction::Sub => (8, &[]),
+            Instruction::Mul => (9, &[]),
+            Instruction::Div => (10, &[]),
+            Instruction::Neg => (11, &[]),
+            Instruction::Compare(relation) => {
+                let code = RELATIONS.iter().position(|r| *r == relation);
+                self.u8(12);
+                self.u8(code.expect("every relation has a code") as u8);
+                return;
+            }
+            Instruction::Builtin { builtin, args } => {
+                let code = BUILTINS.iter().position(|b| *b == builtin);
+                self.u8(13);
+                self.u8(code.expect("every built-in function has a code") as u8);
+                self.u32(args);
+                return;
+            }
+            Instruction::Jump(target) => (14, &[target]),
+            Instruction::JumpIfFalse(target) => (15, &[target]),
+            Instruction::JumpIfTrue(target) => (16, &[target]),
+            Instruction::Gosub(target) => (17, &[target]),
+            Instruction::ReturnSub => (18, &[]),
+            Instruction::Call(index) => (19, &[index]),
+            Instruction::Return => (20, &[]),
+            Instruction::ReturnValue => (21, &[]),
+            Instruction::Print => (22, &[]),
+            Instruction::Input(ty) => {
+                self.u8(23);
+                self.u8(type_code(ty));
+                return;
+            }
+            Instruction::Halt => (24, &[]),
+            Instruction::Step(var) => (25, &[var]),
+        };
+        self.u8(opcode);
+        for operand in operands {
+            self.u32(*operand);
+        }
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+    /// Section being read, for errors.
+    section: &'static str,
+    strings: Vec<String>,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, len: usize) -> Result<&'b [u8]> {
+        let end = self.position.saturating_add(len);
+        let bytes = self
+            .bytes
+            .get(self.position..end)
+            .ok_or_else(|| invalid(format!("file is truncated in the {}", self.section)))?;
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N]> {
+        Ok(self.take(N)?.try_into().expect("took N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    /// Reads the number of entries of a list. Every entry takes at least a
+    /// byte, so a count larger than what is left means the file is truncated
+    /// rather than a reason to allocate that much.
+    fn count(&mut self) -> Result<usize> {
+        let count = self.u32()? as usize;
+        if count > self.bytes.len() - self.position {
+            return Err(invalid(format!(
+                "file is truncated in the {}",
+                self.section
+            )));
+        }
+        Ok(count)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let index = self.u32()?;
+        self.strings
+            .get(index as usize)
+            .cloned()
+            .ok_or_else(|| invalid(format!("string {index} is not in the string table")))
+    }
+
+    fn type_of(&self, code: u8) -> Result<Type> {
+        TYPES
+            .get(code as usize)
+            .copied()
+            .ok_or_else(|| invalid(format!("unknown type {code} in the {}", self.section)))
+    }
+
+    fn locals(&mut self) -> Result<Locals> {
+        let mut locals = Locals::default();
+        for list in [&mut locals.variables, &mut locals.arrays] {
+            for _ in 0..self.count()? {
+                let name = self.string()?;
+                let code = self.u8()?;
+                list.push((name, self.type_of(code)?));
+            }
+        }
+        Ok(locals)
+    }
+
+    fn instruction(&mut self, address: usize) -> Result<Instruction> {
+        Ok(match self.u8()? {
+            0 => Instruction::Const(self.u32()?),
+            1 => Instruction::Load(self.u32()?),
+            2 => Instruction::Store(self.u32()?),
+            3 => Instruction::Dim {
+                array: self.u32()?,
+                dimensions: self.u32()?,
+            },
+            4 => Instruction::LoadElement {
+                array: self.u32()?,
+                indices: self.u32()?,
+            },
+            5 => Instruction::StoreElement {
+                array: self.u32()?,
+                indices: self.u32()?,
+            },
+            6 => Instruction::Pop,
+            7 => Instruction::Add,
+            8 => Instruction::Sub,
+            9 => Instruction::Mul,
+            10 => Instruction::Div,
+            11 => Instruction::Neg,
+            12 => {
+                let code = self.u8()?;
+                Instruction::Compare(*RELATIONS.get(code as usize).ok_or_else(|| {
+                    invalid(format!("unknown comparison {code} at address {address}"))
+                })?)
+            }
+            13 => {
+                let code = self.u8()?;
+                let builtin = *BUILTINS.get(code as usize).ok_or_else(|| {
+                    invalid(format!(
+                        "unknown built-in function {code} at address {address}"
+                    ))
+                })?;
+                Instruction::Builtin {
+                    builtin,
+                    args: self.u32()?,
+                }
+            }
+            14 => Instruction::Jump(self.u32()?),
+            15 => Instruction::JumpIfFalse(self.u32()?),
+            16 => Instruction::JumpIfTrue(self.u32()?),
+            17 => Instruction::Gosub(self.u32()?),
+            18 => Instruction::ReturnSub,
+            19 => Instruction::Call(self.u32()?),
+            20 => Instruction::Return,
+            21 => Instruction::ReturnValue,
+            22 => Instruction::Print,
+            23 => {
+                let code = self.u8()?;
+                Instruction::Input(self.type_of(code)?)
+            }
+            24 => Instruction::Halt,
+            25 => Instruction::Step(self.u32()?),
+            opcode => {
+                return Err(invalid(format!(
+                    "unknown opcode {opcode:#04x} at address {address}"
+                )))
+            }
+        })
+    }
+}
+
+/// What a run of code is, which decides how it may end.
+#[derive(Clone, Copy)]
+enum Body {
+    Main,
+    Sub,
+    Function(Type),
+}
+
+/// Checks that the VM can run the program without tripping over it: every
+/// operand is in range, jumps stay within the main program or procedure they
+/// are in, and every instruction finds values of the types it expects on the
+/// stack, however it is reached.
+fn verify(program: &Program) -> std::result::Result<(), String> {
+    if program.code.is_empty() {
+        return Err("the program has no code".to_string());
+    }
+
+    let mut start = 0;
+    for procedure in &program.procedures {
+        let entry = procedure.entry as usize;
+        if entry <= start || entry >= program.code.len() {
+            return Err(format!(
+                "{} starts at address {entry}, out of order or past the code",
+                procedure.name
+            ));
+        }
+        if procedure.params as usize > procedure.locals.variables.len() {
+            return Err(format!(
+                "{} has more parameters than variables",
+                procedure.name
+            ));
+        }
+        start = entry;
+    }
+
+    let ends = program
+        .procedures
+        .iter()
+        .map(|procedure| procedure.entry as usize)
+        .chain([program.code.len()]);
+    let bodies = std::iter::once((0, "the main program", &program.main, Body::Main)).chain(
+        program.procedures.iter().map(|procedure| {
+            let body = match procedure.result {
+                Some(ty) => Body::Function(ty),
+                None => Body::Sub,
+            };
+            (
+                procedure.entry as usize,
+                procedure.name.as_str(),
+                &procedure.locals,
+                body,
+            )
+        }),
+    );
+    for ((start, name, locals, body), end) in bodies.zip(ends) {
+        let verifier = Verifier {
+            program,
+            locals,
+            body,
+            start,
+            end,
+            name,
+        };
+        verifier.run()?;
+    }
+    Ok(())
+}
+
+struct Verifier<'p> {
+    program: &'p Program,
+    locals: &'p Locals,
+    body: Body,
+    /// Addresses of the body, `end` being exclusive.
+    start: usize,
+    end: usize,
+    name: &'p str,
+}
+
+impl Verifier<'_> {
+    /// Follows every path through the body, tracking the types on the stack.
+    /// Paths that meet must agree on them.
+    fn run(&self) -> std::result::Result<(), String> {
+        let mut stacks: Vec<Option<Vec<Type>>> = vec![None; self.end - self.start];
+        stacks[0] = Some(Vec::new());
+        let mut pending = vec![self.start];
+
+        while let Some(address) = pending.pop() {
+            let mut stack = stacks[address - self.start].clone().expect("reached");
+            let next = self.step(address, &mut stack)?;
+            for target in next {
+                if target < self.start || target >= self.end {
+                    return Err(if target == self.end && target == address + 1 {
+                        format!("{} runs past its end at address {address}", self.name)
+                    } else {
+                        format!(
+                            "address {address} jumps to {target}, outside of {}",
+                            self.name
+                        )
+                    });
+                }
+                match &stacks[target - self.start] {
+                    None => {
+                        stacks[target - self.start] = Some(stack.clone());
+                        pending.push(target);
+                    }
+                    Some(known) if *known != stack => {
+                        return Err(format!(
+                            "the stack differs between the paths reaching address {target}"
+                        ));
+                    }
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the instruction at `address` to the types on the stack and
+    /// returns the addresses that may run next.
+    fn step(
+        &self,
+        address: usize,
+        stack: &mut Vec<Type>,
+    ) -> std::result::Result<Vec<usize>, String> {
+        let error = |what: &str| format!("{what} at address {address}");
+        let pop = |stack: &mut Vec<Type>| stack.pop().ok_or_else(|| error("stack underflow"));
+        let numeric = |stack: &mut Vec<Type>| match pop(stack)? {
+            Type::Str => Err(error("a string used as a number")),
+            ty => Ok(ty),
+        };
+        let ints = |stack: &mut Vec<Type>, count: u32| {
+            if count == 0 {
+                return Err(error("an array without dimensions"));
+            }
+            for _ in 0..count {
+                if pop(stack)? != Type::Int {
+                    return Err(error("a size or index that is not an integer"));
+                }
+            }
+            Ok(())
+        };
+        let store = |found: Type, expected: Type| {
+            if (found == Type::Str) != (expected == Type::Str) {
+                return Err(error(&format!("{found} value stored as {expected}")));
+            }
+            Ok(())
+        };
+        let array = |index: u32| {
+            self.locals
+                .arrays
+                .get(index as usize)
+                .map(|(_, ty)| *ty)
+                .ok_or_else(|| error(&format!("unknown array {index}")))
+        };
+        let empty = |stack: &Vec<Type>| {
+            if !stack.is_empty() {
+                return Err(error("values left on the stack"));
+            }
+            Ok(())
+        };
+        let main_only = |what: &str| match self.body {
+            Body::Main => Ok(()),
+            _ => Err(error(&format!("{what} outside of the main program"))),
+        };
+
+        let next = address + 1;
+        match self.program.code[address] {
+            Instruction::Const(index) => {
+                let constant = self
+                    .program
+                    .constants
+                    .get(index as usize)
+                    .ok_or_else(|| error(&format!("unknown constant {index}")))?;
+                stack.push(match constant {
+                    Value::Int(_) => Type::Int,
+                    Value::Float(_) => Type::Float,
+                    Value::Str(_) => Type::Str,
+                });
+            }
+            Instruction::Load(var) => stack.push(self.variable(var).map_err(|e| error(&e))?),
+            Instruction::Store(var) => {
+                let expected = self.variable(var).map_err(|e| error(&e))?;
+                store(pop(stack)?, expected)?;
+            }
+            Instruction::Dim {
+                array: index,
+                dimensions,
+            } => {
+                array(index)?;
+                ints(stack, dimensions)?;
+            }
+            Instruction::LoadElement {
+                array: index,
+                indices,
+            } => {
+                let ty = array(index)?;
+                ints(stack, indices)?;
+                stack.push(ty);
+            }
+            Instruction::StoreElement {
+                array: index,
+                indices,
+            } => {
+                let ty = array(index)?;
+                ints(stack, indices)?;
+                store(pop(stack)?, ty)?;
+            }
+            Instruction::Pop | Instruction::Print => {
+                pop(stack)?;
+            }
+            Instruction::Add | Instruction::Sub | Instruction::Mul | Instruction::Div => {
+                let right = pop(stack)?;
+                let left = pop(stack)?;
+                let joins = matches!(self.program.code[address], Instruction::Add);
+                stack.push(match (left, right) {
+                    (Type::Str, Type::Str) if joins => Type::Str,
+                    (Type::Int, Type::Int) => Type::Int,
+                    (Type::Str, _) | (_, Type::Str) => {
+                        return Err(error("invalid operands"));
+                    }
+                    _ => Type::Float,
+                });
+            }
+            Instruction::Neg => {
+                let ty = numeric(stack)?;
+                stack.push(ty);
+            }
+            Instruction::Step(var) => {
+                if self.variable(var).map_err(|e| error(&e))? == Type::Str {
+                    return Err(error("a loop variable that is not a number"));
+                }
+                numeric(stack)?;
+                stack.push(Type::Int);
+            }
+            Instruction::Compare(_) => {
+                let right = pop(stack)?;
+                let left = pop(stack)?;
+                if (left == Type::Str) != (right == Type::Str) {
+                    return Err(error("a string compared with a number"));
+                }
+                stack.push(Type::Int);
+            }
+            Instruction::Builtin { builtin, args } => {
+                let (params, result) = signature(builtin);
+                if !builtin.arity().contains(&(args as usize)) {
+                    return Err(error(&format!(
+                        "{} called with {args} arguments",
+                        builtin.name()
+                    )));
+                }
+                for param in params[..args as usize].iter().rev() {
+                    if !param.accepts(pop(stack)?) {
+                        return Err(error(&format!("invalid argument to {}", builtin.name())));
+                    }
+                }
+                stack.push(result);
+            }
+            Instruction::Jump(target) => return Ok(vec![target as usize]),
+            Instruction::JumpIfFalse(target) | Instruction::JumpIfTrue(target) => {
+                numeric(stack)?;
+                return Ok(vec![target as usize, next]);
+            }
+            Instruction::Gosub(target) => {
+                main_only("GOSUB")?;
+                empty(stack)?;
+                return Ok(vec![target as usize, next]);
+            }
+            Instruction::ReturnSub => {
+                main_only("RETURN from a GOSUB")?;
+                empty(stack)?;
+                return Ok(Vec::new());
+            }
+            Instruction::Call(index) => {
+                let procedure = self
+                    .program
+                    .procedures
+                    .get(index as usize)
+                    .ok_or_else(|| error(&format!("unknown procedure {index}")))?;
+                let params = &procedure.locals.variables[..procedure.params as usize];
+                for (_, ty) in params.iter().rev() {
+                    store(pop(stack)?, *ty)?;
+                }
+                stack.extend(procedure.result);
+            }
+            Instruction::Return => {
+                if !matches!(self.body, Body::Sub) {
+                    return Err(error("RETURN outside of a SUB"));
+                }
+                empty(stack)?;
+                return Ok(Vec::new());
+            }
+            Instruction::ReturnValue => {
+                let Body::Function(result) = self.body else {
+                    return Err(error("RETURN with a value outside of a FUNCTION"));
+                };
+                store(pop(stack)?, result)?;
+                empty(stack)?;
+                return Ok(Vec::new());
+            }
+            Instruction::Input(ty) => stack.push(ty),
+            Instruction::Halt => {
+                empty(stack)?;
+                return Ok(Vec::new());
+            }
+        }
+        Ok(vec![next])
+    }
+
+    fn variable(&self, index: u32) -> std::result::Result<Type, String> {
+        self.locals
+            .variables
+            .get(index as usize)
+            .map(|(_, ty)| *ty)
+            .ok_or_else(|| format!("unknown variable {index}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::Vm;
+
+    const PROGRAM: &str = include_str!("../examples/procedures.tb");
+
+    fn compile(source: &str) -> Program {
+        crate::bytecode::compile(&crate::check(source).expect("the program is valid"))
+    }
+
+    fn output(program: &Program) -> String {
+        let mut output = Vec::new();
+        Vm::new(&b""[..], &mut output).run(program).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn round_trips() {
+        let program = compile(PROGRAM);
+        for lines in [true, false] {
+            let bytes = write(&program, lines);
+            let loaded = read(&bytes).unwrap();
+            assert_eq!(write(&loaded, lines), bytes);
+            assert_eq!(output(&loaded), output(&program));
+        }
+    }
+
+    #[test]
+    fn line_table_keeps_lines_and_columns() {
+        let program = compile("PRINT 1\nLET a = 0\nPRINT 1 / a\n");
+        let positions = |program: &Program| -> Vec<(usize, usize)> {
+            program
+                .spans
+                .iter()
+                .map(|span| (span.line, span.column))
+                .collect()
+        };
+        let loaded = read(&write(&program, true)).unwrap();
+        assert_eq!(positions(&loaded), positions(&program));
+
+        let stripped = read(&write(&program, false)).unwrap();
+        assert!(stripped.spans.iter().all(|span| span.line == 0));
+    }
+
+    #[test]
+    fn truncated_files_are_rejected() {
+        let bytes = write(&compile(PROGRAM), true);
+        for len in 0..bytes.len() {
+            assert!(read(&bytes[..len]).is_err(), "{len} bytes are accepted");
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let mut bytes = write(&compile("PRINT 1\n"), false);
+        bytes[MAGIC.len()] = VERSION as u8 + 1;
+        assert!(read(&bytes).is_err());
+        assert!(read(b"PRINT 1\n").is_err());
+    }
+}
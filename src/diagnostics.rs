@@ -0,0 +1,222 @@
+use std::fmt::{self, Write};
+
+use crate::span::Span;
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const YELLOW: &str = "\x1b[1;33m";
+const BLUE: &str = "\x1b[1;34m";
+
+/// Columns a tab is expanded to in snippets, like rustc does, so that the
+/// underline lines up with the code whatever the terminal's tab width.
+const TAB_WIDTH: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Severity {
+    fn color(&self) -> &'static str {
+        match self {
+            Severity::Error => RED,
+            Severity::Warning => YELLOW,
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// Secondary location attached to a diagnostic, e.g. "first declared here".
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Span,
+    pub labels: Vec<Label>,
+    pub help: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn error(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message, span)
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message, span)
+    }
+
+    fn new(severity: Severity, message: impl Into<String>, span: Span) -> Diagnostic {
+        Diagnostic {
+            severity,
+            code: None,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            help: Vec::new(),
+        }
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Diagnostic {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_help(mut self, message: impl Into<String>) -> Diagnostic {
+        self.help.push(message.into());
+        self
+    }
+
+    /// Renders the diagnostic rustc-style against the source it was produced from.
+    ///
+    /// ```text
+    /// error[TB0004]: label `loop` is already declared
+    ///  --> sample.tb:4:1
+    ///   |
+    /// 1 | LABEL loop
+    ///   | ---------- label first declared here
+    /// 4 | LABEL loop
+    ///   | ^^^^^^^^^^
+    /// ```
+    pub fn render(&self, file_name: &str, source: &str, color: bool) -> String {
+        let paint = |style: &str, text: &str| match color {
+            true => format!("{style}{text}{RESET}"),
+            false => text.to_string(),
+        };
+
+        let gutter = self
+            .labels
+            .iter()
+            .map(|label| label.span.line)
+            .chain([self.span.line])
+            .max()
+            .unwrap_or(1)
+            .to_string()
+            .len();
+        let pipe = paint(BLUE, "|");
+
+        let severity = match self.code {
+            Some(code) => format!("{}[{code}]", self.severity),
+            None => self.severity.to_string(),
+        };
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}{}",
+            paint(self.severity.color(), &severity),
+            paint(BOLD, &format!(": {}", self.message)),
+        );
+        let _ = writeln!(
+            out,
+            "{:gutter$}{} {file_name}:{}",
+            "",
+            paint(BLUE, "-->"),
+            self.span
+        );
+        let _ = writeln!(out, "{:gutter$} {pipe}", "");
+
+        let mut snippets: Vec<(Span, &str, char, &str)> = self
+            .labels
+            .iter()
+            .map(|label| (label.span, label.message.as_str(), '-', BLUE))
+            .collect();
+        snippets.push((self.span, "", '^', self.severity.color()));
+        snippets.sort_by_key(|(span, ..)| span.start);
+
+        for (span, message, marker, style) in snippets {
+            let line = source
+                .lines()
+                .nth(span.line.saturating_sub(1))
+                .unwrap_or("");
+            let before: String = line.chars().take(span.column.saturating_sub(1)).collect();
+            let column = expand_tabs(&before).chars().count();
+            let width = source
+                .get(span.start..span.end)
+                .and_then(|text| text.lines().next())
+                .map(|text| expand_tabs(text).chars().count())
+                .unwrap_or(0)
+                .max(1);
+            let underline = marker.to_string().repeat(width);
+
+            let _ = writeln!(
+                out,
+                "{} {pipe} {}",
+                paint(BLUE, &format!("{:>gutter$}", span.line)),
+                expand_tabs(line)
+            );
+            let _ = writeln!(
+                out,
+                "{:gutter$} {pipe} {:column$}{}",
+                "",
+                "",
+                paint(style, format!("{underline} {message}").trim_end())
+            );
+        }
+
+        if !self.help.is_empty() {
+            let _ = writeln!(out, "{:gutter$} {pipe}", "");
+        }
+        for help in &self.help {
+            let _ = writeln!(out, "{:gutter$} {} help: {help}", "", paint(BLUE, "="));
+        }
+
+        out
+    }
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}: {}", self.span, self.severity, self.message)
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn underline_lines_up_with_tab_indented_code() {
+        let source = "LET y = 1\n\tLET x = y\n";
+        let span = Span::new(19, 20, 2, 10);
+        let rendered = Diagnostic::error("boom", span).render("test.tb", source, false);
+
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[3], "2 |     LET x = y");
+        assert_eq!(lines[4], "  |             ^");
+    }
+
+    #[test]
+    fn underline_covers_the_span() {
+        let source = "LABEL loop\n";
+        let span = Span::new(0, 10, 1, 1);
+        let rendered = Diagnostic::error("boom", span).render("test.tb", source, false);
+
+        assert!(rendered.contains("1 | LABEL loop\n  | ^^^^^^^^^^\n"));
+    }
+}
@@ -0,0 +1,130 @@
+use std::fmt::Write;
+
+use crate::bytecode::{Instruction, Locals, Program};
+use crate::interpreter::Value;
+
+/// Lists the bytecode of a program, one instruction per line with its
+/// address, resolving operands to constants, names and procedures. Whenever
+/// the source line changes, the line is shown when `source` is given and its
+/// number otherwise.
+///
+/// ```text
+/// main:
+/// ; 1 | LET a = 5
+///     0  const 0             ; 5
+///     1  store 0             ; a
+/// ```
+pub fn disassemble(program: &Program, source: Option<&str>) -> String {
+    let lines: Vec<&str> = source.map(|s| s.lines().collect()).unwrap_or_default();
+    let mut out = String::new();
+    let mut locals = &program.main;
+    let mut line = 0;
+
+    for (address, instruction) in program.code.iter().enumerate() {
+        if address == 0 {
+            out.push_str("main:\n");
+        }
+        if let Some(procedure) = program
+            .procedures
+            .iter()
+            .find(|procedure| procedure.entry as usize == address)
+        {
+            locals = &procedure.locals;
+            let params: Vec<&str> = locals.variables[..procedure.params as usize]
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .collect();
+            let kind = match procedure.result {
+                Some(_) => "FUNCTION",
+                None => "SUB",
+            };
+            let _ = writeln!(out, "\n{kind} {}({}):", procedure.name, params.join(", "));
+            line = 0;
+        }
+
+        let span = program.spans[address];
+        if span.line != 0 && span.line != line {
+            line = span.line;
+            match lines.get(line - 1) {
+                Some(text) => {
+                    let _ = writeln!(out, "; {line} | {}", text.trim());
+                }
+                None => {
+                    let _ = writeln!(out, "; line {line}");
+                }
+            }
+        }
+
+        let (text, comment) = describe(program, locals, *instruction);
+        let row = format!("{address:>5}  {text}");
+        match comment {
+            Some(comment) => {
+                let _ = writeln!(out, "{row:<27}; {comment}");
+            }
+            None => {
+                let _ = writeln!(out, "{row}");
+            }
+        }
+    }
+
+    out
+}
+
+/// Mnemonic and operands of an instruction, with what its operands refer to.
+fn describe(
+    program: &Program,
+    locals: &Locals,
+    instruction: Instruction,
+) -> (String, Option<String>) {
+    let variable = |index: u32| locals.variables[index as usize].0.clone();
+    let array = |index: u32| locals.arrays[index as usize].0.clone();
+
+    match instruction {
+        Instruction::Const(index) => (
+            format!("const {index}"),
+            Some(match &program.constants[index as usize] {
+                Value::Str(s) => format!("{:?}", String::from_utf8_lossy(s)),
+                value => value.to_string(),
+            }),
+        ),
+        Instruction::Load(index) => (format!("load {index}"), Some(variable(index))),
+        Instruction::Store(index) => (format!("store {index}"), Some(variable(index))),
+        Instruction::Dim {
+            array: index,
+            dimensions,
+        } => (format!("dim {index} {dimensions}"), Some(array(index))),
+        Instruction::LoadElement {
+            array: index,
+            indices,
+        } => (format!("load_elem {index} {indices}"), Some(array(index))),
+        Instruction::StoreElement {
+            array: index,
+            indices,
+        } => (format!("store_elem {index} {indices}"), Some(array(index))),
+        Instruction::Pop => ("pop".to_string(), None),
+        Instruction::Add => ("add".to_string(), None),
+        Instruction::Sub => ("sub".to_string(), None),
+        Instruction::Mul => ("mul".to_string(), None),
+        Instruction::Div => ("div".to_string(), None),
+        Instruction::Neg => ("neg".to_string(), None),
+        Instruction::Step(var) => (format!("step {var}"), Some(variable(var))),
+        Instruction::Compare(relation) => (format!("cmp {}", relation.operator()), None),
+        Instruction::Builtin { builtin, args } => {
+            (format!("builtin {} {args}", builtin.name()), None)
+        }
+        Instruction::Jump(target) => (format!("jump {target}"), None),
+        Instruction::JumpIfFalse(target) => (format!("jump_if_false {target}"), None),
+        Instruction::JumpIfTrue(target) => (format!("jump_if_true {target}"), None),
+        Instruction::Gosub(target) => (format!("gosub {target}"), None),
+        Instruction::ReturnSub => ("return_sub".to_string(), None),
+        Instruction::Call(index) => (
+            format!("call {index}"),
+            Some(program.procedures[index as usize].name.clone()),
+        ),
+        Instruction::Return => ("return".to_string(), None),
+        Instruction::ReturnValue => ("return_value".to_string(), None),
+        Instruction::Print => ("print".to_string(), None),
+        Instruction::Input(ty) => (format!("input {ty}"), None),
+        Instruction::Halt => ("halt".to_string(), None),
+    }
+}
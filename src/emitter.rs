@@ -1,16 +1,77 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 use crate::{
-    ast::{Comparison, Expression, Primary, Statement, Term, Unary},
+    ast::{
+        Builtin, Comparison, Condition, ConditionKind, Expression, ExpressionKind, Primary,
+        PrimaryKind, Procedure, Statement, StatementKind, Target, Term, TermKind, Unary, UnaryKind,
+    },
     parser::Parser,
+    types::{expression_type, term_type, unary_type, ProcedureTypes, Scope, Type},
 };
 
+/// C runtime for string values, only included when a program uses strings.
+const STRING_RUNTIME: &str = include_str!("runtime/strings.c");
+
+/// C runtime reading INPUT a line at a time, only included when a program
+/// has an INPUT.
+const INPUT_RUNTIME: &str = include_str!("runtime/input.c");
+
+/// C runtime for the GOSUB return stack, only included when a program uses
+/// GOSUB.
+const GOSUB_RUNTIME: &str = include_str!("runtime/gosub.c");
+
+/// C runtime for DIM arrays and their bounds checks, only included when a
+/// program uses arrays.
+const ARRAYS_RUNTIME: &str = include_str!("runtime/arrays.c");
+
+/// Largest array of the main program stored as a fixed C array, bigger ones
+/// are allocated on the heap like those with sizes known only at runtime.
+const MAX_FIXED_ELEMENTS: u64 = 65536;
+
+/// C runtime for wrapping and checked integer arithmetic, only included when
+/// a program does arithmetic on integers.
+const ARITH_RUNTIME: &str = include_str!("runtime/arith.c");
+
+/// C runtime limiting the depth of procedure calls, only included when a
+/// program defines a SUB or FUNCTION.
+const CALLS_RUNTIME: &str = include_str!("runtime/calls.c");
+
 #[derive(Default)]
 pub struct Emitter {
     full_path: String,
     header: String,
     code: String,
-    variables: Option<HashSet<String>>,
+    types: Scope,
+    procedures: HashMap<String, ProcedureTypes>,
+    /// Arrays of the main program or of the procedure being generated.
+    arrays: HashMap<String, Array>,
+    /// Whether a SUB or FUNCTION body is being generated rather than `main`.
+    in_procedure: bool,
+    /// Whether the procedure being generated collects string temporaries,
+    /// so that it needs a frame of its own in the pool.
+    framed: bool,
+    /// Whether the program needs the string runtime.
+    strings: bool,
+    /// Whether the program has a DIM.
+    dims: bool,
+    /// Whether the program does arithmetic on integers.
+    arithmetic: bool,
+    /// Whether the program has an INPUT.
+    input: bool,
+    /// Whether string temporaries were created since the last `tb_collect()`.
+    temporaries: bool,
+    /// Hidden variables holding the limit and step of FOR loops.
+    loop_variables: Vec<(String, Type)>,
+    /// Number of GOSUB statements, each returns to its own label.
+    gosubs: usize,
+}
+
+/// How an array of the body being generated is stored.
+struct Array {
+    dimensions: usize,
+    /// Number of elements if it is a fixed C array, `None` if its DIM
+    /// allocates it.
+    fixed: Option<u64>,
 }
 
 impl Emitter {
@@ -21,24 +82,68 @@ impl Emitter {
         }
     }
 
+    /// SUBs and FUNCTIONs become C functions, declared and defined before
+    /// `main`.
     pub fn process(&mut self, parser: Parser) {
-        self.variables = Some(parser.variables);
-
-        self.emit_header("#include <stdio.h>");
-        self.emit_header("int main(void){");
+        self.types = parser.types;
+        self.procedures = parser.procedures;
+        self.strings = uses_strings(&self.types)
+            || self.procedures.values().any(|procedure| {
+                procedure.result == Some(Type::Str) || uses_strings(&procedure.scope)
+            });
+        self.arrays = arrays(&parser.ast.program, true);
 
-        for stmnt in parser.ast.program {
-            let code = self.gen_statement(&stmnt);
+        for stmnt in &parser.ast.program {
+            let code = self.gen_statement(stmnt);
             self.emit(code.as_str());
         }
 
         self.emit_line("return 0;");
+        self.gen_return_dispatch();
         self.emit_line("}");
+
+        let procedures: Vec<(String, String)> = parser
+            .ast
+            .procedures
+            .iter()
+            .map(|procedure| (self.gen_prototype(procedure), self.gen_procedure(procedure)))
+            .collect();
+
+        self.emit_header("#include <stdio.h>");
+        if self.strings {
+            self.emit_header(STRING_RUNTIME.trim_end());
+        }
+        if self.input {
+            self.emit_header(INPUT_RUNTIME.trim_end());
+        }
+        if self.arithmetic {
+            self.emit_header(ARITH_RUNTIME.trim_end());
+        }
+        if self.gosubs > 0 {
+            self.emit_header(GOSUB_RUNTIME.trim_end());
+        }
+        if self.dims {
+            self.emit_header(ARRAYS_RUNTIME.trim_end());
+        }
+        if !procedures.is_empty() {
+            self.emit_header(CALLS_RUNTIME.trim_end());
+        }
+        for (prototype, _) in &procedures {
+            self.emit_header(&format!("{prototype};"));
+        }
+        for (_, definition) in &procedures {
+            self.emit_header(definition);
+        }
+        self.emit_header("int main(void){");
+        self.gen_declarations();
+    }
+
+    pub fn output(&self) -> String {
+        format!("{}{}", self.header, self.code)
     }
 
-    pub fn write_to_file(&self) {
-        let content = [self.header.as_bytes(), self.code.as_bytes()].concat();
-        std::fs::write(self.full_path.as_str(), content).expect("Couldnt write to file");
+    pub fn write_to_file(&self) -> std::io::Result<()> {
+        std::fs::write(self.full_path.as_str(), self.output())
     }
 
     fn emit(&mut self, code: &str) {
@@ -55,119 +160,857 @@ impl Emitter {
         self.header.push('\n');
     }
 
+    /// Every RETURN jumps here, to go back to the label after the GOSUB that
+    /// called the subroutine.
+    fn gen_return_dispatch(&mut self) {
+        if self.gosubs == 0 {
+            return;
+        }
+
+        self.emit_line("tb_dispatch_return:");
+        self.emit_line("switch (tb_return()) {");
+        for site in 0..self.gosubs {
+            self.emit_line(&format!("case {site}: goto tb_return_{site};"));
+        }
+        self.emit_line("}");
+        self.emit_line("return 0;");
+    }
+
+    /// Declares every variable and array of the program once at the top of
+    /// `main`, so that it is in scope everywhere and no GOTO can jump over its
+    /// declaration. BASIC variables start out as zero, arrays with no
+    /// elements until their DIM runs.
+    fn gen_declarations(&mut self) {
+        for declaration in self.declarations(&[]) {
+            self.emit_header(&declaration);
+        }
+    }
+
+    /// Declarations of the variables in `types` other than `params`, of the
+    /// arrays, then of the hidden FOR loop variables.
+    fn declarations(&self, params: &[String]) -> Vec<String> {
+        let mut variables: Vec<(&String, &Type)> = self
+            .types
+            .variables
+            .iter()
+            .filter(|(var, _)| !params.contains(var))
+            .collect();
+        variables.sort_by_key(|(var, _)| *var);
+
+        variables
+            .into_iter()
+            .map(|(var, ty)| match ty {
+                Type::Str => format!("char *{} = tb_new(\"\");", var_name(var)),
+                _ => format!("{} {} = 0;", c_type(*ty), var_name(var)),
+            })
+            .chain(self.array_declarations())
+            .chain(
+                self.loop_variables
+                    .iter()
+                    .map(|(name, ty)| format!("{} {name} = 0;", c_type(*ty))),
+            )
+            .collect()
+    }
+
+    /// Every array has the size of each dimension in `tb_size_<name>`, 0
+    /// until its DIM runs. The arrays of `main` are `static`, fixed ones to
+    /// stay off the stack and allocated ones to stay reachable until exit.
+    fn array_declarations(&self) -> Vec<String> {
+        let mut arrays: Vec<(&String, &Array)> = self.arrays.iter().collect();
+        arrays.sort_by_key(|(name, _)| *name);
+        let storage = if self.in_procedure { "" } else { "static " };
+
+        let mut declarations = Vec::new();
+        for (name, array) in arrays {
+            let element = match self.element_type(name) {
+                Type::Str => "char *".to_string(),
+                ty => format!("{} ", c_type(ty)),
+            };
+            declarations.push(match array.fixed {
+                Some(count) => format!("{storage}{element}{}[{count}];", arr_name(name)),
+                None => format!("{storage}{element}*{} = NULL;", arr_name(name)),
+            });
+            declarations.push(format!(
+                "long long {}[{}] = {{0}};",
+                size_name(name),
+                array.dimensions
+            ));
+        }
+        declarations
+    }
+
+    fn element_type(&self, array: &str) -> Type {
+        self.types.arrays.get(array).copied().unwrap_or(Type::Int)
+    }
+
+    fn gen_prototype(&self, procedure: &Procedure) -> String {
+        let types = &self.procedures[&procedure.name];
+        let params: Vec<String> = procedure
+            .params
+            .iter()
+            .zip(&types.params)
+            .map(|(param, ty)| format!("{} {}", c_type(*ty), var_name(param)))
+            .collect();
+        let params = match params.is_empty() {
+            true => "void".to_string(),
+            false => params.join(", "),
+        };
+        let result = types.result.map_or("void", c_type);
+        format!("static {result} {}({params})", fn_name(&procedure.name))
+    }
+
+    /// Strings passed as arguments are copied on entry, the procedure owns
+    /// them like its other variables. Every RETURN jumps to `tb_exit`, which
+    /// frees them, the arrays and the string temporaries of the call.
+    ///
+    /// Procedures may be recursive, so their arrays are always allocated on
+    /// the heap rather than risk overflowing the C stack.
+    fn gen_procedure(&mut self, procedure: &Procedure) -> String {
+        let types = self.procedures[&procedure.name].clone();
+        let globals = std::mem::replace(&mut self.types, types.scope);
+        let global_arrays =
+            std::mem::replace(&mut self.arrays, arrays([procedure.body.as_ref()], false));
+        let loop_variables = std::mem::take(&mut self.loop_variables);
+        self.in_procedure = true;
+        self.framed = false;
+
+        let body = self.gen_statement(&procedure.body);
+
+        let mut code = format!(
+            "{} {{
+",
+            self.gen_prototype(procedure)
+        );
+        if let Some(result) = types.result {
+            match result {
+                Type::Str => {
+                    code += "char *tb_result = tb_new(\"\");
+"
+                }
+                _ => {
+                    code += &format!(
+                        "{} tb_result = 0;
+",
+                        c_type(result)
+                    )
+                }
+            }
+        }
+        for declaration in self.declarations(&procedure.params) {
+            code += &format!(
+                "{declaration}
+"
+            );
+        }
+        code += "tb_call();
+";
+        for (param, ty) in procedure.params.iter().zip(&types.params) {
+            if *ty == Type::Str {
+                code += &format!(
+                    "{0} = tb_new({0});
+",
+                    var_name(param)
+                );
+            }
+        }
+        if self.framed {
+            code += "size_t tb_caller_frame = tb_enter();
+";
+        }
+        code += &body;
+
+        code += "tb_exit:
+";
+        let mut owned: Vec<&String> = self
+            .types
+            .variables
+            .iter()
+            .filter(|(_, ty)| **ty == Type::Str)
+            .map(|(var, _)| var)
+            .collect();
+        owned.sort();
+        for var in owned {
+            code += &format!(
+                "free({});
+",
+                var_name(var)
+            );
+        }
+        let mut arrays: Vec<(&String, &Array)> = self.arrays.iter().collect();
+        arrays.sort_by_key(|(name, _)| *name);
+        for (name, array) in arrays {
+            if self.element_type(name) == Type::Str {
+                code += &format!(
+                    "tb_clear({}, tb_count({}, {}));
+",
+                    arr_name(name),
+                    size_name(name),
+                    array.dimensions
+                );
+            }
+            code += &format!(
+                "free({});
+",
+                arr_name(name)
+            );
+        }
+        if self.framed {
+            code += "tb_leave(tb_caller_frame);
+";
+        }
+        code += "tb_call_depth--;
+";
+        code += match types.result {
+            Some(Type::Str) => {
+                "return tb_temp(tb_result);
+}"
+            }
+            Some(_) => {
+                "return tb_result;
+}"
+            }
+            None => {
+                "return;
+}"
+            }
+        };
+
+        self.types = globals;
+        self.arrays = global_arrays;
+        self.loop_variables = loop_variables;
+        self.in_procedure = false;
+        code
+    }
+
     fn gen_statement(&mut self, s: &Statement) -> String {
-        match s {
-            Statement::Print(expr) => {
-                format!("printf(\"%d\\n\", {});\n", self.gen_expression(expr))
+        match &s.kind {
+            StatementKind::Print(expr) => {
+                let format = printf_format(expression_type(expr, &self.types, &self.procedures));
+                let code = format!("printf(\"{format}\\n\", {});\n", self.gen_expression(expr));
+                code + self.collect()
+            }
+            StatementKind::PrintStr(string) => {
+                format!("fputs({}, stdout);\n", c_string(&format!("{string}\n")))
+            }
+            StatementKind::Dim(array, sizes) => {
+                let code = self.gen_dim(array, sizes, s.span.line);
+                code + self.collect()
+            }
+            StatementKind::Let(target, expr) => {
+                let value = self.gen_expression(expr);
+                let (var, ty) = self.gen_target(target, s.span.line);
+                let code = match ty {
+                    Type::Str => format!("tb_assign(&{var}, {value});\n"),
+                    _ => format!("{var} = {value};\n"),
+                };
+                code + self.collect()
             }
-            Statement::PrintStr(string) => {
-                format!("printf(\"{}\\n\");\n", string)
+            // Temporaries of a condition are collected at the start of every
+            // branch and after the statement.
+            StatementKind::If(comp, stmt, otherwise) => {
+                let condition = self.gen_condition(comp);
+                let collect = self.collect();
+                let then = self.gen_statement(stmt);
+                let otherwise = match otherwise {
+                    Some(stmt) if matches!(stmt.kind, StatementKind::If(..)) => {
+                        format!(" else {}", self.gen_statement(stmt))
+                    }
+                    Some(stmt) => {
+                        format!(" else {{\n{collect}{}\n}}\n", self.gen_statement(stmt))
+                    }
+                    None => "\n".to_string(),
+                };
+                format!("if ({condition}) {{\n{collect}{then}\n}}{otherwise}{collect}")
             }
-            Statement::Let(var, expr) => {
+            StatementKind::While(comp, stmt) => {
+                let condition = self.gen_condition(comp);
+                let collect = self.collect();
                 format!(
-                    "{} = {};\n",
-                    self.gen_primary(&Primary::Variable(var.to_string())),
-                    self.gen_expression(expr)
+                    "while ({condition}) {{\n{collect}{}\n}}\n{collect}",
+                    self.gen_statement(stmt)
                 )
             }
-            Statement::If(comp, stmt) => {
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => {
+                let int = self.types.variables[var] == Type::Int;
+                let var = var_name(var);
+                let from = self.gen_expression(from);
+                let limit = self.loop_variable("to", to);
+                let mut init = format!("{var} = {from}, {limit} = {}", self.gen_expression(to));
+
+                let (condition, step) = match step {
+                    Some(step) => {
+                        let name = self.loop_variable("step", step);
+                        init += &format!(", {name} = {}", self.gen_expression(step));
+                        (
+                            format!("{name} >= 0 ? {var} <= {limit} : {var} >= {limit}"),
+                            name,
+                        )
+                    }
+                    None => (format!("{var} <= {limit}"), "1LL".to_string()),
+                };
+                // An integer step past the end of the integers is past the
+                // limit too, so it ends the loop rather than wrapping around.
+                self.arithmetic |= int;
+                let (increment, next) = match int {
+                    true => (
+                        String::new(),
+                        format!("if (!tb_step(&{var}, {step})) {{\nbreak;\n}}\n"),
+                    ),
+                    false => (format!("{var} += {step}"), String::new()),
+                };
+
+                let collect = self.collect();
                 format!(
-                    "if ({}) {{\n{}\n}}\n",
-                    self.gen_comparison(comp),
-                    self.gen_statement(stmt)
+                    "for ({init}; {condition}; {increment}) {{\n{collect}{}\n{next}}}\n{collect}",
+                    self.gen_statement(body)
                 )
             }
-            Statement::While(comp, stmt) => {
+            StatementKind::Label(label) => {
+                format!("{}:\n", label_name(label))
+            }
+            StatementKind::Goto(label) => {
+                format!("goto {};\n", label_name(label))
+            }
+            StatementKind::Gosub(label) => {
+                let site = self.gosubs;
+                self.gosubs += 1;
                 format!(
-                    "while ({}) {{\n{}\n}}\n",
-                    self.gen_comparison(comp),
-                    self.gen_statement(stmt)
+                    "tb_gosub({site});\ngoto {};\ntb_return_{site}:;\n",
+                    label_name(label)
                 )
             }
-            Statement::Label(label) => {
-                format!("{}:\n", label)
+            StatementKind::Return(None) if !self.in_procedure => {
+                "goto tb_dispatch_return;\n".to_string()
             }
-            Statement::Goto(label) => {
-                format!("goto {};\n", label)
+            StatementKind::Return(None) => "goto tb_exit;\n".to_string(),
+            // Temporaries of the value are freed when the procedure leaves
+            // its frame.
+            StatementKind::Return(Some(expr)) => {
+                let value = self.gen_expression(expr);
+                self.framed |= std::mem::take(&mut self.temporaries);
+                match expression_type(expr, &self.types, &self.procedures) {
+                    Type::Str => format!("tb_assign(&tb_result, {value});\ngoto tb_exit;\n"),
+                    _ => format!("tb_result = {value};\ngoto tb_exit;\n"),
+                }
             }
-            Statement::Input(var) => {
-                // Check if var is a string or an integer
-
-                let typed_var = self.gen_primary(&Primary::Variable(var.to_string()));
-                format!("{typed_var};\nscanf(\"%d\", &{});\n", var)
+            StatementKind::Call(name, args) => {
+                let code = format!("{};\n", self.gen_function_call(name, args));
+                code + self.collect()
+            }
+            // Input is read a line at a time and checked like the
+            // interpreter does.
+            StatementKind::Input(target) => {
+                self.input = true;
+                let line = s.span.line;
+                let (var, ty) = self.gen_target(target, line);
+                let code = match ty {
+                    Type::Str => format!("tb_input_str(&{var}, {line});\n"),
+                    Type::Int => format!("{var} = tb_input_int({line});\n"),
+                    Type::Float => format!("{var} = tb_input_float({line});\n"),
+                };
+                code + self.collect()
             }
-            Statement::Statement(stmt1, stmt2) => {
+            StatementKind::Statement(stmt1, stmt2) => {
                 format!(
                     "{}\n{}",
                     self.gen_statement(stmt1),
                     self.gen_statement(stmt2)
                 )
             }
-            Statement::End => "\n".to_string(),
+            StatementKind::End => "\n".to_string(),
         }
     }
 
+    /// Evaluates the sizes before anything else, as they may read the array
+    /// being created again. A fixed array is cleared in place, an allocated
+    /// one is freed and allocated again with the new sizes.
+    fn gen_dim(&mut self, array: &str, sizes: &[Expression], line: usize) -> String {
+        self.dims = true;
+        let sizes: Vec<String> = sizes.iter().map(|size| self.gen_expression(size)).collect();
+        let dimensions = self.arrays[array].dimensions;
+        let (arr, size, name) = (arr_name(array), size_name(array), c_string(array));
+        let dim = format!("tb_dim({size}, tb_dims, {dimensions}, {name}, {line})");
+        let strings = self.element_type(array) == Type::Str;
+
+        let mut code = format!("{{\nlong long tb_dims[] = {{{}}};\n", sizes.join(", "));
+        match self.arrays[array].fixed {
+            Some(count) if strings => code += &format!("{dim};\ntb_clear({arr}, {count});\n"),
+            Some(_) => code += &format!("{dim};\nmemset({arr}, 0, sizeof {arr});\n"),
+            None => {
+                if strings {
+                    code += &format!("tb_clear({arr}, tb_count({size}, {dimensions}));\n");
+                }
+                code += &format!(
+                    "free({arr});\n{arr} = tb_array_alloc({dim}, sizeof *{arr}, {name}, {line});\n"
+                );
+            }
+        }
+        code + "}\n"
+    }
+
+    /// C lvalue assigned by LET or INPUT, and its type.
+    fn gen_target(&mut self, target: &Target, line: usize) -> (String, Type) {
+        match target {
+            Target::Variable(var) => (var_name(var), self.types.variables[var]),
+            Target::Element(array, indices) => (
+                self.gen_element(array, indices, line),
+                self.element_type(array),
+            ),
+        }
+    }
+
+    /// Element of an array, found by `tb_index` which stops the program if
+    /// an index is out of bounds.
+    fn gen_element(&mut self, array: &str, indices: &[Expression], line: usize) -> String {
+        let indices: Vec<String> = indices
+            .iter()
+            .map(|index| self.gen_expression(index))
+            .collect();
+        format!(
+            "{}[tb_index({}, (long long[]){{{}}}, {}, {}, {line})]",
+            arr_name(array),
+            size_name(array),
+            indices.join(", "),
+            indices.len(),
+            c_string(array)
+        )
+    }
+
+    /// Operands are always parenthesized, C's `&&` and `||` give the
+    /// short-circuit evaluation BASIC conditions need.
+    fn gen_condition(&mut self, c: &Condition) -> String {
+        match &c.kind {
+            ConditionKind::Comparison(comp) => self.gen_comparison(comp),
+            ConditionKind::Not(cond) => format!("!({})", self.gen_condition(cond)),
+            ConditionKind::And(left, right) => format!(
+                "({}) && ({})",
+                self.gen_condition(left),
+                self.gen_condition(right)
+            ),
+            ConditionKind::Or(left, right) => format!(
+                "({}) || ({})",
+                self.gen_condition(left),
+                self.gen_condition(right)
+            ),
+        }
+    }
+
+    /// Strings are compared with `tb_eq`, numbers with C's operators, which
+    /// chain the same way BASIC comparisons do.
     fn gen_comparison(&mut self, c: &Comparison) -> String {
-        match c {
-            Comparison::Left(expr) => self.gen_expression(expr),
-            Comparison::Right(expr, comp) => {
+        let (first, rest) = c.chain();
+
+        let mut code = self.gen_expression(first);
+        let mut strings = expression_type(first, &self.types, &self.procedures) == Type::Str;
+        for (op, expr) in rest {
+            let right = self.gen_expression(expr);
+            self.strings |= strings;
+            code = match (strings, op) {
+                (true, "==") => format!("tb_eq({code}, {right})"),
+                (true, _) => format!("!tb_eq({code}, {right})"),
+                (false, _) => format!("{code} {op} {right}"),
+            };
+            strings = false;
+        }
+
+        code
+    }
+
+    fn gen_expression(&mut self, e: &Expression) -> String {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.gen_term(term),
+            ExpressionKind::Add(expr, term)
+                if expression_type(e, &self.types, &self.procedures) == Type::Str =>
+            {
+                self.strings = true;
+                self.temporaries = true;
                 format!(
-                    "{} {}",
+                    "tb_concat({}, {})",
                     self.gen_expression(expr),
-                    self.gen_comparison(comp)
+                    self.gen_term(term)
                 )
             }
-            Comparison::Compare(op, comp) => {
-                format!("{} {}", op, self.gen_comparison(comp))
+            ExpressionKind::Add(expr, term) => {
+                let (left, right) = (self.gen_expression(expr), self.gen_term(term));
+                self.gen_arithmetic(e, "+", "tb_add", left, right)
+            }
+            ExpressionKind::Sub(expr, term) => {
+                let (left, right) = (self.gen_expression(expr), self.gen_term(term));
+                self.gen_arithmetic(e, "-", "tb_sub", left, right)
             }
         }
     }
 
-    fn gen_expression(&mut self, e: &Expression) -> String {
-        match e {
-            Expression::Term(term) => self.gen_term(term),
-            Expression::Add(term, expr) => {
-                format!("{} + {}", self.gen_term(term), self.gen_expression(expr))
+    fn gen_term(&mut self, t: &Term) -> String {
+        match &t.kind {
+            TermKind::Unary(u) => self.gen_unary(u).to_string(),
+            TermKind::Mul(left, right) => {
+                let (left, right) = (self.gen_term(left), self.gen_unary(right));
+                match term_type(t, &self.types, &self.procedures) {
+                    Type::Int => self.gen_int_call("tb_mul", &[left, right]),
+                    _ => format!("{left} * {right}"),
+                }
             }
-            Expression::Sub(term, expr) => {
-                format!("{} - {}", self.gen_term(term), self.gen_expression(expr))
+            TermKind::Div(left, right) => {
+                let (left, right) = (self.gen_term(left), self.gen_unary(right));
+                match term_type(t, &self.types, &self.procedures) {
+                    Type::Int => {
+                        self.gen_int_call("tb_div", &[left, right, t.span.line.to_string()])
+                    }
+                    _ => format!("{left} / {right}"),
+                }
             }
         }
     }
 
-    fn gen_term(&mut self, t: &Term) -> String {
-        match t {
-            Term::Unary(u) => self.gen_unary(u).to_string(),
-            Term::Mul(left, right) => {
-                format!("{} * {}", self.gen_unary(left), self.gen_term(right))
-            }
-            Term::Div(left, right) => {
-                format!("{} / {}", self.gen_unary(left), self.gen_term(right))
-            }
+    /// C's operator on floats, the runtime's wrapping `function` on integers.
+    fn gen_arithmetic(
+        &mut self,
+        e: &Expression,
+        op: &str,
+        function: &str,
+        left: String,
+        right: String,
+    ) -> String {
+        match expression_type(e, &self.types, &self.procedures) {
+            Type::Int => self.gen_int_call(function, &[left, right]),
+            _ => format!("{left} {op} {right}"),
         }
     }
+
+    fn gen_int_call(&mut self, function: &str, args: &[String]) -> String {
+        self.arithmetic = true;
+        format!("{function}({})", args.join(", "))
+    }
+
     fn gen_unary(&mut self, u: &Unary) -> String {
-        match u {
-            Unary::Primary(p) => self.gen_primary(p).to_string(),
-            Unary::Positive(u) => format!("+{}", self.gen_unary(u)),
-            Unary::Negative(u) => format!("-{}", self.gen_unary(u)),
+        match &u.kind {
+            UnaryKind::Primary(p) => self.gen_primary(p).to_string(),
+            UnaryKind::Positive(u) => format!("+{}", self.gen_signed(u)),
+            UnaryKind::Negative(operand) => match unary_type(u, &self.types, &self.procedures) {
+                Type::Int => {
+                    let operand = self.gen_unary(operand);
+                    self.gen_int_call("tb_neg", &[operand])
+                }
+                _ => format!("-{}", self.gen_signed(operand)),
+            },
+        }
+    }
+
+    /// Operand of a sign, parenthesized when it is signed itself so that
+    /// `- -x` doesn't turn into C's `--x`.
+    fn gen_signed(&mut self, u: &Unary) -> String {
+        match &u.kind {
+            UnaryKind::Primary(_) => self.gen_unary(u),
+            _ => format!("({})", self.gen_unary(u)),
         }
     }
+
     fn gen_primary(&mut self, p: &Primary) -> String {
-        match p {
-            Primary::Integer(v) => format!("{}", v),
-            Primary::Float(v) => format!("{}", v),
-            Primary::Number(v) => format!("{}", v),
-            Primary::Variable(v) => {
-                let decl = match self.variables.as_ref().unwrap().contains(v) {
-                    true => {
-                        self.variables.as_mut().unwrap().remove(v);
-                        "int"
-                    }
-                    false => "",
-                };
-                format!("{decl} {}", v)
+        match &p.kind {
+            // Integers are `long long` to match the interpreter's 64-bit
+            // arithmetic, floats always keep a decimal point or exponent.
+            PrimaryKind::Integer(v) => format!("{}LL", v),
+            PrimaryKind::Float(v) => format!("{:?}", v),
+            PrimaryKind::Number(v) => format!("{}LL", v),
+            PrimaryKind::String(v) => c_string(v),
+            PrimaryKind::Variable(v) => var_name(v),
+            PrimaryKind::Call(builtin, args) => self.gen_call(*builtin, args),
+            PrimaryKind::FunctionCall(name, args) => self.gen_function_call(name, args),
+            // String elements not assigned yet are NULL.
+            PrimaryKind::Index(array, indices) => {
+                let element = self.gen_element(array, indices, p.span.line);
+                match self.element_type(array) {
+                    Type::Str => format!("tb_element({element})"),
+                    _ => element,
+                }
+            }
+            PrimaryKind::Grouped(expr) => format!("({})", self.gen_expression(expr)),
+        }
+    }
+
+    fn gen_call(&mut self, builtin: Builtin, args: &[Expression]) -> String {
+        let mut code: Vec<String> = args.iter().map(|arg| self.gen_expression(arg)).collect();
+
+        let function = match builtin {
+            Builtin::Len => "tb_len",
+            Builtin::Left => "tb_left",
+            Builtin::Mid => {
+                if code.len() == 2 {
+                    code.push("LLONG_MAX".to_string());
+                }
+                "tb_mid"
+            }
+            Builtin::Str => match expression_type(&args[0], &self.types, &self.procedures) {
+                Type::Float => "tb_str_float",
+                _ => "tb_str_int",
+            },
+            Builtin::Val => "tb_val",
+        };
+        self.strings = true;
+        if matches!(builtin, Builtin::Left | Builtin::Mid | Builtin::Str) {
+            self.temporaries = true;
+        }
+
+        format!("{function}({})", code.join(", "))
+    }
+
+    /// A string returned by a FUNCTION is a temporary of the caller.
+    fn gen_function_call(&mut self, name: &str, args: &[Expression]) -> String {
+        let args: Vec<String> = args.iter().map(|arg| self.gen_expression(arg)).collect();
+        if self.procedures[name].result == Some(Type::Str) {
+            self.temporaries = true;
+        }
+
+        format!("{}({})", fn_name(name), args.join(", "))
+    }
+
+    /// Declares a hidden variable for the `part` of a FOR loop, so that it is
+    /// evaluated once before the loop starts.
+    fn loop_variable(&mut self, part: &str, e: &Expression) -> String {
+        let name = format!("tb_{part}_{}", self.loop_variables.len());
+        let ty = expression_type(e, &self.types, &self.procedures);
+        self.loop_variables.push((name.clone(), ty));
+        name
+    }
+
+    /// `tb_collect();` if the code generated since the last call created
+    /// string temporaries.
+    fn collect(&mut self) -> &'static str {
+        match std::mem::take(&mut self.temporaries) {
+            true => {
+                self.framed = true;
+                "tb_collect();\n"
+            }
+            false => "",
+        }
+    }
+}
+
+/// C name of a `kind` of name, prefixed to keep it apart from C keywords,
+/// libc and the runtime. A `name$` gets a prefix of its own, `x$` becomes
+/// `tb_strvar_x`, so that no other name can map to the same C name.
+fn c_name(kind: &str, name: &str) -> String {
+    match name.strip_suffix('$') {
+        Some(stem) => format!("tb_str{kind}_{stem}"),
+        None => format!("tb_{kind}_{name}"),
+    }
+}
+
+fn var_name(name: &str) -> String {
+    c_name("var", name)
+}
+
+fn label_name(name: &str) -> String {
+    c_name("label", name)
+}
+
+fn arr_name(name: &str) -> String {
+    c_name("arr", name)
+}
+
+/// C name of the array holding the size of each dimension of an array.
+fn size_name(name: &str) -> String {
+    c_name("size", name)
+}
+
+/// C name of a SUB or FUNCTION.
+fn fn_name(name: &str) -> String {
+    c_name("fn", name)
+}
+
+fn uses_strings(types: &Scope) -> bool {
+    types
+        .variables
+        .values()
+        .chain(types.arrays.values())
+        .any(|ty| *ty == Type::Str)
+}
+
+/// Arrays created by the DIM statements in `statements`, fixed C arrays if
+/// `fixed` allows it and their sizes are small enough integer literals.
+fn arrays<'a>(
+    statements: impl IntoIterator<Item = &'a Statement>,
+    fixed: bool,
+) -> HashMap<String, Array> {
+    let mut dims = Vec::new();
+    for statement in statements {
+        collect_dims(statement, &mut dims);
+    }
+
+    dims.into_iter()
+        .map(|(name, sizes)| {
+            let count = sizes.iter().try_fold(1u64, |count, size| {
+                let size = u64::try_from(size.as_integer_literal()?).ok()?;
+                count.checked_mul(size.checked_add(1)?)
+            });
+            let array = Array {
+                dimensions: sizes.len(),
+                fixed: count.filter(|count| fixed && *count <= MAX_FIXED_ELEMENTS),
+            };
+            (name.to_string(), array)
+        })
+        .collect()
+}
+
+fn collect_dims<'a>(statement: &'a Statement, dims: &mut Vec<(&'a str, &'a [Expression])>) {
+    match &statement.kind {
+        StatementKind::Dim(name, sizes) => dims.push((name, sizes)),
+        StatementKind::If(_, then, otherwise) => {
+            collect_dims(then, dims);
+            if let Some(otherwise) = otherwise {
+                collect_dims(otherwise, dims);
             }
         }
+        StatementKind::While(_, body) | StatementKind::For { body, .. } => collect_dims(body, dims),
+        StatementKind::Statement(first, rest) => {
+            collect_dims(first, dims);
+            collect_dims(rest, dims);
+        }
+        _ => (),
+    }
+}
+
+/// C string literal holding `s`. Everything but printable ASCII is written as
+/// an octal escape, which unlike `\x` cannot run into the following character.
+fn c_string(s: &str) -> String {
+    let mut literal = String::from('"');
+    for byte in s.bytes() {
+        match byte {
+            b'"' => literal.push_str("\\\""),
+            b'\\' => literal.push_str("\\\\"),
+            b'\n' => literal.push_str("\\n"),
+            b'\t' => literal.push_str("\\t"),
+            // Avoids forming trigraphs such as `??/`.
+            b'?' => literal.push_str("\\?"),
+            b' '..=b'~' => literal.push(byte as char),
+            _ => literal.push_str(&format!("\\{byte:03o}")),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+fn c_type(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => "long long",
+        Type::Float => "double",
+        Type::Str => "char *",
+    }
+}
+
+fn printf_format(ty: Type) -> &'static str {
+    match ty {
+        Type::Int => "%lld",
+        Type::Float => "%g",
+        Type::Str => "%s",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    fn emit(source: &str) -> String {
+        crate::compile(source).expect("the program is valid")
+    }
+
+    #[test]
+    fn integer_division_is_checked() {
+        let code = emit("LET a = 7\nPRINT a / 2\n");
+        assert!(code.contains("tb_div(tb_var_a, 2LL, 2)"));
+        assert!(code.contains("attempt to divide by zero on line %d"));
+    }
+
+    #[test]
+    fn integer_arithmetic_wraps() {
+        let code = emit("LET a = 1\nPRINT -a * a + a - 1\n");
+        assert!(code.contains("tb_sub(tb_add(tb_mul(tb_neg(tb_var_a), tb_var_a), tb_var_a), 1LL)"));
+    }
+
+    #[test]
+    fn float_arithmetic_uses_c_operators() {
+        let code = emit("LET a = 1.5\nPRINT -a * 2 / a + 1\n");
+        assert!(code.contains("-tb_var_a * 2LL / tb_var_a + 1LL"));
+        assert!(!code.contains("tb_add"));
+    }
+
+    #[test]
+    fn parentheses_are_kept_where_the_source_has_them() {
+        let code = emit("LET a = 1.5\nPRINT 10 - (a - 2) - 1\nPRINT (a + 2) * 3 / (a - 1)\n");
+        assert!(code.contains("10LL - (tb_var_a - 2LL) - 1LL"));
+        assert!(code.contains("(tb_var_a + 2LL) * 3LL / (tb_var_a - 1LL)"));
+    }
+
+    #[test]
+    fn left_associative_operators_need_no_parentheses() {
+        let code = emit("LET a = 1.5\nPRINT a - 3 - 2\nPRINT a / 3 / 2\nPRINT 10 - 3 - 2\n");
+        assert!(code.contains("tb_var_a - 3LL - 2LL"));
+        assert!(code.contains("tb_var_a / 3LL / 2LL"));
+        assert!(code.contains("tb_sub(tb_sub(10LL, 3LL), 2LL)"));
+    }
+
+    #[test]
+    fn conditions_use_short_circuit_operators() {
+        let code = emit("LET x = 0\nIF x != 0 AND 10 / x > 1 OR NOT x == 0 THEN\nPRINT 1\nENDIF\n");
+        assert!(code.contains(
+            "if (((tb_var_x != 0LL) && (tb_div(10LL, tb_var_x, 2) > 1LL)) || (!(tb_var_x == 0LL)))"
+        ));
+    }
+
+    #[test]
+    fn integer_for_loops_stop_rather_than_wrap() {
+        let code = emit("FOR i = 1 TO 3\nPRINT i\nNEXT\n");
+        assert!(code.contains("tb_var_i <= tb_to_0; )"));
+        assert!(code.contains("if (!tb_step(&tb_var_i, 1LL)) {\nbreak;\n}\n}"));
+    }
+
+    #[test]
+    fn variables_cannot_clash_with_c_names() {
+        let code = emit("LET int = 4\nLET printf = 1\nLET main = 5\nPRINT int + printf + main\n");
+        assert!(code.contains("long long tb_var_int = 0;"));
+        assert!(code.contains("tb_var_printf = 1LL;"));
+        assert!(code.contains("tb_var_main = 5LL;"));
+    }
+
+    #[test]
+    fn variables_cannot_clash_with_loop_limits() {
+        let code = emit("LET tb_to_0 = 1\nFOR i = 1 TO 3\nPRINT tb_to_0\nNEXT\n");
+        assert!(code.contains("tb_var_tb_to_0 = 1LL;"));
+        assert!(code.contains("tb_to_0 = 3LL"));
+    }
+
+    #[test]
+    fn labels_cannot_clash_with_runtime_labels() {
+        let code = emit("GOTO tb_exit\nLABEL tb_exit\n");
+        assert!(code.contains("goto tb_label_tb_exit;"));
+        assert!(code.contains("tb_label_tb_exit:"));
+    }
+
+    #[test]
+    fn string_names_cannot_clash_with_other_names() {
+        let code = emit("LET a$ = \"x\"\nLET a_str = 1\nLET stra = 2\nPRINT a$\n");
+        assert!(code.contains("char *tb_strvar_a = tb_new(\"\");"));
+        assert!(code.contains("long long tb_var_a_str = 0;"));
+        assert!(code.contains("long long tb_var_stra = 0;"));
+    }
+
+    #[test]
+    fn string_literals_are_printed_without_a_format() {
+        let code = emit("PRINT \"100% \\\"sure\\\"\\t\\u{e9}?\"\n");
+        assert!(code.contains("fputs(\"100% \\\"sure\\\"\\t\\303\\251\\?\\n\", stdout);"));
+    }
+
+    #[test]
+    fn input_reads_a_checked_line() {
+        let code = emit("LET f = 0.5\nINPUT n\nINPUT f\nINPUT s$\n");
+        assert!(code.contains("tb_var_n = tb_input_int(2);"));
+        assert!(code.contains("tb_var_f = tb_input_float(3);"));
+        assert!(code.contains("tb_input_str(&tb_strvar_s, 4);"));
+        assert!(!code.contains("scanf"));
     }
 }
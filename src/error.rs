@@ -0,0 +1,608 @@
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::diagnostics::Diagnostic;
+use crate::interpreter::RuntimeError;
+use crate::lexer::Token;
+use crate::span::Span;
+use crate::types::Type;
+
+#[derive(Debug)]
+pub enum CompileError {
+    UnknownCharacter {
+        character: char,
+        span: Span,
+    },
+    InvalidNumber {
+        literal: String,
+        span: Span,
+    },
+    UnterminatedString {
+        span: Span,
+    },
+    InvalidEscape {
+        escape: String,
+        span: Span,
+    },
+    UnexpectedToken {
+        expected: String,
+        found: Option<Token>,
+        span: Span,
+    },
+    InvalidStatement {
+        found: Option<Token>,
+        span: Span,
+    },
+    UnmatchedBlockEnd {
+        found: Token,
+        span: Span,
+    },
+    DuplicateLabel {
+        label: String,
+        span: Span,
+        first: Span,
+    },
+    UndeclaredLabel {
+        keyword: &'static str,
+        label: String,
+        span: Span,
+    },
+    JumpIntoBlock {
+        label: String,
+        span: Span,
+        target: Span,
+    },
+    SubroutineInBlock {
+        label: String,
+        span: Span,
+        target: Span,
+    },
+    ReturnWithoutGosub {
+        span: Span,
+    },
+    UndefinedProcedure {
+        keyword: &'static str,
+        name: String,
+        span: Span,
+    },
+    SubWithoutValue {
+        name: String,
+        span: Span,
+    },
+    UndeclaredArray {
+        name: String,
+        span: Span,
+    },
+    DimensionMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+        dim: Span,
+    },
+    DuplicateDefinition {
+        name: String,
+        span: Span,
+        first: Span,
+    },
+    InvalidReturn {
+        function: Option<String>,
+        span: Span,
+    },
+    NestedProcedure {
+        keyword: &'static str,
+        span: Span,
+    },
+    GosubInProcedure {
+        span: Span,
+    },
+    UseBeforeAssign {
+        variable: String,
+        span: Span,
+    },
+    MismatchedNext {
+        expected: String,
+        found: String,
+        span: Span,
+        start: Span,
+    },
+    MismatchedTypes {
+        variable: String,
+        expected: Type,
+        found: Type,
+        span: Span,
+        first: Option<Span>,
+    },
+    InvalidOperands {
+        op: String,
+        left: Type,
+        right: Type,
+        span: Span,
+    },
+    ExpectedType {
+        expected: String,
+        found: Type,
+        span: Span,
+    },
+    ArityMismatch {
+        function: String,
+        expected: RangeInclusive<usize>,
+        found: usize,
+        span: Span,
+    },
+    /// A feature a backend other than C cannot compile.
+    UnsupportedFeature {
+        feature: &'static str,
+        backend: &'static str,
+        span: Span,
+    },
+    NoCCompiler,
+    CCompilerFailed {
+        compiler: String,
+        stderr: String,
+    },
+    ToolFailed {
+        tool: String,
+        stderr: String,
+    },
+    /// A bytecode file that is corrupted, truncated or from another version.
+    InvalidBytecode {
+        reason: String,
+    },
+    Io(std::io::Error),
+}
+
+impl CompileError {
+    /// Location in the source the error points at, if it has one.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            CompileError::UnknownCharacter { span, .. }
+            | CompileError::InvalidNumber { span, .. }
+            | CompileError::UnterminatedString { span }
+            | CompileError::InvalidEscape { span, .. }
+            | CompileError::UnexpectedToken { span, .. }
+            | CompileError::InvalidStatement { span, .. }
+            | CompileError::UnmatchedBlockEnd { span, .. }
+            | CompileError::DuplicateLabel { span, .. }
+            | CompileError::UndeclaredLabel { span, .. }
+            | CompileError::JumpIntoBlock { span, .. }
+            | CompileError::SubroutineInBlock { span, .. }
+            | CompileError::ReturnWithoutGosub { span }
+            | CompileError::UndefinedProcedure { span, .. }
+            | CompileError::SubWithoutValue { span, .. }
+            | CompileError::DuplicateDefinition { span, .. }
+            | CompileError::UndeclaredArray { span, .. }
+            | CompileError::DimensionMismatch { span, .. }
+            | CompileError::InvalidReturn { span, .. }
+            | CompileError::NestedProcedure { span, .. }
+            | CompileError::GosubInProcedure { span }
+            | CompileError::UseBeforeAssign { span, .. }
+            | CompileError::MismatchedNext { span, .. }
+            | CompileError::MismatchedTypes { span, .. }
+            | CompileError::InvalidOperands { span, .. }
+            | CompileError::ExpectedType { span, .. }
+            | CompileError::ArityMismatch { span, .. }
+            | CompileError::UnsupportedFeature { span, .. } => Some(*span),
+            CompileError::NoCCompiler
+            | CompileError::CCompilerFailed { .. }
+            | CompileError::ToolFailed { .. }
+            | CompileError::InvalidBytecode { .. }
+            | CompileError::Io(_) => None,
+        }
+    }
+
+    /// Stable code identifying the kind of error, see `--explain`.
+    pub fn code(&self) -> Option<&'static str> {
+        Some(match self {
+            CompileError::UnknownCharacter { .. } => "TB0001",
+            CompileError::InvalidNumber { .. } => "TB0002",
+            CompileError::UndeclaredLabel { .. } => "TB0003",
+            CompileError::DuplicateLabel { .. } => "TB0004",
+            CompileError::UseBeforeAssign { .. } => "TB0005",
+            CompileError::UnexpectedToken { .. } => "TB0006",
+            CompileError::InvalidStatement { .. } => "TB0007",
+            CompileError::UnmatchedBlockEnd { .. } => "TB0008",
+            CompileError::CCompilerFailed { .. } => "TB0009",
+            CompileError::JumpIntoBlock { .. } => "TB0010",
+            CompileError::MismatchedTypes { .. } => "TB0011",
+            CompileError::InvalidOperands { .. } => "TB0012",
+            CompileError::ExpectedType { .. } => "TB0013",
+            CompileError::ArityMismatch { .. } => "TB0014",
+            CompileError::UnterminatedString { .. } => "TB0015",
+            CompileError::InvalidEscape { .. } => "TB0016",
+            CompileError::MismatchedNext { .. } => "TB0017",
+            CompileError::SubroutineInBlock { .. } => "TB0018",
+            CompileError::ReturnWithoutGosub { .. } => "TB0019",
+            CompileError::UndefinedProcedure { .. } | CompileError::SubWithoutValue { .. } => {
+                "TB0020"
+            }
+            CompileError::DuplicateDefinition { .. } => "TB0021",
+            CompileError::InvalidReturn { .. } => "TB0022",
+            CompileError::NestedProcedure { .. } => "TB0023",
+            CompileError::GosubInProcedure { .. } => "TB0024",
+            CompileError::UndeclaredArray { .. } => "TB0025",
+            CompileError::DimensionMismatch { .. } => "TB0026",
+            CompileError::UnsupportedFeature { .. } => "TB0027",
+            CompileError::ToolFailed { .. } => "TB0028",
+            CompileError::NoCCompiler
+            | CompileError::InvalidBytecode { .. }
+            | CompileError::Io(_) => return None,
+        })
+    }
+
+    /// Converts the error into a diagnostic that can be rendered against the
+    /// source. Returns `None` for errors that do not point into the source.
+    pub fn to_diagnostic(&self) -> Option<Diagnostic> {
+        let mut diagnostic = Diagnostic::error(self.to_string(), self.span()?);
+        diagnostic.code = self.code();
+
+        Some(match self {
+            CompileError::InvalidStatement { .. } => diagnostic.with_help(
+                "statements start with PRINT, IF, WHILE, FOR, LABEL, GOTO, GOSUB, RETURN, CALL, DIM, LET or INPUT",
+            ),
+            CompileError::DuplicateLabel { first, .. } => diagnostic
+                .with_label(*first, "label first declared here")
+                .with_help("rename one of the labels"),
+            CompileError::JumpIntoBlock { target, .. } => diagnostic
+                .with_label(*target, "label declared inside this IF, WHILE or FOR body")
+                .with_help("move the label out of the block, or jump to the start of the block"),
+            CompileError::SubroutineInBlock { target, .. } => diagnostic
+                .with_label(*target, "label declared inside this IF, WHILE or FOR body")
+                .with_help("subroutines must start at a label outside of any block"),
+            CompileError::ReturnWithoutGosub { .. } => diagnostic
+                .with_help("jump past the subroutines with GOTO at the end of the main program"),
+            CompileError::SubWithoutValue { name, .. } => {
+                diagnostic.with_help(format!("run it with `CALL {name}(...)`"))
+            }
+            CompileError::DuplicateDefinition { first, .. } => diagnostic
+                .with_label(*first, "first defined here")
+                .with_help("rename one of them"),
+            CompileError::InvalidReturn { function: None, .. } => {
+                diagnostic.with_help("only RETURN in a FUNCTION takes a value")
+            }
+            CompileError::InvalidReturn { .. } => diagnostic
+                .with_help("return a value with `RETURN expression`, or make it a SUB"),
+            CompileError::NestedProcedure { .. } => {
+                diagnostic.with_help("define it at the top level of the program")
+            }
+            CompileError::GosubInProcedure { .. } => {
+                diagnostic.with_help("make the subroutine a SUB and call it with CALL")
+            }
+            CompileError::UndeclaredArray { name, .. } => {
+                diagnostic.with_help(format!("declare it first with `DIM {name}(...)`"))
+            }
+            CompileError::UndefinedProcedure {
+                keyword: "FUNCTION",
+                ..
+            } => diagnostic.with_help("arrays must be declared with DIM before they are indexed"),
+            CompileError::DimensionMismatch { dim, .. } => {
+                diagnostic.with_label(*dim, "array declared here")
+            }
+            CompileError::UnsupportedFeature { .. } => {
+                diagnostic.with_help("build with the C backend, the default, instead")
+            }
+            CompileError::UnterminatedString { .. } => {
+                diagnostic.with_help("close the string with `\"` before the end of the line")
+            }
+            CompileError::InvalidEscape { .. } => diagnostic
+                .with_help("valid escapes are `\\\"`, `\\\\`, `\\n`, `\\t` and `\\u{...}`"),
+            CompileError::UseBeforeAssign { variable, .. } => {
+                diagnostic.with_help(format!("assign it first with `LET {variable} = ...`"))
+            }
+            CompileError::MismatchedNext {
+                expected, start, ..
+            } => diagnostic
+                .with_label(*start, format!("loop over `{expected}` starts here"))
+                .with_help(format!("write `NEXT {expected}`, or just `NEXT`")),
+            CompileError::MismatchedTypes {
+                variable,
+                expected,
+                first: Some(first),
+                ..
+            } => {
+                let diagnostic = diagnostic.with_label(
+                    *first,
+                    format!("`{variable}` first assigned here as {expected}"),
+                );
+                match expected {
+                    Type::Int => diagnostic.with_help(format!(
+                        "assign a float such as `0.0` first to make `{variable}` a float"
+                    )),
+                    _ => diagnostic,
+                }
+            }
+            CompileError::MismatchedTypes { .. } => {
+                diagnostic.with_help("variables ending in `$` hold strings")
+            }
+            CompileError::InvalidOperands {
+                op, left, right, ..
+            } if op == "+" && (*left == Type::Str || *right == Type::Str) => {
+                diagnostic.with_help("convert the number with `STR$` to join it to a string")
+            }
+            _ => diagnostic,
+        })
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::UnknownCharacter { character, .. } => {
+                write!(f, "unknown character `{character}`")
+            }
+            CompileError::InvalidNumber { literal, .. } => write!(f, "invalid number `{literal}`"),
+            CompileError::UnterminatedString { .. } => write!(f, "unterminated string"),
+            CompileError::InvalidEscape { escape, .. } => {
+                write!(f, "invalid escape `{escape}` in string")
+            }
+            CompileError::UnexpectedToken {
+                expected, found, ..
+            } => write!(f, "expected {expected}, found {}", describe(found)),
+            CompileError::InvalidStatement { found, .. } => {
+                write!(f, "expected a statement, found {}", describe(found))
+            }
+            CompileError::UnmatchedBlockEnd { found, .. } => {
+                write!(f, "{found:?} without a matching block")
+            }
+            CompileError::DuplicateLabel { label, .. } => {
+                write!(f, "label `{label}` is already declared")
+            }
+            CompileError::UndeclaredLabel { keyword, label, .. } => {
+                write!(f, "attempt to {keyword} to undeclared label `{label}`")
+            }
+            CompileError::JumpIntoBlock { label, .. } => {
+                write!(f, "GOTO jumps into the body of a block to label `{label}`")
+            }
+            CompileError::SubroutineInBlock { label, .. } => {
+                write!(f, "GOSUB to label `{label}` inside the body of a block")
+            }
+            CompileError::ReturnWithoutGosub { .. } => write!(f, "RETURN without GOSUB"),
+            CompileError::UndefinedProcedure { keyword, name, .. } => {
+                write!(f, "no {keyword} named `{name}`")
+            }
+            CompileError::SubWithoutValue { name, .. } => {
+                write!(f, "SUB `{name}` has no value")
+            }
+            CompileError::DuplicateDefinition { name, .. } => {
+                write!(f, "`{name}` is defined more than once")
+            }
+            CompileError::InvalidReturn { function: None, .. } => {
+                write!(f, "RETURN with a value outside of a FUNCTION")
+            }
+            CompileError::InvalidReturn {
+                function: Some(function),
+                ..
+            } => write!(f, "RETURN without a value in FUNCTION `{function}`"),
+            CompileError::NestedProcedure { keyword, .. } => {
+                write!(f, "{keyword} defined inside another statement")
+            }
+            CompileError::GosubInProcedure { .. } => write!(f, "GOSUB inside a SUB or FUNCTION"),
+            CompileError::UndeclaredArray { name, .. } => {
+                write!(f, "array `{name}` indexed before its DIM")
+            }
+            CompileError::DimensionMismatch {
+                name,
+                expected,
+                found,
+                ..
+            } => {
+                let expected = match expected {
+                    1 => "1 dimension".to_string(),
+                    expected => format!("{expected} dimensions"),
+                };
+                let found = match found {
+                    1 => "1 index was".to_string(),
+                    found => format!("{found} indices were"),
+                };
+                write!(f, "`{name}` has {expected} but {found} given")
+            }
+            CompileError::UseBeforeAssign { variable, .. } => {
+                write!(f, "variable `{variable}` referenced before assignment")
+            }
+            CompileError::MismatchedNext {
+                expected, found, ..
+            } => write!(f, "`NEXT {found}` closes the loop over `{expected}`"),
+            CompileError::MismatchedTypes {
+                variable,
+                expected,
+                found,
+                ..
+            } => write!(
+                f,
+                "mismatched types: `{variable}` is {expected} but is assigned {found}"
+            ),
+            CompileError::InvalidOperands {
+                op, left, right, ..
+            } => write!(f, "cannot apply `{op}` to {left} and {right}"),
+            CompileError::ExpectedType {
+                expected, found, ..
+            } => write!(f, "expected {expected}, found {found}"),
+            CompileError::ArityMismatch {
+                function,
+                expected,
+                found,
+                ..
+            } => {
+                let expected = match (*expected.start(), *expected.end()) {
+                    (1, 1) => "1 argument".to_string(),
+                    (start, end) if start == end => format!("{start} arguments"),
+                    (start, end) if start + 1 == end => format!("{start} or {end} arguments"),
+                    (start, end) => format!("{start} to {end} arguments"),
+                };
+                let found = match found {
+                    1 => "1 was".to_string(),
+                    found => format!("{found} were"),
+                };
+                write!(f, "`{function}` takes {expected} but {found} supplied")
+            }
+            CompileError::NoCCompiler => write!(
+                f,
+                "no C compiler found, install cc, gcc or clang or set `CC` / `--cc`"
+            ),
+            CompileError::CCompilerFailed { compiler, stderr } => {
+                write!(f, "C compiler `{compiler}` failed")?;
+                if !stderr.is_empty() {
+                    write!(f, ":\n{stderr}")?;
+                }
+                Ok(())
+            }
+            CompileError::UnsupportedFeature {
+                feature, backend, ..
+            } => write!(f, "{feature} are not supported by the {backend} backend"),
+            CompileError::ToolFailed { tool, stderr } => {
+                write!(f, "`{tool}` failed")?;
+                if !stderr.is_empty() {
+                    write!(f, ":\n{stderr}")?;
+                }
+                Ok(())
+            }
+            CompileError::InvalidBytecode { reason } => {
+                write!(f, "invalid bytecode file: {reason}")
+            }
+            CompileError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CompileError {
+    fn from(err: std::io::Error) -> CompileError {
+        CompileError::Io(err)
+    }
+}
+
+/// Problems that do not stop compilation but are likely mistakes.
+#[derive(Debug)]
+pub enum CompileWarning {
+    UnusedLabel { label: String, span: Span },
+}
+
+impl CompileWarning {
+    pub fn span(&self) -> Span {
+        match self {
+            CompileWarning::UnusedLabel { span, .. } => *span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = Diagnostic::warning(self.to_string(), self.span());
+
+        match self {
+            CompileWarning::UnusedLabel { .. } => {
+                diagnostic.with_help("remove the label if no GOTO needs it")
+            }
+        }
+    }
+}
+
+impl fmt::Display for CompileWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileWarning::UnusedLabel { label, .. } => {
+                write!(f, "label `{label}` is never jumped to")
+            }
+        }
+    }
+}
+
+/// Failure of a whole [`run`](crate::run): either the program did not
+/// compile, or it failed while being interpreted.
+#[derive(Debug)]
+pub enum RunError {
+    Compile(Vec<CompileError>),
+    Runtime(RuntimeError),
+    Io(std::io::Error),
+    Usage(String),
+}
+
+impl RunError {
+    /// Process exit code for the failure: 1 for compile errors, 2 for usage
+    /// errors, 3 for I/O failures and 4 for errors while running the program.
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RunError::Compile(_) => 1,
+            RunError::Usage(_) => 2,
+            RunError::Io(_) | RunError::Runtime(RuntimeError::Io(_)) => 3,
+            RunError::Runtime(_) => 4,
+        }
+    }
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::Compile(errors) => match errors.as_slice() {
+                [err] => write!(f, "{err}"),
+                errors => write!(f, "{} errors while compiling", errors.len()),
+            },
+            RunError::Runtime(err) => write!(f, "{err}"),
+            RunError::Io(err) => write!(f, "{err}"),
+            RunError::Usage(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for RunError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RunError::Compile(_) => None,
+            RunError::Runtime(err) => Some(err),
+            RunError::Io(err) => Some(err),
+            RunError::Usage(_) => None,
+        }
+    }
+}
+
+/// Human readable name of a token for error messages.
+fn describe(token: &Option<Token>) -> String {
+    match token {
+        Some(Token::NEWLINE) => "end of line".to_string(),
+        Some(Token::VARIABLE(name)) => format!("`{name}`"),
+        Some(Token::STRING(value)) => format!("string \"{value}\""),
+        Some(Token::INTEGER(value)) => format!("`{value}`"),
+        Some(Token::FLOAT(value)) => format!("`{value}`"),
+        Some(Token::EQ) => "`=`".to_string(),
+        Some(Token::PLUS) => "`+`".to_string(),
+        Some(Token::MINUS) => "`-`".to_string(),
+        Some(Token::ASTERISK) => "`*`".to_string(),
+        Some(Token::SLASH) => "`/`".to_string(),
+        Some(Token::EQEQ) => "`==`".to_string(),
+        Some(Token::NOTEQ) => "`!=`".to_string(),
+        Some(Token::LT) => "`<`".to_string(),
+        Some(Token::LTEQ) => "`<=`".to_string(),
+        Some(Token::GT) => "`>`".to_string(),
+        Some(Token::GTEQ) => "`>=`".to_string(),
+        Some(Token::LPAREN) => "`(`".to_string(),
+        Some(Token::RPAREN) => "`)`".to_string(),
+        Some(Token::COMMA) => "`,`".to_string(),
+        Some(token) => format!("{token:?}"),
+        None => "end of file".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_codes_tell_failures_apart() {
+        let compile = crate::check("PRINT x\n").err().unwrap();
+        let io = || std::io::Error::from(std::io::ErrorKind::NotFound);
+        let span = Span::default();
+
+        assert_eq!(RunError::Compile(compile).exit_code(), 1);
+        assert_eq!(RunError::Usage("usage".to_string()).exit_code(), 2);
+        assert_eq!(RunError::Io(io()).exit_code(), 3);
+        assert_eq!(RunError::Runtime(RuntimeError::Io(io())).exit_code(), 3);
+        let runtime = RuntimeError::DivisionByZero { span };
+        assert_eq!(RunError::Runtime(runtime).exit_code(), 4);
+    }
+}
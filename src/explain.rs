@@ -0,0 +1,776 @@
+/// Long-form explanations of error codes, shown by `--explain`.
+const EXPLANATIONS: &[(&str, &str)] = &[
+    (
+        "TB0001",
+        r#"A character that cannot start any token was found in the source.
+
+Only letters, digits, the operators `= + - * / < > == != <= >=`, double-quoted
+strings and `#` comments are understood by the lexer.
+
+Erroneous code example:
+
+    LET a = 1 ; 2
+
+Remove the stray character:
+
+    LET a = 1
+"#,
+    ),
+    (
+        "TB0002",
+        r#"A number literal could not be parsed.
+
+Integers must fit into a signed 64-bit integer and floats may contain a single
+decimal point.
+
+Erroneous code example:
+
+    LET a = 1.2.3
+
+Write a valid number instead:
+
+    LET a = 1.23
+"#,
+    ),
+    (
+        "TB0003",
+        r#"A GOTO or GOSUB refers to a label that is never declared.
+
+Every GOTO and GOSUB target must be declared somewhere in the program with
+LABEL.
+
+Erroneous code example:
+
+    GOTO finish
+    PRINT "skipped"
+
+Declare the label you jump to:
+
+    GOTO finish
+    PRINT "skipped"
+    LABEL finish
+"#,
+    ),
+    (
+        "TB0004",
+        r#"A label was declared more than once.
+
+Labels name a single place in the program, so a GOTO to a label declared twice
+would be ambiguous.
+
+Erroneous code example:
+
+    LABEL loop
+    PRINT 1
+    LABEL loop
+
+Give each label a unique name:
+
+    LABEL first
+    PRINT 1
+    LABEL second
+"#,
+    ),
+    (
+        "TB0005",
+        r#"A variable was used before any value was assigned to it.
+
+Variables come into existence through LET or INPUT, and must be assigned
+before they are read.
+
+Erroneous code example:
+
+    PRINT total
+    LET total = 10
+
+Assign the variable first:
+
+    LET total = 10
+    PRINT total
+"#,
+    ),
+    (
+        "TB0006",
+        r#"The parser found a token it did not expect at this point of a statement.
+
+This usually means a keyword is missing or the statement continues past the
+end of the line.
+
+Erroneous code example:
+
+    LET a = 2
+    IF a > 1
+        PRINT a
+    ENDIF
+
+Add the missing keyword:
+
+    LET a = 2
+    IF a > 1 THEN
+        PRINT a
+    ENDIF
+"#,
+    ),
+    (
+        "TB0007",
+        r#"A line does not start with a statement keyword.
+
+Statements start with PRINT, IF, WHILE, FOR, LABEL, GOTO, GOSUB, RETURN, LET or
+INPUT.
+
+Erroneous code example:
+
+    a = 1
+
+Use LET to assign a variable:
+
+    LET a = 1
+"#,
+    ),
+    (
+        "TB0008",
+        r#"ENDIF, ELSE, ELSEIF, ENDWHILE or NEXT was found without a block to close.
+
+Erroneous code example:
+
+    PRINT 1
+    ENDWHILE
+
+Remove the terminator, or open the block it belongs to:
+
+    WHILE 1 > 2 REPEAT
+        PRINT 1
+    ENDWHILE
+"#,
+    ),
+    (
+        "TB0009",
+        r#"The C compiler rejected the code emitted for the program.
+
+`build` hands the emitted C to the system compiler (`cc`, `gcc` or `clang`, or
+the one named by `CC` or `--cc`). Its output is shown below the error.
+
+This usually points to a problem with the C toolchain itself, e.g. missing
+headers, or to a bug in the emitter. Inspect the emitted code with:
+
+    basic_compiler build program.tb --emit=c -o program.c
+
+or keep the intermediate file next to the executable with `--keep-c`.
+"#,
+    ),
+    (
+        "TB0010",
+        r#"A GOTO jumps into the body of an IF, WHILE or FOR from outside of it.
+
+Labels inside a block can only be reached from the same block or from blocks
+nested in it. Jumping into a block would skip its condition, and the variables
+declared in it would never be set up.
+
+Erroneous code example:
+
+    LET a = 1
+    GOTO inside
+    WHILE a < 10 REPEAT
+        LABEL inside
+        LET a = a + 1
+    ENDWHILE
+
+Jump to the start of the block instead:
+
+    LET a = 1
+    GOTO loop
+    LABEL loop
+    WHILE a < 10 REPEAT
+        LET a = a + 1
+    ENDWHILE
+"#,
+    ),
+    (
+        "TB0011",
+        r#"A variable is assigned a value of a different type than it already has.
+
+The first assignment of a variable decides whether it holds integers or
+floats, INPUT into a fresh variable makes it an integer. Integers may later be
+assigned to a float variable, but a float would be truncated when stored in an
+integer variable and is rejected instead.
+
+Erroneous code example:
+
+    LET total = 0
+    LET total = total + 0.5
+
+Make the variable a float from the start:
+
+    LET total = 0.0
+    LET total = total + 0.5
+"#,
+    ),
+    (
+        "TB0012",
+        r#"An operator was applied to values it does not work on.
+
+Arithmetic needs numbers. Strings can only be joined with `+` and compared
+with `==` and `!=`, and a string is never converted to or from a number
+implicitly.
+
+Erroneous code example:
+
+    LET name$ = "Bob"
+    PRINT "Hello " + name$ + 1
+
+Convert the number to a string first:
+
+    LET name$ = "Bob"
+    PRINT "Hello " + name$ + STR$(1)
+"#,
+    ),
+    (
+        "TB0013",
+        r#"A value of the wrong type was used.
+
+Conditions, signs and the numeric arguments of built-in functions need
+numbers, while functions such as LEN and LEFT$ need a string.
+
+Erroneous code example:
+
+    LET name$ = "Bob"
+    IF name$ THEN
+        PRINT LEN(42)
+    ENDIF
+
+Use a value of the expected type:
+
+    LET name$ = "Bob"
+    IF LEN(name$) > 0 THEN
+        PRINT LEN(STR$(42))
+    ENDIF
+"#,
+    ),
+    (
+        "TB0014",
+        r#"A function was called with the wrong number of arguments.
+
+LEN, STR$ and VAL take one argument, LEFT$ takes a string and a length, and
+MID$ takes a string, a 1-based start position and an optional length.
+
+Erroneous code example:
+
+    LET name$ = "Tiny Basic"
+    PRINT LEFT$(name$)
+
+Supply every argument:
+
+    LET name$ = "Tiny Basic"
+    PRINT LEFT$(name$, 4)
+"#,
+    ),
+    (
+        "TB0015",
+        r#"A string literal is missing its closing quote.
+
+Strings cannot span several lines, so every `"` must be closed on the line it
+is opened on. Write `\"` for a quote inside a string.
+
+Erroneous code example:
+
+    PRINT "She said "hi"
+
+Close the string and escape the quotes inside it:
+
+    PRINT "She said \"hi\""
+"#,
+    ),
+    (
+        "TB0016",
+        r#"A string contains a backslash escape that is not understood.
+
+The escapes are `\"` for a quote, `\\` for a backslash, `\n` for a line
+break, `\t` for a tab and `\u{...}` for a Unicode character given by one to
+six hex digits. `\u{0}` is not allowed.
+
+Erroneous code example:
+
+    PRINT "C:\temp"
+
+Escape the backslash:
+
+    PRINT "C:\\temp"
+"#,
+    ),
+    (
+        "TB0017",
+        r#"A NEXT names a different variable than the FOR loop it closes.
+
+NEXT may repeat the loop variable for readability, but it always closes the
+innermost open FOR, so the names must match.
+
+Erroneous code example:
+
+    FOR i = 1 TO 3
+        FOR j = 1 TO 3
+            PRINT i * j
+        NEXT i
+    NEXT j
+
+Close the inner loop first:
+
+    FOR i = 1 TO 3
+        FOR j = 1 TO 3
+            PRINT i * j
+        NEXT j
+    NEXT i
+"#,
+    ),
+    (
+        "TB0018",
+        r#"A GOSUB calls a label declared inside an IF, WHILE or FOR body.
+
+Subroutines start at a label outside of any block, so that RETURN always goes
+back to a point the program can continue from.
+
+Erroneous code example:
+
+    GOSUB greet
+    WHILE 1 == 1 REPEAT
+        LABEL greet
+        PRINT "hello"
+        RETURN
+    ENDWHILE
+
+Move the subroutine out of the block:
+
+    GOSUB greet
+    GOTO done
+    LABEL greet
+    PRINT "hello"
+    RETURN
+    LABEL done
+"#,
+    ),
+    (
+        "TB0019",
+        r#"A RETURN is reached without a GOSUB to return to.
+
+RETURN ends a subroutine entered with GOSUB. This error is reported when the
+program contains no GOSUB at all, or when the main program runs into a RETURN
+before reaching any LABEL or GOTO. Other RETURNs without a GOSUB are caught
+when the program runs.
+
+Erroneous code example:
+
+    GOSUB greet
+    LABEL greet
+    PRINT "hello"
+    RETURN
+
+After the GOSUB returns, the main program runs into the subroutine again. Jump
+past the subroutines at the end of the main program:
+
+    GOSUB greet
+    GOTO done
+    LABEL greet
+    PRINT "hello"
+    RETURN
+    LABEL done
+"#,
+    ),
+    (
+        "TB0020",
+        r#"A call names a SUB or FUNCTION that is not defined, or uses a SUB as a value.
+
+CALL runs a SUB or a FUNCTION, discarding the value of the latter. A call in
+an expression needs a value, so it must name a FUNCTION.
+
+Erroneous code example:
+
+    SUB greet(name$)
+        PRINT "hello " + name$
+    ENDSUB
+    PRINT greet("world")
+
+Call the SUB with CALL:
+
+    CALL greet("world")
+"#,
+    ),
+    (
+        "TB0021",
+        r#"A SUB, FUNCTION, parameter or array name is defined more than once.
+
+SUBs and FUNCTIONs share one set of names, and the parameters of each one must
+have distinct names. An array may only have one DIM in the main program or in
+a procedure, though that DIM may run more than once.
+
+Erroneous code example:
+
+    FUNCTION add(a, a)
+        RETURN a + a
+    ENDFUNCTION
+
+Rename one of them:
+
+    FUNCTION add(a, b)
+        RETURN a + b
+    ENDFUNCTION
+"#,
+    ),
+    (
+        "TB0022",
+        r#"A RETURN does not match the procedure it is in.
+
+RETURN in a FUNCTION must give the value of the call. RETURN in a SUB or in a
+subroutine entered with GOSUB must not have a value.
+
+Erroneous code example:
+
+    FUNCTION square(x)
+        PRINT x * x
+        RETURN
+    ENDFUNCTION
+
+Return the value instead:
+
+    FUNCTION square(x)
+        RETURN x * x
+    ENDFUNCTION
+"#,
+    ),
+    (
+        "TB0023",
+        r#"A SUB or FUNCTION is defined inside another statement or procedure.
+
+Procedures may only be defined at the top level of the program, outside of any
+IF, WHILE or FOR body and of other procedures.
+
+Erroneous code example:
+
+    IF debug == 1 THEN
+        SUB trace(x)
+            PRINT x
+        ENDSUB
+    ENDIF
+
+Move the definition to the top level:
+
+    SUB trace(x)
+        PRINT x
+    ENDSUB
+"#,
+    ),
+    (
+        "TB0024",
+        r#"A GOSUB is used inside a SUB or FUNCTION.
+
+Inside a procedure, RETURN leaves the procedure, so a subroutine entered with
+GOSUB could never return to it.
+
+Erroneous code example:
+
+    SUB report(x)
+        GOSUB show
+        LABEL show
+        PRINT x
+        RETURN
+    ENDSUB
+
+Make the subroutine a SUB of its own:
+
+    SUB show(x)
+        PRINT x
+    ENDSUB
+    SUB report(x)
+        CALL show(x)
+    ENDSUB
+"#,
+    ),
+    (
+        "TB0025",
+        r#"An array is indexed before its DIM.
+
+`name(...)` only indexes an array once a DIM of it appears earlier in the
+source, in the main program or in the same procedure. Assigning to an element
+needs that DIM too.
+
+Erroneous code example:
+
+    LET scores(0) = 10
+    DIM scores(9)
+
+Declare the array first:
+
+    DIM scores(9)
+    LET scores(0) = 10
+"#,
+    ),
+    (
+        "TB0026",
+        r#"An array is indexed with the wrong number of indices.
+
+Every element access gives one index per dimension in the DIM of the array.
+
+Erroneous code example:
+
+    DIM grid(10, 10)
+    PRINT grid(3)
+
+Give an index for each dimension:
+
+    PRINT grid(3, 0)
+"#,
+    ),
+    (
+        "TB0027",
+        r#"The program uses a feature the selected backend cannot compile.
+
+The x86-64 assembly backend (`--backend=asm`) and the WebAssembly backend
+(`--emit=wat`) only handle integers: integer variables and arithmetic,
+comparisons, IF, WHILE, FOR, GOTO, GOSUB, SUB and FUNCTION, PRINT of integers
+and of string literals, and INPUT of integers. The LLVM backend
+(`--backend=llvm`) handles floats as well. String values, built-in functions
+and arrays need the C backend.
+
+Erroneous code example, built with `--backend=asm`:
+
+    LET name$ = "world"
+    PRINT "hello " + name$
+
+Build the program with the C backend, the default, instead:
+
+    basic_compiler build program.tb
+"#,
+    ),
+    (
+        "TB0028",
+        r#"A tool of the assembly or LLVM backend rejected the code emitted for the program.
+
+`build --backend=asm` hands the emitted assembly to `as` and links the object
+file with `ld`, both part of binutils. `build --backend=llvm` hands the emitted
+LLVM IR to `clang`, or to `llc` and then the C compiler to link it. The tool's
+output is shown below the error.
+
+This usually points to a missing or unusual toolchain, e.g. one that does not
+target x86-64 Linux or an LLVM older than 15, which needs `-opaque-pointers`
+to read the emitted IR, or to a bug in the backend. Inspect the emitted code
+with:
+
+    basic_compiler build program.tb --emit=asm -o program.s
+    basic_compiler build program.tb --emit=llvm -o program.ll
+"#,
+    ),
+];
+
+/// Long-form explanation of an error code such as `TB0003`.
+pub fn explain(code: &str) -> Option<&'static str> {
+    EXPLANATIONS
+        .iter()
+        .find(|(known, _)| known.eq_ignore_ascii_case(code))
+        .map(|(_, explanation)| *explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::CompileError;
+    use crate::lexer::Token;
+    use crate::span::Span;
+    use crate::types::Type;
+
+    /// One error of every kind.
+    fn every_error() -> Vec<CompileError> {
+        let span = Span::default();
+        let name = || "a".to_string();
+        let errors = vec![
+            CompileError::UnknownCharacter {
+                character: ';',
+                span,
+            },
+            CompileError::InvalidNumber {
+                literal: "1.2.3".to_string(),
+                span,
+            },
+            CompileError::UnterminatedString { span },
+            CompileError::InvalidEscape {
+                escape: "\\q".to_string(),
+                span,
+            },
+            CompileError::UnexpectedToken {
+                expected: "`)`".to_string(),
+                found: None,
+                span,
+            },
+            CompileError::InvalidStatement { found: None, span },
+            CompileError::UnmatchedBlockEnd {
+                found: Token::ENDIF,
+                span,
+            },
+            CompileError::DuplicateLabel {
+                label: name(),
+                span,
+                first: span,
+            },
+            CompileError::UndeclaredLabel {
+                keyword: "GOTO",
+                label: name(),
+                span,
+            },
+            CompileError::JumpIntoBlock {
+                label: name(),
+                span,
+                target: span,
+            },
+            CompileError::SubroutineInBlock {
+                label: name(),
+                span,
+                target: span,
+            },
+            CompileError::ReturnWithoutGosub { span },
+            CompileError::UndefinedProcedure {
+                keyword: "CALL",
+                name: name(),
+                span,
+            },
+            CompileError::SubWithoutValue { name: name(), span },
+            CompileError::UndeclaredArray { name: name(), span },
+            CompileError::DimensionMismatch {
+                name: name(),
+                expected: 1,
+                found: 2,
+                span,
+                dim: span,
+            },
+            CompileError::DuplicateDefinition {
+                name: name(),
+                span,
+                first: span,
+            },
+            CompileError::InvalidReturn {
+                function: None,
+                span,
+            },
+            CompileError::NestedProcedure {
+                keyword: "SUB",
+                span,
+            },
+            CompileError::GosubInProcedure { span },
+            CompileError::UseBeforeAssign {
+                variable: name(),
+                span,
+            },
+            CompileError::MismatchedNext {
+                expected: name(),
+                found: "b".to_string(),
+                span,
+                start: span,
+            },
+            CompileError::MismatchedTypes {
+                variable: name(),
+                expected: Type::Int,
+                found: Type::Str,
+                span,
+                first: None,
+            },
+            CompileError::InvalidOperands {
+                op: "-".to_string(),
+                left: Type::Str,
+                right: Type::Int,
+                span,
+            },
+            CompileError::ExpectedType {
+                expected: "a number".to_string(),
+                found: Type::Str,
+                span,
+            },
+            CompileError::ArityMismatch {
+                function: "LEN".to_string(),
+                expected: 1..=1,
+                found: 2,
+                span,
+            },
+            CompileError::UnsupportedFeature {
+                feature: "floats",
+                backend: "assembly",
+                span,
+            },
+            CompileError::NoCCompiler,
+            CompileError::CCompilerFailed {
+                compiler: "cc".to_string(),
+                stderr: String::new(),
+            },
+            CompileError::ToolFailed {
+                tool: "llc".to_string(),
+                stderr: String::new(),
+            },
+            CompileError::InvalidBytecode {
+                reason: "truncated".to_string(),
+            },
+            CompileError::Io(std::io::ErrorKind::NotFound.into()),
+        ];
+
+        // Has no catch-all, so that a new kind of error is added above.
+        for error in &errors {
+            match error {
+                CompileError::UnknownCharacter { .. }
+                | CompileError::InvalidNumber { .. }
+                | CompileError::UnterminatedString { .. }
+                | CompileError::InvalidEscape { .. }
+                | CompileError::UnexpectedToken { .. }
+                | CompileError::InvalidStatement { .. }
+                | CompileError::UnmatchedBlockEnd { .. }
+                | CompileError::DuplicateLabel { .. }
+                | CompileError::UndeclaredLabel { .. }
+                | CompileError::JumpIntoBlock { .. }
+                | CompileError::SubroutineInBlock { .. }
+                | CompileError::ReturnWithoutGosub { .. }
+                | CompileError::UndefinedProcedure { .. }
+                | CompileError::SubWithoutValue { .. }
+                | CompileError::UndeclaredArray { .. }
+                | CompileError::DimensionMismatch { .. }
+                | CompileError::DuplicateDefinition { .. }
+                | CompileError::InvalidReturn { .. }
+                | CompileError::NestedProcedure { .. }
+                | CompileError::GosubInProcedure { .. }
+                | CompileError::UseBeforeAssign { .. }
+                | CompileError::MismatchedNext { .. }
+                | CompileError::MismatchedTypes { .. }
+                | CompileError::InvalidOperands { .. }
+                | CompileError::ExpectedType { .. }
+                | CompileError::ArityMismatch { .. }
+                | CompileError::UnsupportedFeature { .. }
+                | CompileError::NoCCompiler
+                | CompileError::CCompilerFailed { .. }
+                | CompileError::ToolFailed { .. }
+                | CompileError::InvalidBytecode { .. }
+                | CompileError::Io(_) => (),
+            }
+        }
+        errors
+    }
+
+    #[test]
+    fn every_error_code_is_explained() {
+        for error in every_error() {
+            if let Some(code) = error.code() {
+                assert!(explain(code).is_some(), "{code} has no explanation");
+            }
+        }
+    }
+
+    #[test]
+    fn every_explanation_is_of_an_error_code() {
+        let codes: Vec<&str> = every_error().iter().filter_map(|e| e.code()).collect();
+        for (i, (code, _)) in EXPLANATIONS.iter().enumerate() {
+            assert!(codes.contains(code), "no error has the code {code}");
+            assert!(
+                EXPLANATIONS[..i].iter().all(|(other, _)| other != code),
+                "{code} is explained twice"
+            );
+        }
+    }
+
+    #[test]
+    fn codes_are_looked_up_ignoring_case() {
+        assert_eq!(explain("tb0003"), explain("TB0003"));
+        assert!(explain("TB0003").is_some());
+        assert!(explain("TB9999").is_none());
+    }
+}
@@ -0,0 +1,128 @@
+use crate::lexer::{Lexer, Token};
+use crate::span::Span;
+
+const INDENT: &str = "    ";
+
+/// Piece of a source line, either a token or a comment.
+struct Item<'a> {
+    token: Option<&'a Token>,
+    span: Span,
+}
+
+/// Reprints a program in the canonical style: one statement per line, blocks
+/// indented by four spaces, single spaces around binary operators and at most
+/// one blank line in a row. Comments are kept where they were.
+///
+/// Only meant for programs that lexed and parsed without errors.
+pub fn format(source: &str, lexer: &Lexer) -> String {
+    let mut items: Vec<Item> = lexer
+        .tokens
+        .iter()
+        .filter(|t| !matches!(t.token, Token::NEWLINE))
+        .map(|t| Item {
+            token: Some(&t.token),
+            span: t.span,
+        })
+        .chain(lexer.comments.iter().map(|span| Item {
+            token: None,
+            span: *span,
+        }))
+        .collect();
+    items.sort_by_key(|item| item.span.start);
+
+    let mut out = String::new();
+    let mut depth: usize = 0;
+    let mut previous_line = None;
+
+    for line in items.chunk_by(|a, b| a.span.line == b.span.line) {
+        let first = line[0].token;
+
+        if matches!(
+            first,
+            Some(
+                Token::ENDIF
+                    | Token::ENDWHILE
+                    | Token::NEXT
+                    | Token::ENDSUB
+                    | Token::ENDFUNCTION
+                    | Token::ELSE
+                    | Token::ELSEIF
+            )
+        ) {
+            depth = depth.saturating_sub(1);
+        }
+
+        if previous_line.is_some_and(|previous| line[0].span.line > previous + 1) {
+            out.push('\n');
+        }
+        previous_line = Some(line[line.len() - 1].span.line);
+
+        out.push_str(&INDENT.repeat(depth));
+        out.push_str(&format_line(source, line));
+        out.push('\n');
+
+        if matches!(
+            first,
+            Some(
+                Token::IF
+                    | Token::WHILE
+                    | Token::FOR
+                    | Token::SUB
+                    | Token::FUNCTION
+                    | Token::ELSE
+                    | Token::ELSEIF
+            )
+        ) {
+            depth += 1;
+        }
+    }
+
+    out
+}
+
+fn format_line(source: &str, line: &[Item]) -> String {
+    let mut out = String::new();
+    let mut previous: Option<&Token> = None;
+    let mut previous_unary = false;
+
+    for (index, item) in line.iter().enumerate() {
+        let text = &source[item.span.start..item.span.end];
+
+        let space = match (previous, item.token) {
+            _ if index == 0 => false,
+            (Some(Token::LPAREN), _) | (_, Some(Token::RPAREN | Token::COMMA)) => false,
+            (
+                Some(
+                    Token::LEN
+                    | Token::LEFT
+                    | Token::MID
+                    | Token::STR
+                    | Token::VAL
+                    | Token::VARIABLE(_),
+                ),
+                Some(Token::LPAREN),
+            ) => false,
+            _ => !previous_unary,
+        };
+        if space {
+            out.push(' ');
+        }
+        out.push_str(text.trim_end());
+
+        // A sign is unary unless it follows something that ends an operand.
+        previous_unary = matches!(item.token, Some(Token::PLUS | Token::MINUS))
+            && !matches!(
+                previous,
+                Some(
+                    Token::VARIABLE(_)
+                        | Token::INTEGER(_)
+                        | Token::FLOAT(_)
+                        | Token::STRING(_)
+                        | Token::RPAREN
+                )
+            );
+        previous = item.token;
+    }
+
+    out
+}
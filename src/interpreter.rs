@@ -0,0 +1,1183 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::rc::Rc;
+
+use crate::ast::{
+    Builtin, Comparison, Condition, ConditionKind, Expression, ExpressionKind, Primary,
+    PrimaryKind, Procedure, Statement, StatementKind, Target, Term, TermKind, Unary, UnaryKind,
+};
+use crate::diagnostics::Diagnostic;
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::types::{ProcedureTypes, Scope, Type};
+
+/// Strings are bytes like in the C runtime, so that they are sliced, measured
+/// and read from input the same way.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    Str(Vec<u8>),
+}
+
+impl Value {
+    pub(crate) fn zero(ty: Type) -> Value {
+        match ty {
+            Type::Int => Value::Int(0),
+            Type::Float => Value::Float(0.0),
+            Type::Str => Value::Str(Vec::new()),
+        }
+    }
+
+    /// Converts the value to the type `like` has, the way C converts on
+    /// assignment.
+    pub(crate) fn cast_like(self, like: &Value) -> Value {
+        match (self, like) {
+            (Value::Int(v), Value::Float(_)) => Value::Float(v as f64),
+            (Value::Float(v), Value::Int(_)) => Value::Int(v as i64),
+            (value, _) => value,
+        }
+    }
+
+    fn as_float(&self) -> f64 {
+        match self {
+            Value::Int(v) => *v as f64,
+            Value::Float(v) => *v,
+            Value::Str(_) => unreachable!("strings are not used as numbers"),
+        }
+    }
+
+    pub(crate) fn is_true(&self) -> bool {
+        match self {
+            Value::Int(v) => *v != 0,
+            Value::Float(v) => *v != 0.0,
+            Value::Str(_) => unreachable!("strings are not used as conditions"),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Int(v) => write!(f, "{v}"),
+            Value::Float(v) => write!(f, "{}", format_g(*v)),
+            Value::Str(v) => write!(f, "{}", String::from_utf8_lossy(v)),
+        }
+    }
+}
+
+/// Prints `value` on a line of its own, strings byte for byte.
+pub(crate) fn print(output: &mut impl Write, value: &Value) -> std::io::Result<()> {
+    match value {
+        Value::Str(s) => {
+            output.write_all(s)?;
+            writeln!(output)
+        }
+        value => writeln!(output, "{value}"),
+    }
+}
+
+/// Formats a float like C's `printf("%g")`: six significant digits without
+/// trailing zeros, in scientific notation for very large or small values.
+fn format_g(v: f64) -> String {
+    if !v.is_finite() {
+        let text = if v.is_nan() { "nan" } else { "inf" };
+        return match v.is_sign_negative() {
+            true => format!("-{text}"),
+            false => text.to_string(),
+        };
+    }
+    if v == 0.0 {
+        return match v.is_sign_negative() {
+            true => "-0".to_string(),
+            false => "0".to_string(),
+        };
+    }
+
+    let scientific = format!("{v:.5e}");
+    let (mantissa, exponent) = scientific.split_once('e').expect("scientific notation");
+    let exponent: i32 = exponent.parse().expect("exponent is an integer");
+
+    let trim = |digits: &str| match digits.contains('.') {
+        true => digits
+            .trim_end_matches('0')
+            .trim_end_matches('.')
+            .to_string(),
+        false => digits.to_string(),
+    };
+
+    match exponent {
+        -4..=5 => trim(&format!("{v:.*}", (5 - exponent) as usize)),
+        _ => {
+            let sign = if exponent < 0 { '-' } else { '+' };
+            format!("{}e{sign}{:02}", trim(mantissa), exponent.abs())
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    DivisionByZero {
+        span: Span,
+    },
+    InvalidInput {
+        input: String,
+        expected: Type,
+        span: Span,
+    },
+    EndOfInput {
+        span: Span,
+    },
+    ReturnWithoutGosub {
+        span: Span,
+    },
+    GosubTooDeep {
+        span: Span,
+    },
+    CallTooDeep {
+        span: Span,
+    },
+    ArrayBeforeDim {
+        array: String,
+        span: Span,
+    },
+    NegativeSize {
+        array: String,
+        size: i64,
+        span: Span,
+    },
+    ArrayTooLarge {
+        array: String,
+        span: Span,
+    },
+    IndexOutOfBounds {
+        array: String,
+        index: i64,
+        /// 1-based dimension the index is for, `None` for 1-D arrays.
+        dimension: Option<usize>,
+        max: usize,
+        span: Span,
+    },
+    /// A program run on the VM used up its budget of instructions.
+    StepLimit {
+        limit: u64,
+        span: Span,
+    },
+    Io(std::io::Error),
+}
+
+impl RuntimeError {
+    pub fn to_diagnostic(&self) -> Option<Diagnostic> {
+        let span = match self {
+            RuntimeError::DivisionByZero { span }
+            | RuntimeError::InvalidInput { span, .. }
+            | RuntimeError::EndOfInput { span }
+            | RuntimeError::ReturnWithoutGosub { span }
+            | RuntimeError::GosubTooDeep { span }
+            | RuntimeError::CallTooDeep { span }
+            | RuntimeError::ArrayBeforeDim { span, .. }
+            | RuntimeError::NegativeSize { span, .. }
+            | RuntimeError::ArrayTooLarge { span, .. }
+            | RuntimeError::IndexOutOfBounds { span, .. }
+            | RuntimeError::StepLimit { span, .. } => *span,
+            RuntimeError::Io(_) => return None,
+        };
+        Some(Diagnostic::error(self.to_string(), span))
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeError::DivisionByZero { .. } => write!(f, "attempt to divide by zero"),
+            RuntimeError::InvalidInput {
+                input, expected, ..
+            } => match expected {
+                Type::Int => write!(f, "expected an integer as input, got `{input}`"),
+                _ => write!(f, "expected a number as input, got `{input}`"),
+            },
+            RuntimeError::EndOfInput { .. } => write!(f, "unexpected end of input"),
+            RuntimeError::ReturnWithoutGosub { .. } => write!(f, "RETURN without GOSUB"),
+            RuntimeError::GosubTooDeep { .. } => {
+                write!(f, "GOSUB nested more than {MAX_GOSUB_DEPTH} levels deep")
+            }
+            RuntimeError::CallTooDeep { .. } => {
+                write!(
+                    f,
+                    "procedure calls nested more than {MAX_CALL_DEPTH} levels deep"
+                )
+            }
+            RuntimeError::ArrayBeforeDim { array, .. } => {
+                write!(f, "array `{array}` used before its DIM")
+            }
+            RuntimeError::NegativeSize { array, size, .. } => {
+                write!(f, "negative size {size} in DIM of `{array}`")
+            }
+            RuntimeError::ArrayTooLarge { array, .. } => write!(f, "array `{array}` is too large"),
+            RuntimeError::IndexOutOfBounds {
+                array,
+                index,
+                dimension,
+                max,
+                ..
+            } => {
+                write!(f, "index {index} out of bounds for ")?;
+                if let Some(dimension) = dimension {
+                    write!(f, "dimension {dimension} of ")?;
+                }
+                write!(f, "`{array}`, which goes from 0 to {max}")
+            }
+            RuntimeError::StepLimit { limit, .. } => {
+                write!(f, "program stopped after running {limit} instructions")
+            }
+            RuntimeError::Io(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            RuntimeError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for RuntimeError {
+    fn from(err: std::io::Error) -> RuntimeError {
+        RuntimeError::Io(err)
+    }
+}
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Number of GOSUBs that may be active at once, the same limit as the C
+/// runtime's return stack.
+pub(crate) const MAX_GOSUB_DEPTH: usize = 1000;
+
+/// Number of SUB and FUNCTION calls that may be active at once, the same
+/// limit as the C runtime.
+pub(crate) const MAX_CALL_DEPTH: usize = 10000;
+
+/// Stack used by a procedure call, which nests several calls of the
+/// interpreter, with room for nested blocks and expressions in its body.
+/// Unoptimized builds need several times more.
+const CALL_STACK_SIZE: usize = if cfg!(debug_assertions) {
+    56 * 1024
+} else {
+    8 * 1024
+};
+
+/// Stack size of the thread programs are interpreted on, enough for
+/// `MAX_CALL_DEPTH` calls, far more than the stack of the main thread.
+const STACK_SIZE: usize = MAX_CALL_DEPTH * CALL_STACK_SIZE;
+
+/// Calls `f` on a thread with a stack deep enough to interpret any program.
+/// When no such thread can be spawned, e.g. as memory is limited, `f` runs on
+/// the current thread and only shallower calls fit.
+pub fn with_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+    with_stack_size(STACK_SIZE, f)
+}
+
+fn with_stack_size<T: Send>(size: usize, f: impl FnOnce() -> T + Send) -> T {
+    let mut f = Some(f);
+    let result = std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(size)
+            .spawn_scoped(scope, || f.take().map(|f| f()));
+        match thread {
+            Ok(thread) => thread
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+            Err(_) => None,
+        }
+    });
+    // The closure is dropped along with the thread it was not run on.
+    result.unwrap_or_else(|| f.take().expect("`f` runs only once")())
+}
+
+/// Elements of a DIM array, stored row by row.
+pub(crate) struct Array {
+    /// Number of elements of each dimension, empty until the DIM runs.
+    sizes: Vec<usize>,
+    pub(crate) elements: Vec<Value>,
+    pub(crate) zero: Value,
+}
+
+impl Array {
+    pub(crate) fn new(ty: Type) -> Array {
+        Array {
+            sizes: Vec::new(),
+            elements: Vec::new(),
+            zero: Value::zero(ty),
+        }
+    }
+
+    /// Creates the array `name` again with the highest index of each
+    /// dimension in `sizes` and every element zero, like the C backend does
+    /// when a DIM runs twice.
+    pub(crate) fn dim(&mut self, name: &str, sizes: &[i64], span: Span) -> Result<()> {
+        let mut dimensions = Vec::new();
+        for &size in sizes {
+            let size = usize::try_from(size).map_err(|_| RuntimeError::NegativeSize {
+                array: name.to_string(),
+                size,
+                span,
+            })?;
+            dimensions.push(size.saturating_add(1));
+        }
+
+        let too_large = || RuntimeError::ArrayTooLarge {
+            array: name.to_string(),
+            span,
+        };
+        let count = dimensions
+            .iter()
+            .try_fold(1usize, |count, size| count.checked_mul(*size))
+            .ok_or_else(too_large)?;
+
+        let mut elements = Vec::new();
+        elements.try_reserve_exact(count).map_err(|_| too_large())?;
+        elements.resize(count, self.zero.clone());
+        self.sizes = dimensions;
+        self.elements = elements;
+        Ok(())
+    }
+
+    /// Position of the element at `indices` in `elements`.
+    pub(crate) fn position(&self, name: &str, indices: &[i64], span: Span) -> Result<usize> {
+        if self.sizes.is_empty() {
+            return Err(RuntimeError::ArrayBeforeDim {
+                array: name.to_string(),
+                span,
+            });
+        }
+
+        let mut position = 0;
+        for (dimension, (&index, size)) in indices.iter().zip(&self.sizes).enumerate() {
+            match usize::try_from(index) {
+                Ok(index) if index < *size => position = position * size + index,
+                _ => {
+                    return Err(RuntimeError::IndexOutOfBounds {
+                        array: name.to_string(),
+                        index,
+                        dimension: (self.sizes.len() > 1).then_some(dimension + 1),
+                        max: size - 1,
+                        span,
+                    })
+                }
+            }
+        }
+        Ok(position)
+    }
+}
+
+/// Arrays of a scope, none of which has run its DIM yet.
+fn arrays(types: &Scope) -> HashMap<String, Array> {
+    types
+        .arrays
+        .iter()
+        .map(|(array, ty)| (array.clone(), Array::new(*ty)))
+        .collect()
+}
+
+/// What to do after a statement has been executed.
+enum Flow {
+    Next,
+    Goto(String),
+    /// Go back to the statement after the innermost GOSUB, or leave the
+    /// running procedure.
+    Return,
+    /// The program ran off its end.
+    End,
+}
+
+/// Executes a checked program directly, without generating C.
+///
+/// Arithmetic follows the C backend: integers wrap, division truncates and
+/// chained comparisons such as `a < b < c` compare the 0/1 result of `a < b`.
+pub struct Interpreter<'a, R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    variables: HashMap<String, Value>,
+    arrays: HashMap<String, Array>,
+    /// Top-level statements, where every GOSUB target is.
+    program: Rc<[&'a Statement]>,
+    /// Number of GOSUBs that have not returned yet.
+    depth: usize,
+    procedures: HashMap<&'a str, (&'a Procedure, &'a ProcedureTypes)>,
+    /// Number of procedure calls that have not returned yet.
+    calls: usize,
+    /// Value of the last RETURN in a FUNCTION.
+    result: Option<Value>,
+}
+
+impl<'a, R: BufRead, W: Write> Interpreter<'a, R, W> {
+    pub fn new(input: R, output: W) -> Interpreter<'a, R, W> {
+        Interpreter {
+            input,
+            output,
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            program: Rc::new([]),
+            depth: 0,
+            procedures: HashMap::new(),
+            calls: 0,
+            result: None,
+        }
+    }
+
+    pub fn run(&mut self, parser: &'a Parser) -> Result<()> {
+        // Like the C backend, every variable exists from the start as zero.
+        self.variables = parser
+            .types
+            .variables
+            .iter()
+            .map(|(var, ty)| (var.clone(), Value::zero(*ty)))
+            .collect();
+        self.arrays = arrays(&parser.types);
+
+        self.program = parser.ast.program.iter().collect();
+        self.procedures = parser
+            .ast
+            .procedures
+            .iter()
+            .map(|procedure| {
+                let types = &parser.procedures[&procedure.name];
+                (procedure.name.as_str(), (procedure, types))
+            })
+            .collect();
+
+        // The parser rejects GOTOs to undeclared labels, so a jump can
+        // always be resolved somewhere in the program.
+        let program = Rc::clone(&self.program);
+        self.exec_block(&program, 0, None)?;
+
+        self.output.flush()?;
+        Ok(())
+    }
+
+    /// Executes `block` from `index` on. When `entering` is set, the statement
+    /// at `index` contains that label and execution resumes right after it.
+    fn exec_block(
+        &mut self,
+        block: &[&Statement],
+        mut index: usize,
+        mut entering: Option<String>,
+    ) -> Result<Flow> {
+        while let Some(statement) = block.get(index) {
+            let flow = match entering.take() {
+                Some(label) => self.enter(statement, &label)?,
+                None => self.exec(statement)?,
+            };
+
+            match flow {
+                Flow::Next => index += 1,
+                Flow::Goto(label) => match block.iter().position(|s| contains_label(s, &label)) {
+                    Some(target) => {
+                        index = target;
+                        entering = Some(label);
+                    }
+                    None => return Ok(Flow::Goto(label)),
+                },
+                Flow::Return | Flow::End => return Ok(flow),
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    /// Resumes execution after `label` somewhere inside `statement`, the way a
+    /// C `goto` into a nested block would.
+    fn enter(&mut self, statement: &Statement, label: &str) -> Result<Flow> {
+        match &statement.kind {
+            StatementKind::Label(_) => Ok(Flow::Next),
+            StatementKind::While(condition, body) => match self.enter_block(body, label)? {
+                Flow::Next => self.exec_while(condition, &body.block()),
+                flow => Ok(flow),
+            },
+            StatementKind::If(_, then, otherwise) => match contains_label(then, label) {
+                true => self.enter_block(then, label),
+                false => {
+                    let otherwise = otherwise.as_ref().expect("label is in one of the branches");
+                    self.enter_block(otherwise, label)
+                }
+            },
+            StatementKind::For { .. } => unreachable!("the resolver rejects jumps into FOR bodies"),
+            _ => unreachable!("statement does not contain label `{label}`"),
+        }
+    }
+
+    fn enter_block(&mut self, body: &Statement, label: &str) -> Result<Flow> {
+        let body = body.block();
+        let target = body
+            .iter()
+            .position(|s| contains_label(s, label))
+            .expect("label is in the block");
+        self.exec_block(&body, target, Some(label.to_string()))
+    }
+
+    fn exec(&mut self, statement: &Statement) -> Result<Flow> {
+        match &statement.kind {
+            StatementKind::Print(expr) => {
+                let value = self.eval_expression(expr)?;
+                print(&mut self.output, &value)?;
+            }
+            StatementKind::PrintStr(string) => writeln!(self.output, "{string}")?,
+            StatementKind::Dim(array, sizes) => self.dim(array, sizes, statement.span)?,
+            StatementKind::Let(target, expr) => {
+                let value = self.eval_expression(expr)?;
+                self.assign_to(target, value, statement.span)?;
+            }
+            StatementKind::Input(target) => {
+                let like = match target {
+                    Target::Variable(var) => self.variables[var].clone(),
+                    Target::Element(array, _) => self.arrays[array].zero.clone(),
+                };
+                let value = self.read_input(&like, statement.span)?;
+                self.assign_to(target, value, statement.span)?;
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                if self.eval_condition(condition)? {
+                    return self.exec_block(&then.block(), 0, None);
+                }
+                if let Some(otherwise) = otherwise {
+                    return self.exec_block(&otherwise.block(), 0, None);
+                }
+            }
+            StatementKind::While(condition, body) => {
+                return self.exec_while(condition, &body.block());
+            }
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => {
+                return self.exec_for(var, from, to, step.as_deref(), &body.block());
+            }
+            StatementKind::Label(_) => (),
+            StatementKind::Goto(label) => return Ok(Flow::Goto(label.clone())),
+            StatementKind::Gosub(label) => return self.gosub(label, statement.span),
+            // GOSUB is only allowed outside of procedures, so a RETURN
+            // during a call leaves the procedure.
+            StatementKind::Return(value) => {
+                if let Some(value) = value {
+                    self.result = Some(self.eval_expression(value)?);
+                }
+                if self.calls == 0 && self.depth == 0 {
+                    Err(RuntimeError::ReturnWithoutGosub {
+                        span: statement.span,
+                    })?
+                }
+                return Ok(Flow::Return);
+            }
+            StatementKind::Call(name, args) => {
+                self.call(name, args, statement.span)?;
+            }
+            StatementKind::Statement(..) | StatementKind::End => {
+                return self.exec_block(&statement.block(), 0, None);
+            }
+        }
+
+        Ok(Flow::Next)
+    }
+
+    fn exec_while(&mut self, condition: &Condition, body: &[&Statement]) -> Result<Flow> {
+        while self.eval_condition(condition)? {
+            match self.exec_block(body, 0, None)? {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Runs the program from the top-level `label` until a RETURN comes back
+    /// here. Running off the end of the program inside the subroutine ends
+    /// the program, as it does in C.
+    fn gosub(&mut self, label: &str, span: Span) -> Result<Flow> {
+        if self.depth == MAX_GOSUB_DEPTH {
+            return Err(RuntimeError::GosubTooDeep { span });
+        }
+
+        let program = Rc::clone(&self.program);
+        let target = program
+            .iter()
+            .position(|s| contains_label(s, label))
+            .expect("GOSUB targets a top-level label");
+
+        self.depth += 1;
+        let flow = self.exec_block(&program, target, Some(label.to_string()))?;
+        self.depth -= 1;
+
+        Ok(match flow {
+            Flow::Return => Flow::Next,
+            Flow::Next | Flow::End => Flow::End,
+            Flow::Goto(label) => unreachable!("label `{label}` is in the program"),
+        })
+    }
+
+    /// Runs a SUB or FUNCTION with variables of its own, giving the value of
+    /// the last RETURN, or zero if a FUNCTION ends without one.
+    fn call(&mut self, name: &str, args: &[Expression], span: Span) -> Result<Value> {
+        let (procedure, types) = self.procedures[name];
+        let args = args
+            .iter()
+            .map(|arg| self.eval_expression(arg))
+            .collect::<Result<Vec<Value>>>()?;
+        if self.calls == MAX_CALL_DEPTH {
+            return Err(RuntimeError::CallTooDeep { span });
+        }
+
+        let mut variables: HashMap<String, Value> = types
+            .scope
+            .variables
+            .iter()
+            .map(|(var, ty)| (var.clone(), Value::zero(*ty)))
+            .collect();
+        for (param, arg) in procedure.params.iter().zip(args) {
+            let variable = variables.get_mut(param).expect("parameter has a type");
+            *variable = arg.cast_like(variable);
+        }
+
+        let caller = std::mem::replace(&mut self.variables, variables);
+        let caller_arrays = std::mem::replace(&mut self.arrays, arrays(&types.scope));
+        self.calls += 1;
+        let flow = self.exec_block(&procedure.body.block(), 0, None);
+        self.calls -= 1;
+        self.variables = caller;
+        self.arrays = caller_arrays;
+        flow?;
+
+        let result = self.result.take();
+        Ok(match types.result {
+            Some(ty) => {
+                let zero = Value::zero(ty);
+                result.map_or(zero.clone(), |value| value.cast_like(&zero))
+            }
+            None => Value::Int(0),
+        })
+    }
+
+    /// Like the emitted C `for`, the limit and step are evaluated once, after
+    /// the loop variable has been set to its start value.
+    fn exec_for(
+        &mut self,
+        var: &str,
+        from: &Expression,
+        to: &Expression,
+        step: Option<&Expression>,
+        body: &[&Statement],
+    ) -> Result<Flow> {
+        let start = self.eval_expression(from)?;
+        self.assign(var, start);
+        let to = self.eval_expression(to)?;
+        let step = match step {
+            Some(step) => self.eval_expression(step)?,
+            None => Value::Int(1),
+        };
+        let op = if step.as_float() >= 0.0 { "<=" } else { ">=" };
+
+        while compare_values(op, self.variables[var].clone(), to.clone()) {
+            match self.exec_block(body, 0, None)? {
+                Flow::Next => (),
+                flow => return Ok(flow),
+            }
+            match for_step(self.variables[var].clone(), step.clone()) {
+                Some(next) => self.assign(var, next),
+                None => break,
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn dim(&mut self, array: &str, sizes: &[Expression], span: Span) -> Result<()> {
+        let sizes = self.eval_ints(sizes)?;
+        let entry = self.arrays.get_mut(array).expect("array has a type");
+        entry.dim(array, &sizes, span)
+    }
+
+    /// Position of the element at `indices` in the elements of `array`.
+    fn element(&mut self, array: &str, indices: &[Expression], span: Span) -> Result<usize> {
+        let indices = self.eval_ints(indices)?;
+        self.arrays[array].position(array, &indices, span)
+    }
+
+    /// Evaluates type checked integer expressions.
+    fn eval_ints(&mut self, expressions: &[Expression]) -> Result<Vec<i64>> {
+        expressions
+            .iter()
+            .map(|expr| match self.eval_expression(expr)? {
+                Value::Int(value) => Ok(value),
+                _ => unreachable!("sizes and indices are type checked"),
+            })
+            .collect()
+    }
+
+    fn assign_to(&mut self, target: &Target, value: Value, span: Span) -> Result<()> {
+        match target {
+            Target::Variable(var) => self.assign(var, value),
+            Target::Element(array, indices) => {
+                let position = self.element(array, indices, span)?;
+                let array = self.arrays.get_mut(array).expect("array has a type");
+                array.elements[position] = value.cast_like(&array.zero);
+            }
+        }
+        Ok(())
+    }
+
+    fn assign(&mut self, var: &str, value: Value) {
+        let variable = self.variables.get_mut(var).expect("variable has a type");
+        *variable = value.cast_like(variable);
+    }
+
+    fn read_input(&mut self, like: &Value, span: Span) -> Result<Value> {
+        self.output.flush()?;
+        read_input(&mut self.input, like, span)
+    }
+
+    /// `AND` and `OR` short-circuit, the right operand is only evaluated
+    /// when it decides the result.
+    fn eval_condition(&mut self, c: &Condition) -> Result<bool> {
+        Ok(match &c.kind {
+            ConditionKind::Comparison(comp) => self.eval_comparison(comp)?.is_true(),
+            ConditionKind::Not(cond) => !self.eval_condition(cond)?,
+            ConditionKind::And(left, right) => {
+                self.eval_condition(left)? && self.eval_condition(right)?
+            }
+            ConditionKind::Or(left, right) => {
+                self.eval_condition(left)? || self.eval_condition(right)?
+            }
+        })
+    }
+
+    fn eval_comparison(&mut self, c: &Comparison) -> Result<Value> {
+        let (first, rest) = c.chain();
+
+        let mut left = self.eval_expression(first)?;
+        for (op, expr) in rest {
+            let right = self.eval_expression(expr)?;
+            left = Value::Int(compare_values(op, left, right) as i64);
+        }
+
+        Ok(left)
+    }
+
+    fn eval_expression(&mut self, e: &Expression) -> Result<Value> {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.eval_term(term),
+            ExpressionKind::Add(expr, term) => {
+                let (left, right) = (self.eval_expression(expr)?, self.eval_term(term)?);
+                Ok(match (left, right) {
+                    (Value::Str(l), Value::Str(r)) => Value::Str([l, r].concat()),
+                    (l, r) => arithmetic(l, r, i64::wrapping_add, |l, r| l + r),
+                })
+            }
+            ExpressionKind::Sub(expr, term) => {
+                let (left, right) = (self.eval_expression(expr)?, self.eval_term(term)?);
+                Ok(arithmetic(left, right, i64::wrapping_sub, |l, r| l - r))
+            }
+        }
+    }
+
+    fn eval_term(&mut self, t: &Term) -> Result<Value> {
+        match &t.kind {
+            TermKind::Unary(u) => self.eval_unary(u),
+            TermKind::Mul(term, unary) => {
+                let (left, right) = (self.eval_term(term)?, self.eval_unary(unary)?);
+                Ok(arithmetic(left, right, i64::wrapping_mul, |l, r| l * r))
+            }
+            TermKind::Div(term, unary) => {
+                let (left, right) = (self.eval_term(term)?, self.eval_unary(unary)?);
+                if let (Value::Int(_), Value::Int(0)) = (&left, &right) {
+                    return Err(RuntimeError::DivisionByZero { span: t.span });
+                }
+                Ok(arithmetic(left, right, i64::wrapping_div, |l, r| l / r))
+            }
+        }
+    }
+
+    fn eval_unary(&mut self, u: &Unary) -> Result<Value> {
+        match &u.kind {
+            UnaryKind::Primary(p) => self.eval_primary(p),
+            UnaryKind::Positive(u) => self.eval_unary(u),
+            UnaryKind::Negative(u) => Ok(match self.eval_unary(u)? {
+                Value::Int(v) => Value::Int(v.wrapping_neg()),
+                Value::Float(v) => Value::Float(-v),
+                Value::Str(_) => unreachable!("strings are not used as numbers"),
+            }),
+        }
+    }
+
+    fn eval_primary(&mut self, p: &Primary) -> Result<Value> {
+        Ok(match &p.kind {
+            PrimaryKind::Integer(v) => Value::Int(*v),
+            PrimaryKind::Float(v) => Value::Float(*v),
+            PrimaryKind::Number(v) => Value::Int(*v as i64),
+            PrimaryKind::String(v) => Value::Str(v.clone().into_bytes()),
+            PrimaryKind::Variable(v) => self.variables[v].clone(),
+            PrimaryKind::Call(builtin, args) => self.eval_call(*builtin, args)?,
+            PrimaryKind::FunctionCall(name, args) => self.call(name, args, p.span)?,
+            PrimaryKind::Index(array, indices) => {
+                let position = self.element(array, indices, p.span)?;
+                self.arrays[array].elements[position].clone()
+            }
+            PrimaryKind::Grouped(expr) => self.eval_expression(expr)?,
+        })
+    }
+
+    fn eval_call(&mut self, builtin: Builtin, args: &[Expression]) -> Result<Value> {
+        let args = args
+            .iter()
+            .map(|arg| self.eval_expression(arg))
+            .collect::<Result<Vec<Value>>>()?;
+
+        Ok(call_builtin(builtin, &args))
+    }
+}
+
+/// Reads a line of input as a value of the same type as `like`.
+pub(crate) fn read_input(input: &mut impl BufRead, like: &Value, span: Span) -> Result<Value> {
+    let mut line = Vec::new();
+    if input.read_until(b'\n', &mut line)? == 0 {
+        return Err(RuntimeError::EndOfInput { span });
+    }
+
+    let text = String::from_utf8_lossy(&line);
+    let input = text.trim();
+    let (value, expected) = match like {
+        Value::Int(_) => (input.parse().ok().map(Value::Int), Type::Int),
+        Value::Float(_) => (input.parse().ok().map(Value::Float), Type::Float),
+        Value::Str(_) => {
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            return Ok(Value::Str(line.to_vec()));
+        }
+    };
+    value.ok_or_else(|| RuntimeError::InvalidInput {
+        input: input.to_string(),
+        expected,
+        span,
+    })
+}
+
+/// Built-in functions behave like the C runtime: strings are indexed by byte
+/// and positions past either end are clamped.
+pub(crate) fn call_builtin(builtin: Builtin, args: &[Value]) -> Value {
+    match (builtin, args) {
+        (Builtin::Len, [Value::Str(s)]) => Value::Int(s.len() as i64),
+        (Builtin::Left, [Value::Str(s), Value::Int(len)]) => Value::Str(substring(s, 0, *len)),
+        (Builtin::Mid, [Value::Str(s), Value::Int(start), rest @ ..]) => {
+            let len = match rest {
+                [Value::Int(len)] => *len,
+                _ => i64::MAX,
+            };
+            Value::Str(substring(s, start.saturating_sub(1), len))
+        }
+        (Builtin::Str, [value]) => Value::Str(value.to_string().into_bytes()),
+        (Builtin::Val, [Value::Str(s)]) => Value::Float(parse_number_prefix(s)),
+        _ => unreachable!("arguments of {} are type checked", builtin.name()),
+    }
+}
+
+/// `len` bytes of `s` from the 0-based `start`, clamped to the string.
+fn substring(s: &[u8], start: i64, len: i64) -> Vec<u8> {
+    let start = start.clamp(0, s.len() as i64) as usize;
+    let len = len.clamp(0, (s.len() - start) as i64) as usize;
+    s[start..start + len].to_vec()
+}
+
+/// Parses the longest prefix of `s` that is a decimal number the way C's
+/// `strtod` does, giving 0 when there is none. Hexadecimal numbers, infinity
+/// and NaN are not decimal, so the C runtime does not read them either.
+fn parse_number_prefix(s: &[u8]) -> f64 {
+    // C's `isspace`, which unlike `u8::is_ascii_whitespace` includes `\v`.
+    let blank = s
+        .iter()
+        .take_while(|byte| matches!(byte, b' ' | b'\t'..=b'\r'))
+        .count();
+    let bytes = &s[blank..];
+    let digits = |mut i: usize| {
+        while bytes.get(i).is_some_and(u8::is_ascii_digit) {
+            i += 1;
+        }
+        i
+    };
+
+    let sign = usize::from(matches!(bytes.first(), Some(b'+' | b'-')));
+    let mut end = digits(sign);
+    let mut has_digits = end > sign;
+    if bytes.get(end) == Some(&b'.') {
+        let fraction = digits(end + 1);
+        has_digits |= fraction > end + 1;
+        end = fraction;
+    }
+    if !has_digits {
+        return 0.0;
+    }
+
+    if matches!(bytes.get(end), Some(b'e' | b'E')) {
+        let start = end + 1 + usize::from(matches!(bytes.get(end + 1), Some(b'+' | b'-')));
+        let exponent = digits(start);
+        if exponent > start {
+            end = exponent;
+        }
+    }
+
+    std::str::from_utf8(&bytes[..end])
+        .ok()
+        .and_then(|number| number.parse().ok())
+        .unwrap_or(0.0)
+}
+
+fn contains_label(statement: &Statement, label: &str) -> bool {
+    match &statement.kind {
+        StatementKind::Label(name) => name == label,
+        StatementKind::If(_, then, otherwise) => {
+            contains_label(then, label)
+                || otherwise
+                    .as_ref()
+                    .is_some_and(|otherwise| contains_label(otherwise, label))
+        }
+        StatementKind::While(_, body) | StatementKind::For { body, .. } => {
+            contains_label(body, label)
+        }
+        StatementKind::Statement(first, rest) => {
+            contains_label(first, label) || contains_label(rest, label)
+        }
+        _ => false,
+    }
+}
+
+/// Compares two values of the same kind, converting an int compared with a
+/// float to a float like C does.
+pub(crate) fn compare_values(op: &str, left: Value, right: Value) -> bool {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => compare(op, l, r),
+        (Value::Str(l), Value::Str(r)) => compare(op, l, r),
+        (l, r) => compare(op, l.as_float(), r.as_float()),
+    }
+}
+
+fn compare<T: PartialOrd>(op: &str, left: T, right: T) -> bool {
+    match op {
+        "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        "<=" => left <= right,
+        ">" => left > right,
+        ">=" => left >= right,
+        _ => unreachable!("unknown comparison operator `{op}`"),
+    }
+}
+
+/// Applies an operator, promoting to float when either side is a float.
+/// Next value of a FOR loop variable, or `None` when an integer step would
+/// wrap around: the loop ends then, as the sum is past any limit.
+pub(crate) fn for_step(var: Value, step: Value) -> Option<Value> {
+    match (var, step) {
+        (Value::Int(var), Value::Int(step)) => var.checked_add(step).map(Value::Int),
+        (var, step) => Some(arithmetic(var, step, i64::wrapping_add, |l, r| l + r)),
+    }
+}
+
+pub(crate) fn arithmetic(
+    left: Value,
+    right: Value,
+    int: fn(i64, i64) -> i64,
+    float: fn(f64, f64) -> f64,
+) -> Value {
+    match (left, right) {
+        (Value::Int(l), Value::Int(r)) => Value::Int(int(l, r)),
+        (l, r) => Value::Float(float(l.as_float(), r.as_float())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(source: &str, input: &str) -> (String, Result<()>) {
+        let parser = crate::check(source).expect("the program is valid");
+        let mut output = Vec::new();
+        let result = Interpreter::new(input.as_bytes(), &mut output).run(&parser);
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    const SUM: &str = "LET total = 0\nLET n = 1\nWHILE n != 0 REPEAT\nINPUT n\n\
+                       LET total = total + n\nENDWHILE\nPRINT total\n";
+
+    #[test]
+    fn integer_arithmetic_wraps() {
+        let (output, result) = run(
+            "LET a = 9223372036854775807\nPRINT a + 1\nPRINT a * 3\nLET b = 0 - a - 1\n\
+             PRINT b / (0 - 1)\nPRINT -b\n",
+            "",
+        );
+        result.unwrap();
+        assert_eq!(
+            output,
+            "-9223372036854775808\n9223372036854775805\n\
+             -9223372036854775808\n-9223372036854775808\n"
+        );
+    }
+
+    #[test]
+    fn division_by_zero_stops_the_program() {
+        let (output, result) = run("PRINT 1\nLET a = 0\nPRINT 7 / a\nPRINT 2\n", "");
+        assert_eq!(output, "1\n");
+        assert!(matches!(
+            result,
+            Err(RuntimeError::DivisionByZero { span }) if span.line == 3
+        ));
+    }
+
+    #[test]
+    fn input_is_a_whole_trimmed_line() {
+        let (output, result) = run(SUM, "5\n  -3\t\n+10\r\n0\n");
+        result.unwrap();
+        assert_eq!(output, "12\n");
+    }
+
+    #[test]
+    fn invalid_input_is_rejected() {
+        for input in ["\n", "+10 apples\n", "99999999999999999999\n", "0x10\n"] {
+            let (_, result) = run(SUM, input);
+            let expected = input.trim();
+            assert!(
+                matches!(&result, Err(RuntimeError::InvalidInput { input, .. }) if input == expected),
+                "{input:?} gives {result:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn end_of_input_is_an_error() {
+        let (_, result) = run(SUM, "5\n");
+        assert!(matches!(
+            result,
+            Err(RuntimeError::EndOfInput { span }) if span.line == 4
+        ));
+    }
+
+    #[test]
+    fn string_input_keeps_the_whole_line() {
+        let (output, result) = run("INPUT a$\nPRINT a$ + \"!\"\n", "  hello there \r\n");
+        result.unwrap();
+        assert_eq!(output, "  hello there !\n");
+    }
+
+    #[test]
+    fn gosub_returns_after_the_call() {
+        let (output, result) = run(
+            "GOSUB twice\nPRINT 3\nGOTO done\nLABEL twice\nPRINT 1\nPRINT 2\nRETURN\nLABEL done\n",
+            "",
+        );
+        result.unwrap();
+        assert_eq!(output, "1\n2\n3\n");
+    }
+
+    #[test]
+    fn return_without_gosub_is_an_error() {
+        let (_, result) = run(
+            "GOTO skip\nLABEL sub\nRETURN\nLABEL skip\nGOSUB sub\nRETURN\n",
+            "",
+        );
+        assert!(matches!(
+            result,
+            Err(RuntimeError::ReturnWithoutGosub { span }) if span.line == 6
+        ));
+    }
+
+    #[test]
+    fn strings_are_sliced_by_byte() {
+        let (output, result) = run(
+            "LET s$ = \"aéb\"\nPRINT LEN(LEFT$(s$, 2))\nPRINT LEFT$(s$, 2) + MID$(s$, 3)\n\
+             INPUT s$\nPRINT LEN(s$)\n",
+            "é\r\n",
+        );
+        result.unwrap();
+        assert_eq!(output, "2\naéb\n2\n");
+    }
+
+    #[test]
+    fn val_reads_only_decimal_numbers() {
+        assert_eq!(parse_number_prefix(b" \x0b-12.5e1x"), -125.0);
+        assert_eq!(parse_number_prefix(b"1e"), 1.0);
+        assert_eq!(parse_number_prefix(b"+.5"), 0.5);
+        assert_eq!(parse_number_prefix(b"0x1A"), 0.0);
+        assert_eq!(parse_number_prefix(b"inf"), 0.0);
+        assert_eq!(parse_number_prefix(b"nan"), 0.0);
+        assert_eq!(parse_number_prefix(b"."), 0.0);
+    }
+
+    #[test]
+    fn for_loops_stop_at_the_end_of_the_integers() {
+        let (output, result) = run(
+            "FOR i = 9223372036854775806 TO 9223372036854775807\nPRINT i\nNEXT\nPRINT i\n\
+             FOR j = 0 - 9223372036854775807 TO 0 - 9223372036854775807 - 1 STEP 0 - 9223372036854775807\nPRINT j\nNEXT\n",
+            "",
+        );
+        result.unwrap();
+        assert_eq!(
+            output,
+            "9223372036854775806\n9223372036854775807\n9223372036854775807\n\
+             -9223372036854775807\n"
+        );
+    }
+
+    #[test]
+    fn conditions_follow_not_and_or_precedence() {
+        let (output, result) = run(
+            "LET a = 1\nIF a == 1 OR a == 2 AND a == 3 THEN\nPRINT 1\nENDIF\n\
+             IF NOT a == 1 AND a == 2 THEN\nPRINT 2\nENDIF\n\
+             IF NOT a == 2 AND a == 1 OR a == 5 THEN\nPRINT 3\nENDIF\n",
+            "",
+        );
+        result.unwrap();
+        assert_eq!(output, "1\n3\n");
+    }
+
+    #[test]
+    fn and_and_or_evaluate_their_right_side_only_when_needed() {
+        let (output, result) = run(
+            "LET x = 0\nIF x != 0 AND 10 / x > 1 THEN\nPRINT 1\nENDIF\n\
+             IF x == 0 OR 10 / x > 1 THEN\nPRINT 2\nENDIF\n",
+            "",
+        );
+        result.unwrap();
+        assert_eq!(output, "2\n");
+
+        let (_, result) = run(
+            "LET x = 0\nIF x == 0 AND 10 / x > 1 THEN\nPRINT 1\nENDIF\n",
+            "",
+        );
+        assert!(matches!(result, Err(RuntimeError::DivisionByZero { .. })));
+    }
+
+    #[test]
+    fn deep_calls_fit_on_the_stack() {
+        let source = "FUNCTION f(n)\nIF n > 0 THEN\nRETURN (1 + f(n - 1)) * 1\nENDIF\n\
+                      RETURN 0\nENDFUNCTION\nPRINT f(9999)\nPRINT f(10000)\n";
+        let (output, result) = with_stack(|| run(source, ""));
+        assert_eq!(output, "9999\n");
+        assert!(matches!(result, Err(RuntimeError::CallTooDeep { .. })));
+    }
+
+    #[test]
+    fn a_thread_that_cannot_be_spawned_runs_on_the_current_one() {
+        let current = std::thread::current().id();
+        assert_eq!(
+            with_stack_size(1 << 62, || std::thread::current().id()),
+            current
+        );
+        assert_ne!(
+            with_stack_size(1 << 20, || std::thread::current().id()),
+            current
+        );
+    }
+}
@@ -1,6 +1,8 @@
-use anyhow::{anyhow, Result};
 use std::{iter::Peekable, str::Chars};
 
+use crate::error::CompileError;
+use crate::span::Span;
+
 #[allow(clippy::upper_case_acronyms)]
 #[derive(Debug, Clone)]
 pub enum Token {
@@ -10,18 +12,44 @@ pub enum Token {
     STRING(String),
     INTEGER(i64),
     FLOAT(f64),
+    /// Stands for an unknown character or an invalid number, whose error is
+    /// already reported, so that the parser does not report it again.
+    INVALID,
     // Keywords
     PRINT,
     LABEL,
     GOTO,
+    GOSUB,
+    RETURN,
+    SUB,
+    ENDSUB,
+    FUNCTION,
+    ENDFUNCTION,
+    CALL,
+    DIM,
     INPUT,
     LET,
     IF,
     THEN,
+    ELSE,
+    ELSEIF,
     ENDIF,
     WHILE,
     REPEAT,
     ENDWHILE,
+    FOR,
+    TO,
+    STEP,
+    NEXT,
+    AND,
+    OR,
+    NOT,
+    // Built-in functions
+    LEN,
+    LEFT,
+    MID,
+    STR,
+    VAL,
     // Operators
     EQ,
     PLUS,
@@ -34,6 +62,9 @@ pub enum Token {
     LTEQ,
     GT,
     GTEQ,
+    LPAREN,
+    RPAREN,
+    COMMA,
 }
 
 impl Token {
@@ -42,14 +73,36 @@ impl Token {
             "PRINT" => Token::PRINT,
             "LABEL" => Token::LABEL,
             "GOTO" => Token::GOTO,
+            "GOSUB" => Token::GOSUB,
+            "RETURN" => Token::RETURN,
+            "SUB" => Token::SUB,
+            "ENDSUB" => Token::ENDSUB,
+            "FUNCTION" => Token::FUNCTION,
+            "ENDFUNCTION" => Token::ENDFUNCTION,
+            "CALL" => Token::CALL,
+            "DIM" => Token::DIM,
             "INPUT" => Token::INPUT,
             "LET" => Token::LET,
             "IF" => Token::IF,
             "THEN" => Token::THEN,
+            "ELSE" => Token::ELSE,
+            "ELSEIF" => Token::ELSEIF,
             "ENDIF" => Token::ENDIF,
             "WHILE" => Token::WHILE,
             "REPEAT" => Token::REPEAT,
             "ENDWHILE" => Token::ENDWHILE,
+            "FOR" => Token::FOR,
+            "TO" => Token::TO,
+            "STEP" => Token::STEP,
+            "NEXT" => Token::NEXT,
+            "AND" => Token::AND,
+            "OR" => Token::OR,
+            "NOT" => Token::NOT,
+            "LEN" => Token::LEN,
+            "LEFT$" => Token::LEFT,
+            "MID$" => Token::MID,
+            "STR$" => Token::STR,
+            "VAL" => Token::VAL,
             "=" => Token::EQ,
             "+" => Token::PLUS,
             "-" => Token::MINUS,
@@ -61,15 +114,71 @@ impl Token {
             "<=" => Token::LTEQ,
             ">=" => Token::GTEQ,
             "!=" => Token::NOTEQ,
+            "(" => Token::LPAREN,
+            ")" => Token::RPAREN,
+            "," => Token::COMMA,
             "\n" => Token::NEWLINE,
             _ => return None,
         })
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Character iterator that keeps track of where in the source it is.
+struct Cursor<'a> {
+    chars: Peekable<Chars<'a>>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(contents: &'a str) -> Cursor<'a> {
+        Cursor {
+            chars: contents.chars().peekable(),
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    /// Zero-width span at the position of the next character.
+    fn location(&self) -> Span {
+        Span::new(self.offset, self.offset, self.line, self.column)
+    }
+}
+
+impl Iterator for Cursor<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.chars.next()?;
+        self.offset += ch.len_utf8();
+        if ch == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(ch)
+    }
+}
+
 #[derive(Default)]
 pub struct Lexer {
-    pub tokens: Vec<Token>,
+    pub tokens: Vec<SpannedToken>,
+    /// Spans of `#` comments, which produce no tokens.
+    pub comments: Vec<Span>,
+    pub errors: Vec<CompileError>,
 }
 
 impl Lexer {
@@ -79,87 +188,119 @@ impl Lexer {
         }
     }
 
-    pub fn parse(&mut self, contents: &str) -> Result<()> {
-        let mut contents = contents.chars().peekable();
+    /// Tokenizes `contents`. Characters that cannot start a token are recorded
+    /// in `errors` and become an `INVALID` token, so the rest of the input is
+    /// still tokenized.
+    pub fn parse(&mut self, contents: &str) {
+        let mut contents = Cursor::new(contents);
 
-        while let Some(cur_char) = contents.next() {
-            match cur_char {
-                '\n' | '\r' => self.tokens.push(Token::NEWLINE),
-                '#' => self.skip_comments(&mut contents),
-                '"' => self.read_string(&mut contents),
-                '=' | '+' | '-' | '*' | '/' | '<' | '>' | '!' => {
+        loop {
+            let start = contents.location();
+            let Some(cur_char) = contents.next() else {
+                break;
+            };
+
+            let token = match cur_char {
+                '\n' | '\r' => Some(Token::NEWLINE),
+                '#' => {
+                    self.skip_comments(&mut contents);
+                    self.comments.push(start.to(contents.location()));
+                    None
+                }
+                '"' => Some(self.read_string(&mut contents, start)),
+                '=' | '+' | '-' | '*' | '/' | '<' | '>' | '!' | '(' | ')' | ',' => {
                     self.read_short_keyword(&mut contents, &cur_char)
                 }
                 _ if cur_char.is_whitespace() => continue,
-                _ if cur_char.is_alphabetic() => self.read_keyword(&mut contents, &cur_char),
-                _ if cur_char.is_numeric() => self.read_number(&mut contents, &cur_char),
-                _ => Err(anyhow!("Unknown token: {}", cur_char))?,
+                _ if cur_char.is_alphabetic() => Some(self.read_keyword(&mut contents, &cur_char)),
+                _ if cur_char.is_numeric() => {
+                    Some(self.read_number(&mut contents, &cur_char, start))
+                }
+                _ => {
+                    self.errors.push(CompileError::UnknownCharacter {
+                        character: cur_char,
+                        span: start.to(contents.location()),
+                    });
+                    Some(Token::INVALID)
+                }
+            };
+
+            if let Some(token) = token {
+                let span = start.to(contents.location());
+                self.tokens.push(SpannedToken { token, span });
             }
         }
-
-        Ok(())
     }
 
-    fn next_is_keyword(&self, contents: &mut Peekable<Chars>) -> bool {
+    fn next_is_keyword(&self, contents: &mut Cursor) -> bool {
         if let Some(next_char) = contents.peek() {
             return Token::from_str(next_char.to_string().as_str()).is_some();
         }
         false
     }
 
-    fn skip_comments(&mut self, contents: &mut Peekable<Chars>) {
-        contents.find(|&ch| ch == '\n');
+    /// Skips to the end of the line, leaving the line break to be tokenized.
+    fn skip_comments(&mut self, contents: &mut Cursor) {
+        while let Some(&ch) = contents.peek() {
+            if ch == '\n' || ch == '\r' {
+                break;
+            }
+            contents.next();
+        }
     }
 
-    fn read_short_keyword(&mut self, contents: &mut Peekable<Chars>, current_character: &char) {
+    fn read_short_keyword(
+        &mut self,
+        contents: &mut Cursor,
+        current_character: &char,
+    ) -> Option<Token> {
         let mut token = String::from(*current_character);
 
         // Handle 2-char tokens
         if let Some(next_char) = contents.peek() {
             token.push(*next_char);
             if let Some(multi_char_token) = Token::from_str(&token) {
-                self.tokens.push(multi_char_token);
                 contents.next(); // Consume the peeked character
-                return;
+                return Some(multi_char_token);
             } else {
                 token.pop();
             }
         }
 
         // Handle 1-char token
-        if let Some(single_char_token) = Token::from_str(&token) {
-            self.tokens.push(single_char_token);
-        }
+        Token::from_str(&token)
     }
 
-    fn read_keyword(&mut self, contents: &mut Peekable<Chars>, current_character: &char) {
+    fn read_keyword(&mut self, contents: &mut Cursor, current_character: &char) -> Token {
         let mut token = String::from(*current_character);
 
         while let Some(&current_character) = contents.peek() {
-            if current_character.is_whitespace() {
+            if current_character.is_whitespace() || self.next_is_keyword(contents) {
                 break;
             }
             token.push(current_character);
             contents.next();
 
-            if self.next_is_keyword(contents) {
+            // `$` marks a string variable and always ends the name.
+            if current_character == '$' {
                 break;
             }
         }
 
-        if let Some(keyword_token) = Token::from_str(token.as_str()) {
-            self.tokens.push(keyword_token);
-        } else {
-            self.tokens.push(Token::VARIABLE(token.clone()));
-        }
+        Token::from_str(token.as_str()).unwrap_or(Token::VARIABLE(token))
     }
 
-    fn read_number(&mut self, contents: &mut Peekable<Chars>, current_character: &char) {
+    fn read_number(
+        &mut self,
+        contents: &mut Cursor,
+        current_character: &char,
+        start: Span,
+    ) -> Token {
         let mut token = String::from(*current_character);
         let mut is_float = false;
 
         while let Some(&current_character) = contents.peek() {
-            if current_character.is_whitespace() {
+            if current_character.is_whitespace() || self.next_is_keyword(contents) {
                 break;
             }
 
@@ -169,29 +310,118 @@ impl Lexer {
 
             contents.next();
             token.push(current_character);
+        }
 
-            if self.next_is_keyword(contents) {
-                break;
+        let parsed = match is_float {
+            true => token.parse::<f64>().ok().map(Token::FLOAT),
+            false => token.parse::<i64>().ok().map(Token::INTEGER),
+        };
+
+        if parsed.is_none() {
+            self.errors.push(CompileError::InvalidNumber {
+                literal: token,
+                span: start.to(contents.location()),
+            });
+        }
+
+        parsed.unwrap_or(Token::INVALID)
+    }
+
+    /// Reads a string literal up to the closing `"`, resolving escapes. A
+    /// string that runs into the end of the line is reported and ended there,
+    /// so the following lines are still tokenized.
+    fn read_string(&mut self, contents: &mut Cursor, start: Span) -> Token {
+        let mut token = String::new();
+
+        loop {
+            let escape = contents.location();
+            match contents.peek() {
+                None | Some('\n' | '\r') => {
+                    self.errors.push(CompileError::UnterminatedString {
+                        span: start.to(contents.location()),
+                    });
+                    break;
+                }
+                Some('"') => {
+                    contents.next();
+                    break;
+                }
+                Some('\\') => {
+                    contents.next();
+                    if let Some(ch) = self.read_escape(contents, escape) {
+                        token.push(ch);
+                    }
+                }
+                Some(&ch) => {
+                    contents.next();
+                    token.push(ch);
+                }
             }
         }
 
-        if is_float {
-            match token.parse::<f64>() {
-                Ok(token) => self.tokens.push(Token::FLOAT(token)),
-                Err(_) => println!("Failed to parse TOKEN::FLOAT {token}"),
+        Token::STRING(token)
+    }
+
+    /// Reads the escape after a `\`, one of `\"`, `\\`, `\n`, `\t` or
+    /// `\u{...}` with up to six hex digits. A `\` at the end of the line is
+    /// left to be reported as an unterminated string.
+    fn read_escape(&mut self, contents: &mut Cursor, start: Span) -> Option<char> {
+        let ch = match contents.peek() {
+            Some(&ch) if ch != '\n' && ch != '\r' => ch,
+            _ => return None,
+        };
+        contents.next();
+        let mut escape = format!("\\{ch}");
+
+        let resolved = match ch {
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            'u' => self.read_unicode(contents, &mut escape),
+            _ => None,
+        };
+
+        if resolved.is_none() {
+            self.invalid_escape(escape, start, contents);
+        }
+        resolved
+    }
+
+    /// Reads the `{...}` of a `\u` escape. NUL is rejected as C strings cannot
+    /// hold it.
+    fn read_unicode(&mut self, contents: &mut Cursor, escape: &mut String) -> Option<char> {
+        if contents.peek() != Some(&'{') {
+            return None;
+        }
+        contents.next();
+        escape.push('{');
+
+        let mut digits = String::new();
+        while let Some(&ch) = contents.peek() {
+            if ch == '"' || ch == '\n' || ch == '\r' {
+                break;
             }
-        } else {
-            match token.parse::<i64>() {
-                Ok(token) => self.tokens.push(Token::INTEGER(token)),
-                Err(_) => println!("Failed to parse TOKEN::INTEGER {token}"),
+            contents.next();
+            escape.push(ch);
+            if ch == '}' {
+                let value = match digits.len() {
+                    1..=6 => u32::from_str_radix(&digits, 16).ok()?,
+                    _ => return None,
+                };
+                return char::from_u32(value).filter(|&ch| ch != '\0');
             }
+            digits.push(ch);
         }
+
+        None
     }
 
-    fn read_string(&mut self, contents: &mut Peekable<Chars>) {
-        let mut token = String::new();
-        token.extend(contents.take_while(|&c| c != '"'));
-        self.tokens.push(Token::STRING(token));
+    fn invalid_escape(&mut self, escape: String, start: Span, contents: &Cursor) {
+        self.errors.push(CompileError::InvalidEscape {
+            escape,
+            span: start.to(contents.location()),
+        });
     }
 }
 
@@ -200,3 +430,98 @@ impl std::fmt::Display for Token {
         write!(f, "TOKEN::{:?}", self)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(source: &str) -> (Vec<Token>, Vec<CompileError>) {
+        let mut lexer = Lexer::new();
+        lexer.parse(source);
+        let tokens = lexer.tokens.into_iter().map(|t| t.token).collect();
+        (tokens, lexer.errors)
+    }
+
+    /// The value of the only string literal in `source`.
+    fn string(source: &str) -> String {
+        let (tokens, _) = lex(source);
+        match &tokens[..] {
+            [Token::STRING(string)] => string.clone(),
+            tokens => panic!("expected a string, found {tokens:?}"),
+        }
+    }
+
+    /// The escapes reported as invalid in `source`.
+    fn invalid_escapes(source: &str) -> Vec<String> {
+        let (_, errors) = lex(source);
+        errors
+            .into_iter()
+            .map(|error| match error {
+                CompileError::InvalidEscape { escape, .. } => escape,
+                error => panic!("expected an invalid escape, found {error:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn escapes_are_resolved() {
+        assert_eq!(string(r#""say \"hi\"""#), "say \"hi\"");
+        assert_eq!(string(r#""a\\b""#), "a\\b");
+        assert_eq!(string(r#""a\nb\tc""#), "a\nb\tc");
+        assert_eq!(string(r#""\u{e9}\u{1F600}\u{00041}""#), "é😀A");
+        assert!(invalid_escapes(r#""\u{10FFFF}""#).is_empty());
+    }
+
+    #[test]
+    fn nul_cannot_be_escaped() {
+        assert_eq!(invalid_escapes(r#""\u{0}""#), ["\\u{0}"]);
+        assert_eq!(invalid_escapes(r#""\u{000000}""#), ["\\u{000000}"]);
+    }
+
+    #[test]
+    fn invalid_escapes_are_reported() {
+        assert_eq!(invalid_escapes(r#""\q""#), ["\\q"]);
+        assert_eq!(invalid_escapes(r#""\u41""#), ["\\u"]);
+        assert_eq!(invalid_escapes(r#""\u{}""#), ["\\u{}"]);
+        assert_eq!(invalid_escapes(r#""\u{1234567}""#), ["\\u{1234567}"]);
+        assert_eq!(invalid_escapes(r#""\u{zz}""#), ["\\u{zz}"]);
+        assert_eq!(invalid_escapes(r#""\u{110000}""#), ["\\u{110000}"]);
+        assert_eq!(invalid_escapes(r#""\u{D800}""#), ["\\u{D800}"]);
+        assert_eq!(invalid_escapes(r#""\u{41""#), ["\\u{41"]);
+        assert_eq!(string(r#""a\qb""#), "ab");
+    }
+
+    #[test]
+    fn unterminated_strings_end_at_the_line() {
+        let (tokens, errors) = lex("PRINT \"abc\nPRINT 1\n");
+        assert!(matches!(
+            &tokens[..],
+            [
+                Token::PRINT,
+                Token::STRING(s),
+                Token::NEWLINE,
+                Token::PRINT,
+                Token::INTEGER(1),
+                Token::NEWLINE,
+            ] if s == "abc"
+        ));
+        assert!(matches!(
+            &errors[..],
+            [CompileError::UnterminatedString { span }] if *span == Span::new(6, 10, 1, 7)
+        ));
+
+        let (_, errors) = lex("PRINT \"abc\\\nPRINT \"def");
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|error| matches!(error, CompileError::UnterminatedString { .. })));
+    }
+
+    #[test]
+    fn the_parser_goes_on_after_an_unterminated_string() {
+        let errors = crate::check("PRINT \"abc\nPRINT x\n").err().unwrap();
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], CompileError::UnterminatedString { .. }));
+        assert_eq!(errors[1].span().unwrap().line, 2);
+    }
+}
@@ -1,52 +1,761 @@
+pub mod asm;
 pub mod ast;
+pub mod backend;
+pub mod bytecode;
+pub mod bytefile;
+pub mod diagnostics;
+pub mod disasm;
 pub mod emitter;
+pub mod error;
+pub mod explain;
+pub mod formatter;
+pub mod interpreter;
 pub mod lexer;
+pub mod llvm;
 pub mod parser;
+pub mod resolver;
+pub mod span;
+pub mod toolchain;
+pub mod types;
+pub mod vm;
+pub mod wat;
+
+use std::io::{BufRead, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
 use emitter::Emitter;
+use interpreter::Interpreter;
 use lexer::Lexer;
 use parser::Parser;
+use toolchain::{TempFile, Toolchain};
+use vm::Vm;
+
+pub use error::{CompileError, CompileWarning, RunError};
+
+pub const USAGE: &str = "\
+Usage: basic_compiler [COMMAND] <FILE> [OPTIONS]
+
+Commands:
+  build    Compile FILE into a native executable (default)
+  run      Execute FILE with the interpreter
+  check    Report errors in FILE without producing any output
+  tokens   Print the tokens of FILE
+  ast      Print the abstract syntax tree of FILE
+  fmt      Print FILE in the canonical style
+  disasm   Print the bytecode FILE compiles to, with its source lines
+
+FILE may be `-` to read the program from stdin. run, check and disasm also
+accept a bytecode file written by `--emit=bytecode`.
+
+Options:
+  -o, --output <PATH>   Where to write the output, `-` for stdout
+                        (default: FILE without extension for build,
+                        out.c for --emit=c, out.s for --emit=asm,
+                        out.ll for --emit=llvm, out.wat for --emit=wat,
+                        FILE.tbc for --emit=bytecode, stdout otherwise)
+      --emit <KIND>     What build produces: tokens, ast, c, asm, llvm, wat,
+                        bytecode or exe (default: exe)
+      --backend <NAME>  How build makes executables: c, with the C compiler,
+                        asm, x86-64 Linux assembly with as and ld, or llvm,
+                        LLVM IR with clang or llc (default: c)
+      --strip           Leave source lines out of bytecode files
+      --cc <PATH>       C compiler to build with (default: $CC, cc, gcc or clang)
+  -O, --opt-level <N>   Optimization level passed to the C compiler, 0-3 (default: 2)
+      --keep-c          Keep the intermediate C, assembly or IR file next to the executable
+      --vm              Run on the bytecode VM instead of the tree-walking interpreter
+      --max-steps <N>   Stop a program run on the VM after N instructions (implies --vm)
+      --color <WHEN>    Colour diagnostics: auto, always or never
+      --explain <CODE>  Print a detailed explanation of an error code
+      --debug           Trace the compiler
+  -h, --help            Print this message
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Build,
+    Run,
+    Check,
+    Tokens,
+    Ast,
+    Fmt,
+    Disasm,
+    Explain,
+    Help,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Emit {
+    Tokens,
+    Ast,
+    C,
+    Asm,
+    Llvm,
+    Wat,
+    Bytecode,
+    Exe,
+}
+
+impl Emit {
+    fn from_str(emit: &str) -> Option<Emit> {
+        Some(match emit {
+            "tokens" => Emit::Tokens,
+            "ast" => Emit::Ast,
+            "c" => Emit::C,
+            "asm" => Emit::Asm,
+            "llvm" => Emit::Llvm,
+            "wat" => Emit::Wat,
+            "bytecode" => Emit::Bytecode,
+            "exe" => Emit::Exe,
+            _ => return None,
+        })
+    }
+}
+
+/// What `build` turns the program into before making an executable of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    C,
+    Asm,
+    Llvm,
+}
 
 pub struct Config {
+    pub command: Command,
     pub file_path: String,
+    pub output: Option<String>,
+    pub emit: Emit,
+    pub backend: Backend,
+    pub cc: Option<String>,
+    pub opt_level: u8,
+    pub keep_c: bool,
+    pub strip: bool,
+    pub vm: bool,
+    pub max_steps: Option<u64>,
     pub debug: bool,
+    pub color: bool,
+    pub explain: Option<String>,
 }
 
 impl Config {
-    pub fn build(args: &[String]) -> Result<Config, &'static str> {
-        let mut min_arg_count = 1;
+    pub fn build(args: &[String]) -> Result<Config, String> {
+        let mut positional = Vec::new();
+        let mut output = None;
+        let mut emit = Emit::Exe;
+        let mut backend = Backend::C;
+        let mut cc = None;
+        let mut opt_level = 2;
+        let mut keep_c = false;
+        let mut strip = false;
+        let mut vm = false;
+        let mut max_steps = None;
+        let mut debug = false;
+        let mut color = std::io::stderr().is_terminal();
+        let mut explain = None;
 
-        let debug = args.contains(&String::from("--debug"));
+        let mut args = args.iter().skip(1);
+        while let Some(arg) = args.next() {
+            // Options take their value as `--name=value`, `-O2` or as the next argument.
+            let (name, inline) = match arg.split_once('=') {
+                Some((name, value)) if name.starts_with("--") => (name, Some(value.to_string())),
+                _ if arg.starts_with("-O") && arg.len() > 2 => ("-O", Some(arg[2..].to_string())),
+                _ => (arg.as_str(), None),
+            };
+            let mut value = || {
+                inline
+                    .clone()
+                    .or_else(|| args.next().cloned())
+                    .ok_or(format!("Missing value for `{name}`"))
+            };
 
-        if debug {
-            min_arg_count += 1;
+            match name {
+                "-h" | "--help" => positional.insert(0, "help".to_string()),
+                "--debug" => debug = true,
+                "--keep-c" => keep_c = true,
+                "--strip" => strip = true,
+                "--vm" => vm = true,
+                "--max-steps" => {
+                    let steps = value()?;
+                    max_steps = Some(
+                        steps
+                            .parse()
+                            .map_err(|_| format!("Invalid step budget `{steps}`"))?,
+                    );
+                    vm = true;
+                }
+                "--cc" => cc = Some(value()?),
+                "-O" | "--opt-level" => {
+                    let level = value()?;
+                    opt_level = match level.parse() {
+                        Ok(level @ 0..=3) => level,
+                        _ => return Err(format!("Invalid optimization level `{level}`")),
+                    };
+                }
+                "-o" | "--output" => output = Some(value()?),
+                "--explain" => explain = Some(value()?),
+                "--emit" => {
+                    let kind = value()?;
+                    emit = Emit::from_str(&kind).ok_or(format!("Unknown emit kind `{kind}`"))?;
+                }
+                "--backend" => match value()?.as_str() {
+                    "c" => backend = Backend::C,
+                    "asm" => backend = Backend::Asm,
+                    "llvm" => backend = Backend::Llvm,
+                    name => return Err(format!("Unknown backend `{name}`")),
+                },
+                "--color" => match value()?.as_str() {
+                    "always" => color = true,
+                    "never" => color = false,
+                    "auto" => (),
+                    when => return Err(format!("Unknown color choice `{when}`")),
+                },
+                "-" => positional.push(arg.clone()),
+                _ if arg.starts_with('-') => return Err(format!("Unknown option `{arg}`")),
+                _ => positional.push(arg.clone()),
+            }
         }
 
-        if args.len() == min_arg_count {
-            return Err("File path was not provided!");
+        let command = match positional.first().map(String::as_str) {
+            Some("help") => Command::Help,
+            _ if explain.is_some() => Command::Explain,
+            Some("build") => Command::Build,
+            Some("run") => Command::Run,
+            Some("check") => Command::Check,
+            Some("tokens") => Command::Tokens,
+            Some("ast") => Command::Ast,
+            Some("fmt") => Command::Fmt,
+            Some("disasm") => Command::Disasm,
+            // A bare path builds, as before subcommands existed.
+            _ => {
+                positional.insert(0, "build".to_string());
+                Command::Build
+            }
+        };
+
+        let file_path = match (command, positional.get(1..).unwrap_or_default()) {
+            (Command::Help | Command::Explain, _) => String::new(),
+            (_, [path]) => path.clone(),
+            (_, []) => return Err("File path was not provided!".to_string()),
+            (_, _) => return Err("Too many arguments!".to_string()),
+        };
+
+        Ok(Config {
+            command,
+            file_path,
+            output,
+            emit,
+            backend,
+            cc,
+            opt_level,
+            keep_c,
+            strip,
+            vm,
+            max_steps,
+            debug,
+            color,
+            explain,
+        })
+    }
+}
+
+pub fn run(config: Config) -> Result<(), RunError> {
+    match config.command {
+        Command::Help => {
+            print!("{USAGE}");
+            return Ok(());
         }
-        if args.len() > min_arg_count + 1 {
-            return Err("Too many arguments!");
+        Command::Explain => {
+            let code = config.explain.unwrap_or_default();
+            return match explain::explain(&code) {
+                Some(explanation) => {
+                    print!("{explanation}");
+                    Ok(())
+                }
+                None => Err(RunError::Usage(format!(
+                    "no extended information for `{code}`"
+                ))),
+            };
         }
+        _ => (),
+    }
 
-        let file_path = args[1].clone();
-        Ok(Config { file_path, debug })
+    let (file_name, contents) = match config.file_path.as_str() {
+        "-" => {
+            let mut contents = Vec::new();
+            let result = std::io::stdin().read_to_end(&mut contents);
+            ("<stdin>", result.map(|_| contents))
+        }
+        path => (path, std::fs::read(path)),
+    };
+    let read_error = |err: std::io::Error| {
+        RunError::Io(std::io::Error::new(
+            err.kind(),
+            format!("couldn't read `{file_name}`: {err}"),
+        ))
+    };
+    let contents = contents.map_err(read_error)?;
+    if bytefile::is_bytecode(&contents) {
+        return run_bytecode_file(&config, file_name, &contents);
     }
+    let contents = String::from_utf8(contents).map_err(|err| {
+        read_error(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            err.utf8_error(),
+        ))
+    })?;
+
+    let emit = match config.command {
+        Command::Tokens => Some(Emit::Tokens),
+        Command::Ast => Some(Emit::Ast),
+        Command::Build => Some(config.emit),
+        _ => None,
+    };
+
+    let result = match emit {
+        Some(Emit::Tokens) => {
+            let mut lexer = Lexer::new();
+            lexer.parse(&contents);
+            let tokens: String = lexer
+                .tokens
+                .iter()
+                .map(|t| format!("{}\t{:?}\n", t.span, t.token))
+                .collect();
+            write_output(config.output.as_deref(), &tokens)
+        }
+        _ => check(&contents)
+            .map_err(RunError::Compile)
+            .and_then(|parser| {
+                eprint!(
+                    "{}",
+                    render_warnings(&parser.warnings, file_name, &contents, config.color)
+                );
+                execute(&config, &contents, parser, emit)
+            }),
+    };
+
+    result.inspect_err(|err| match err {
+        RunError::Compile(errors) => eprint!(
+            "{}",
+            render_errors(errors, file_name, &contents, config.color)
+        ),
+        RunError::Runtime(err) => match err.to_diagnostic() {
+            Some(diagnostic) => {
+                eprint!("{}", diagnostic.render(file_name, &contents, config.color))
+            }
+            None => eprintln!("error: {err}"),
+        },
+        RunError::Io(_) | RunError::Usage(_) => (),
+    })
+}
+
+/// Carries out the command of `config` on a program that passed `check`.
+fn execute(
+    config: &Config,
+    contents: &str,
+    parser: Parser,
+    emit: Option<Emit>,
+) -> Result<(), RunError> {
+    match (config.command, emit) {
+        (Command::Run, _) if config.vm => {
+            Vm::new(std::io::stdin().lock(), std::io::stdout().lock())
+                .with_budget(config.max_steps)
+                .run(&bytecode::compile(&parser))
+                .map_err(RunError::Runtime)
+        }
+        (Command::Run, _) => interpreter::with_stack(|| {
+            Interpreter::new(std::io::stdin().lock(), std::io::stdout().lock()).run(&parser)
+        })
+        .map_err(RunError::Runtime),
+        (Command::Fmt, _) => {
+            let mut lexer = Lexer::new();
+            lexer.parse(contents);
+            write_output(
+                config.output.as_deref(),
+                formatter::format(contents, &lexer),
+            )
+        }
+        (_, Some(Emit::Ast)) => {
+            write_output(config.output.as_deref(), format!("{:#?}\n", parser.ast))
+        }
+        (Command::Disasm, _) => write_output(
+            config.output.as_deref(),
+            disasm::disassemble(&bytecode::compile(&parser), Some(contents)),
+        ),
+        (_, Some(Emit::C)) => write_output(
+            Some(config.output.as_deref().unwrap_or("out.c")),
+            emit_c(parser),
+        ),
+        (_, Some(Emit::Asm)) => write_output(
+            Some(config.output.as_deref().unwrap_or("out.s")),
+            asm::compile(&parser).map_err(|err| RunError::Compile(vec![err]))?,
+        ),
+        (_, Some(Emit::Llvm)) => write_output(
+            Some(config.output.as_deref().unwrap_or("out.ll")),
+            llvm::compile(&parser).map_err(|err| RunError::Compile(vec![err]))?,
+        ),
+        (_, Some(Emit::Wat)) => write_output(
+            Some(config.output.as_deref().unwrap_or("out.wat")),
+            wat::compile(&parser).map_err(|err| RunError::Compile(vec![err]))?,
+        ),
+        (_, Some(Emit::Bytecode)) => {
+            let output = match config.output.as_deref() {
+                Some(output) => PathBuf::from(output),
+                None if config.file_path == "-" => PathBuf::from("out.tbc"),
+                None => Path::new(&config.file_path).with_extension("tbc"),
+            };
+            write_output(
+                output.to_str(),
+                bytefile::write(&bytecode::compile(&parser), !config.strip),
+            )
+        }
+        (_, Some(Emit::Exe)) => build(parser, config),
+        (_, _) => Ok(()),
+    }
+}
+
+/// Writes `content` to the file at `path`, or to stdout for `None` and `-`.
+fn write_output(path: Option<&str>, content: impl AsRef<[u8]>) -> Result<(), RunError> {
+    let result = match path {
+        None | Some("-") => std::io::stdout().write_all(content.as_ref()),
+        Some(path) => std::fs::write(path, content),
+    };
+    result.map_err(|err| {
+        RunError::Io(std::io::Error::new(
+            err.kind(),
+            format!("couldn't write `{}`: {err}", path.unwrap_or("-")),
+        ))
+    })
+}
+
+/// Carries out the command of `config` on a bytecode file, which has no source
+/// to show errors against.
+fn run_bytecode_file(config: &Config, file_name: &str, contents: &[u8]) -> Result<(), RunError> {
+    let program = bytefile::read(contents)
+        .inspect_err(|err| eprintln!("error: {err}"))
+        .map_err(|err| RunError::Compile(vec![err]))?;
+
+    match config.command {
+        Command::Run => Vm::new(std::io::stdin().lock(), std::io::stdout().lock())
+            .with_budget(config.max_steps)
+            .run(&program)
+            .inspect_err(|err| match err.to_diagnostic() {
+                Some(diagnostic) if diagnostic.span.line > 0 => {
+                    eprintln!("error: {err}\n --> {file_name}:{}", diagnostic.span)
+                }
+                _ => eprintln!("error: {err}"),
+            })
+            .map_err(RunError::Runtime),
+        Command::Check => Ok(()),
+        Command::Disasm => write_output(
+            config.output.as_deref(),
+            disasm::disassemble(&program, None),
+        ),
+        _ => Err(RunError::Usage(format!(
+            "`{file_name}` is a bytecode file, which can only be run, checked or disassembled"
+        ))),
+    }
+}
+
+/// Compiles Tiny Basic `contents` into a native executable with the system C
+/// compiler, with the assembler and linker for `--backend=asm` or with LLVM
+/// for `--backend=llvm`. The intermediate file is written to the temporary
+/// directory, or next to the executable when `--keep-c` was given.
+fn build(parser: Parser, config: &Config) -> Result<(), RunError> {
+    let output = match config.output.as_deref() {
+        Some("-") => {
+            return Err(RunError::Usage(
+                "cannot write an executable to stdout, use `--emit=c`".to_string(),
+            ))
+        }
+        Some(output) => PathBuf::from(output),
+        None if config.file_path == "-" => PathBuf::from("a.out"),
+        None => Path::new(&config.file_path).with_extension(""),
+    };
+    if config.file_path != "-" && same_file(&output, Path::new(&config.file_path)) {
+        return Err(RunError::Usage(format!(
+            "executable `{}` would overwrite the source file, use `-o`",
+            output.display()
+        )));
+    }
+
+    let (extension, kind, emit) = match config.backend {
+        Backend::C => ("c", "C", "c"),
+        Backend::Asm => ("s", "assembly", "asm"),
+        Backend::Llvm => ("ll", "LLVM IR", "llvm"),
+    };
+    let temp_file;
+    let source = match config.keep_c {
+        true => output.with_extension(extension),
+        false => {
+            temp_file = TempFile::new(extension).map_err(RunError::Io)?;
+            temp_file.path().to_path_buf()
+        }
+    };
+    if same_file(&source, &output) || same_file(&source, Path::new(&config.file_path)) {
+        return Err(RunError::Usage(format!(
+            "intermediate {kind} file `{}` would overwrite an input or the executable, use `--emit={emit}`",
+            source.display()
+        )));
+    }
+
+    let result = match config.backend {
+        Backend::C => {
+            let code = emit_c(parser);
+            let toolchain = Toolchain::locate(config.cc.as_deref(), config.opt_level)
+                .map_err(|err| RunError::Compile(vec![err]))?;
+
+            write_output(source.to_str(), &code)?;
+            toolchain.compile(&source, &output)
+        }
+        Backend::Asm => {
+            let code = asm::compile(&parser).map_err(|err| RunError::Compile(vec![err]))?;
+
+            write_output(source.to_str(), &code)?;
+            toolchain::assemble(&source, &output)
+        }
+        Backend::Llvm => {
+            let code = llvm::compile(&parser).map_err(|err| RunError::Compile(vec![err]))?;
+            let toolchain = Toolchain::locate(config.cc.as_deref(), config.opt_level)
+                .map_err(|err| RunError::Compile(vec![err]))?;
+
+            write_output(source.to_str(), &code)?;
+            toolchain.compile_ir(&source, &output)
+        }
+    };
+
+    result.map_err(|err| match err {
+        CompileError::Io(err) => RunError::Io(err),
+        err => RunError::Compile(vec![err]),
+    })
 }
 
-pub fn run(config: Config) -> Result<()> {
-    let contents = std::fs::read_to_string(config.file_path)?;
+/// Whether `a` and `b` name the same file, even if spelled differently.
+fn same_file(a: &Path, b: &Path) -> bool {
+    a == b
+        || matches!(
+            (std::fs::canonicalize(a), std::fs::canonicalize(b)),
+            (Ok(a), Ok(b)) if a == b
+        )
+}
 
+/// Lexes and checks Tiny Basic `contents`, returning the parser holding the AST.
+pub fn check(contents: &str) -> Result<Parser, Vec<CompileError>> {
     let mut lexer = Lexer::new();
-    lexer.parse(contents.as_str())?;
+    lexer.parse(contents);
 
     let mut parser = Parser::new();
-    parser.check(&lexer)?;
+    parser.check(lexer)?;
+
+    Ok(parser)
+}
 
-    let mut emitter = Emitter::new("./out.c");
+/// Compiles Tiny Basic `contents` to C source code.
+pub fn compile(contents: &str) -> Result<String, Vec<CompileError>> {
+    Ok(emit_c(check(contents)?))
+}
+
+fn emit_c(parser: Parser) -> String {
+    let mut emitter = Emitter::new("");
     emitter.process(parser);
-    emitter.write_to_file();
 
-    Ok(())
+    emitter.output()
+}
+
+/// Reprints valid Tiny Basic `contents` in the canonical style.
+pub fn format(contents: &str) -> Result<String, Vec<CompileError>> {
+    check(contents)?;
+
+    let mut lexer = Lexer::new();
+    lexer.parse(contents);
+
+    Ok(formatter::format(contents, &lexer))
+}
+
+/// Executes Tiny Basic `contents` with the interpreter, reading INPUT from
+/// `input` and writing PRINT output to `output`.
+pub fn interpret<R: BufRead + Send, W: Write + Send>(
+    contents: &str,
+    input: R,
+    output: W,
+) -> Result<(), RunError> {
+    let parser = check(contents).map_err(RunError::Compile)?;
+
+    interpreter::with_stack(|| Interpreter::new(input, output).run(&parser))
+        .map_err(RunError::Runtime)
+}
+
+/// Executes Tiny Basic `contents` on the bytecode VM like [`interpret`],
+/// stopping it once it has run `max_steps` instructions if given. Returns the
+/// number of instructions run.
+pub fn run_bytecode<R: BufRead, W: Write>(
+    contents: &str,
+    input: R,
+    output: W,
+    max_steps: Option<u64>,
+) -> Result<u64, RunError> {
+    let parser = check(contents).map_err(RunError::Compile)?;
+    let program = bytecode::compile(&parser);
+
+    let mut vm = Vm::new(input, output).with_budget(max_steps);
+    vm.run(&program).map_err(RunError::Runtime)?;
+    Ok(vm.steps())
+}
+
+/// Renders errors rustc-style. Errors pointing into the source are shown
+/// with the offending source line, anything else as a plain message.
+pub fn render_errors(
+    errors: &[CompileError],
+    file_name: &str,
+    source: &str,
+    color: bool,
+) -> String {
+    let mut rendered: Vec<String> = errors
+        .iter()
+        .map(|err| match err.to_diagnostic() {
+            Some(diagnostic) => diagnostic.render(file_name, source, color),
+            None => format!("error: {err}\n"),
+        })
+        .collect();
+
+    match errors.len() {
+        0 => (),
+        1 => rendered.push("error: aborting due to previous error\n".to_string()),
+        n => rendered.push(format!("error: aborting due to {n} previous errors\n")),
+    }
+
+    let mut codes: Vec<&str> = errors.iter().filter_map(CompileError::code).collect();
+    codes.sort();
+    codes.dedup();
+    match codes.as_slice() {
+        [] => (),
+        [code] => rendered.push(format!(
+            "For more information about this error, try `basic_compiler --explain {code}`.\n"
+        )),
+        codes => rendered.push(format!(
+            "Some errors have detailed explanations: {}.\n\
+             For more information about an error, try `basic_compiler --explain {}`.\n",
+            codes.join(", "),
+            codes[0]
+        )),
+    }
+
+    rendered.join("\n")
+}
+
+/// Renders warnings rustc-style, followed by how many were emitted.
+pub fn render_warnings(
+    warnings: &[CompileWarning],
+    file_name: &str,
+    source: &str,
+    color: bool,
+) -> String {
+    let mut rendered: Vec<String> = warnings
+        .iter()
+        .map(|warning| warning.to_diagnostic().render(file_name, source, color))
+        .collect();
+
+    match warnings.len() {
+        0 => return String::new(),
+        1 => rendered.push("warning: 1 warning emitted\n".to_string()),
+        n => rendered.push(format!("warning: {n} warnings emitted\n")),
+    }
+
+    rendered.join("\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(args: &str) -> Result<Config, String> {
+        let args: Vec<String> = std::iter::once("basic_compiler")
+            .chain(args.split_whitespace())
+            .map(String::from)
+            .collect();
+        Config::build(&args)
+    }
+
+    fn config(args: &str) -> Config {
+        build(args).unwrap_or_else(|err| panic!("`{args}` is rejected: {err}"))
+    }
+
+    fn error(args: &str) -> String {
+        match build(args) {
+            Ok(_) => panic!("`{args}` is accepted"),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn options_take_their_value_inline_or_next() {
+        assert_eq!(config("build a.tb -O2").opt_level, 2);
+        assert_eq!(config("build a.tb -O 0").opt_level, 0);
+        assert_eq!(config("build a.tb --opt-level=3").opt_level, 3);
+        assert_eq!(config("build a.tb --opt-level 1").opt_level, 1);
+        assert_eq!(config("build a.tb --emit=c").emit, Emit::C);
+        assert_eq!(config("build a.tb --emit c").emit, Emit::C);
+        assert_eq!(config("build --emit wat a.tb").emit, Emit::Wat);
+        assert_eq!(config("build a.tb -o out").output.as_deref(), Some("out"));
+        assert_eq!(
+            config("build a.tb --output=out").output.as_deref(),
+            Some("out")
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        assert_eq!(error("build a.tb -O4"), "Invalid optimization level `4`");
+        assert_eq!(error("build a.tb -O"), "Missing value for `-O`");
+        assert_eq!(error("build a.tb --emit"), "Missing value for `--emit`");
+        assert_eq!(error("build a.tb --emit=exe2"), "Unknown emit kind `exe2`");
+        assert_eq!(error("build a.tb --backend=go"), "Unknown backend `go`");
+        assert_eq!(
+            error("run a.tb --max-steps=lots"),
+            "Invalid step budget `lots`"
+        );
+        assert_eq!(error("build a.tb --fast"), "Unknown option `--fast`");
+    }
+
+    #[test]
+    fn a_dash_is_a_path() {
+        let run = config("run -");
+        assert_eq!(run.command, Command::Run);
+        assert_eq!(run.file_path, "-");
+        assert_eq!(config("-").file_path, "-");
+    }
+
+    #[test]
+    fn a_step_budget_runs_on_the_vm() {
+        let run = config("run a.tb --max-steps 100");
+        assert!(run.vm);
+        assert_eq!(run.max_steps, Some(100));
+        assert!(!config("run a.tb").vm);
+    }
+
+    #[test]
+    fn help_wins_wherever_it_is() {
+        for args in [
+            "--help",
+            "-h",
+            "build a.tb --help",
+            "run -h a.tb",
+            "a.tb b.tb -h",
+        ] {
+            assert_eq!(config(args).command, Command::Help, "{args}");
+        }
+    }
+
+    #[test]
+    fn a_bare_path_builds() {
+        let config = config("a.tb");
+        assert_eq!(config.command, Command::Build);
+        assert_eq!(config.file_path, "a.tb");
+    }
+
+    #[test]
+    fn one_path_is_needed() {
+        assert_eq!(error("run"), "File path was not provided!");
+        assert_eq!(error(""), "File path was not provided!");
+        assert_eq!(error("run a.tb b.tb"), "Too many arguments!");
+        assert_eq!(error("a.tb b.tb"), "Too many arguments!");
+    }
 }
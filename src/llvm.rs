@@ -0,0 +1,788 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use tracing::instrument;
+
+use crate::ast::{
+    Comparison, Condition, ConditionKind, Expression, ExpressionKind, Primary, PrimaryKind,
+    Statement, StatementKind, Target, Term, TermKind, Unary, UnaryKind,
+};
+use crate::backend::{self, unsupported};
+use crate::error::CompileError;
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::types::{ProcedureTypes, Scope, Type};
+
+/// Declarations of libc and the GOSUB and call depth runtime, appended to
+/// every program.
+const RUNTIME: &str = include_str!("runtime/llvm.ll");
+
+/// Name of the backend in errors.
+const BACKEND: &str = "LLVM IR";
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+/// Compiles a checked program to textual LLVM IR, for `clang` or `llc`.
+///
+/// Every variable gets an `alloca` in the entry block of its function and
+/// every IF, WHILE and FOR body and every label a basic block of its own,
+/// leaving it to LLVM to promote them to registers. Integers and floats are
+/// supported, but strings only as literals printed by PRINT: programs using
+/// string values, built-in functions or arrays are rejected.
+#[instrument(skip_all)]
+pub fn compile(parser: &Parser) -> Result<String> {
+    let mut compiler = Compiler {
+        procedures: &parser.procedures,
+        types: &parser.types,
+        allocas: String::new(),
+        code: String::new(),
+        terminated: false,
+        result: None,
+        in_procedure: false,
+        temporaries: 0,
+        blocks: 0,
+        gosubs: 0,
+        loop_variables: 0,
+        strings: HashMap::new(),
+        constants: String::new(),
+    };
+
+    let mut out = String::from("; Generated by basic_compiler\n\n");
+    out += &compiler.function(
+        "define i32 @main()",
+        &[],
+        parser.ast.program.iter().collect(),
+    )?;
+
+    for procedure in &parser.ast.procedures {
+        let types = &parser.procedures[&procedure.name];
+        let params: Vec<String> = procedure
+            .params
+            .iter()
+            .zip(&types.params)
+            .map(|(param, ty)| {
+                Ok(format!(
+                    "{} {}",
+                    llvm_type(*ty, procedure.span)?,
+                    param_name(param)
+                ))
+            })
+            .collect::<Result<_>>()?;
+        let result = match types.result {
+            Some(ty) => llvm_type(ty, procedure.span)?,
+            None => "void",
+        };
+        let signature = format!(
+            "define internal {result} {}({})",
+            fn_name(&procedure.name),
+            params.join(", ")
+        );
+
+        compiler.types = &types.scope;
+        compiler.result = types.result;
+        compiler.in_procedure = true;
+        out += "\n";
+        out += &compiler.function(&signature, &procedure.params, vec![procedure.body.as_ref()])?;
+    }
+
+    if !compiler.constants.is_empty() {
+        out += "\n";
+        out += &compiler.constants;
+    }
+    out += "\n";
+    out += RUNTIME;
+    Ok(out)
+}
+
+struct Compiler<'a> {
+    procedures: &'a HashMap<String, ProcedureTypes>,
+    /// Types of the function being compiled.
+    types: &'a Scope,
+    /// `alloca`s of the function being compiled, for its entry block.
+    allocas: String,
+    code: String,
+    /// Whether the current block ends with a terminator, so that the next
+    /// instruction starts a new one.
+    terminated: bool,
+    /// Result type of the FUNCTION being compiled.
+    result: Option<Type>,
+    in_procedure: bool,
+    temporaries: usize,
+    blocks: usize,
+    /// Number of GOSUB statements, each returns to its own block.
+    gosubs: usize,
+    loop_variables: usize,
+    /// Global of every string literal and error message.
+    strings: HashMap<String, String>,
+    constants: String,
+}
+
+impl<'a> Compiler<'a> {
+    /// Compiles the body of a function. Its variables start out as zero,
+    /// but for the parameters, which start out as the arguments.
+    fn function(
+        &mut self,
+        signature: &str,
+        params: &[String],
+        statements: Vec<&'a Statement>,
+    ) -> Result<String> {
+        self.allocas.clear();
+        self.code.clear();
+        self.terminated = false;
+        self.temporaries = 0;
+        self.blocks = 0;
+        self.gosubs = 0;
+
+        let mut variables: Vec<(&String, &Type)> = self.types.variables.iter().collect();
+        variables.sort_by_key(|(var, _)| *var);
+        for (var, ty) in variables {
+            // String variables are rejected where they are used.
+            let llvm = llvm_type(*ty, Span::default()).unwrap_or("i64");
+            let initial = if params.contains(var) {
+                param_name(var)
+            } else {
+                zero(*ty)
+            };
+            let name = var_name(var);
+            let _ = writeln!(self.allocas, "  {name} = alloca {llvm}");
+            let _ = writeln!(self.allocas, "  store {llvm} {initial}, ptr {name}");
+        }
+        if self.in_procedure {
+            self.emit("call void @tb_call()");
+        }
+
+        for statement in statements {
+            self.statement(statement)?;
+        }
+        match self.result {
+            _ if !self.in_procedure => self.terminate("ret i32 0"),
+            Some(ty) => self.ret(Some(&zero(ty))),
+            None => self.ret(None),
+        }
+        self.gen_return_dispatch();
+
+        Ok(format!(
+            "{signature} {{\nentry:\n{}{}}}\n",
+            self.allocas, self.code
+        ))
+    }
+
+    /// Every RETURN of the main program jumps here, to go back to the block
+    /// after the GOSUB that called the subroutine.
+    fn gen_return_dispatch(&mut self) {
+        if self.gosubs == 0 {
+            return;
+        }
+
+        self.start("gosub.dispatch");
+        let site = self.value("call i32 @tb_return()");
+        let cases: Vec<String> = (0..self.gosubs)
+            .map(|site| format!("i32 {site}, label %gosub.return.{site}"))
+            .collect();
+        self.terminate(&format!(
+            "switch i32 {site}, label %gosub.invalid [{}]",
+            cases.join(" ")
+        ));
+        self.start("gosub.invalid");
+        self.terminate("unreachable");
+    }
+
+    fn emit(&mut self, instruction: &str) {
+        if self.terminated {
+            let block = self.new_block("dead");
+            self.start(&block);
+        }
+        let _ = writeln!(self.code, "  {instruction}");
+    }
+
+    /// Emits an instruction giving a value, returning its name.
+    fn value(&mut self, instruction: &str) -> String {
+        self.temporaries += 1;
+        let name = format!("%t{}", self.temporaries);
+        self.emit(&format!("{name} = {instruction}"));
+        name
+    }
+
+    fn terminate(&mut self, instruction: &str) {
+        self.emit(instruction);
+        self.terminated = true;
+    }
+
+    fn new_block(&mut self, kind: &str) -> String {
+        self.blocks += 1;
+        format!("{kind}.{}", self.blocks)
+    }
+
+    /// Starts a block, falling through into it from the current one.
+    fn start(&mut self, block: &str) {
+        if !self.terminated {
+            self.emit(&format!("br label %{block}"));
+        }
+        let _ = writeln!(self.code, "{block}:");
+        self.terminated = false;
+    }
+
+    fn ret(&mut self, value: Option<&str>) {
+        self.emit("call void @tb_call_done()");
+        match (self.result, value) {
+            (Some(ty), Some(value)) => {
+                let ty = llvm_type(ty, Span::default()).unwrap_or("i64");
+                self.terminate(&format!("ret {ty} {value}"));
+            }
+            _ => self.terminate("ret void"),
+        }
+    }
+
+    /// Global holding a string, added the first time. Error messages and
+    /// PRINTed literals are the only strings.
+    fn string(&mut self, s: &str) -> String {
+        if let Some(name) = self.strings.get(s) {
+            return name.clone();
+        }
+        let name = format!("@.str.{}", self.strings.len());
+        let _ = writeln!(
+            self.constants,
+            "{name} = private unnamed_addr constant [{} x i8] c{}",
+            s.len() + 1,
+            llvm_string(s)
+        );
+        self.strings.insert(s.to_string(), name.clone());
+        name
+    }
+
+    fn variable(&self, var: &str, span: Span) -> Result<(String, Type)> {
+        let ty = self.types.variables[var];
+        llvm_type(ty, span)?;
+        Ok((var_name(var), ty))
+    }
+
+    fn statement(&mut self, s: &'a Statement) -> Result<()> {
+        match &s.kind {
+            StatementKind::Print(expr) => {
+                let (value, ty) = self.expression(expr)?;
+                let (format, llvm) = match ty {
+                    Type::Float => ("@tb.print.float", "double"),
+                    _ => ("@tb.print.int", "i64"),
+                };
+                self.value(&format!(
+                    "call i32 (ptr, ...) @printf(ptr {format}, {llvm} {value})"
+                ));
+            }
+            StatementKind::PrintStr(string) => {
+                let string = self.string(&format!("{string}\n"));
+                self.value(&format!(
+                    "call i32 (ptr, ...) @printf(ptr @tb.print.str, ptr {string})"
+                ));
+            }
+            StatementKind::Dim(..) => return Err(unsupported(BACKEND, "arrays", s.span)),
+            StatementKind::Let(target, expr) => {
+                let value = self.expression(expr)?;
+                let (var, ty) = self.target(target, s.span)?;
+                let value = self.convert(value, ty);
+                self.store(ty, &value, &var);
+            }
+            // Input is read a line at a time and checked like the
+            // interpreter does.
+            StatementKind::Input(target) => {
+                let (var, ty) = self.target(target, s.span)?;
+                let function = match ty {
+                    Type::Float => "double @tb_input_float",
+                    _ => "i64 @tb_input_int",
+                };
+                let value = self.value(&format!("call {function}(i32 {})", s.span.line));
+                self.store(ty, &value, &var);
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                let (then_block, end) = (self.new_block("then"), self.new_block("endif"));
+                let else_block = match otherwise {
+                    Some(_) => self.new_block("else"),
+                    None => end.clone(),
+                };
+                self.condition(condition, &then_block, &else_block)?;
+                self.start(&then_block);
+                self.statement(then)?;
+                self.terminate(&format!("br label %{end}"));
+                if let Some(otherwise) = otherwise {
+                    self.start(&else_block);
+                    self.statement(otherwise)?;
+                    self.terminate(&format!("br label %{end}"));
+                }
+                self.start(&end);
+            }
+            StatementKind::While(condition, body) => {
+                let test = self.new_block("while");
+                let (body_block, end) = (self.new_block("do"), self.new_block("endwhile"));
+                self.start(&test);
+                self.condition(condition, &body_block, &end)?;
+                self.start(&body_block);
+                self.statement(body)?;
+                self.terminate(&format!("br label %{test}"));
+                self.start(&end);
+            }
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => self.for_loop(var, from, to, step.as_deref(), body, s.span)?,
+            StatementKind::Label(label) => self.start(&format!("label.{label}")),
+            StatementKind::Goto(label) => self.terminate(&format!("br label %label.{label}")),
+            StatementKind::Gosub(label) => {
+                let site = self.gosubs;
+                self.gosubs += 1;
+                self.emit(&format!("call void @tb_gosub(i32 {site})"));
+                self.terminate(&format!("br label %label.{label}"));
+                self.start(&format!("gosub.return.{site}"));
+            }
+            StatementKind::Return(None) if !self.in_procedure => {
+                self.terminate("br label %gosub.dispatch");
+            }
+            StatementKind::Return(None) => self.ret(None),
+            StatementKind::Return(Some(expr)) => {
+                let value = self.expression(expr)?;
+                let result = self.result.unwrap_or(Type::Int);
+                let value = self.convert(value, result);
+                self.ret(Some(&value));
+            }
+            StatementKind::Call(name, args) => {
+                self.call(name, args, s.span)?;
+            }
+            StatementKind::Statement(first, rest) => {
+                self.statement(first)?;
+                self.statement(rest)?;
+            }
+            StatementKind::End => (),
+        }
+        Ok(())
+    }
+
+    fn target(&self, target: &Target, span: Span) -> Result<(String, Type)> {
+        match target {
+            Target::Variable(var) => self.variable(var, span),
+            Target::Element(..) => Err(unsupported(BACKEND, "arrays", span)),
+        }
+    }
+
+    fn store(&mut self, ty: Type, value: &str, var: &str) {
+        let ty = llvm_type(ty, Span::default()).unwrap_or("i64");
+        self.emit(&format!("store {ty} {value}, ptr {var}"));
+    }
+
+    fn load(&mut self, ty: Type, var: &str) -> String {
+        let ty = llvm_type(ty, Span::default()).unwrap_or("i64");
+        self.value(&format!("load {ty}, ptr {var}"))
+    }
+
+    /// Hidden variable holding the limit or step of a FOR loop, evaluated
+    /// once before the loop starts.
+    fn loop_variable(&mut self, part: &str, ty: Type) -> String {
+        self.loop_variables += 1;
+        let name = format!("%for.{part}.{}", self.loop_variables);
+        let llvm = llvm_type(ty, Span::default()).unwrap_or("i64");
+        let _ = writeln!(self.allocas, "  {name} = alloca {llvm}");
+        name
+    }
+
+    /// Runs the same tests as the C backend, see [`crate::backend`].
+    fn for_loop(
+        &mut self,
+        var: &str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+        body: &'a Statement,
+        span: Span,
+    ) -> Result<()> {
+        let (var, ty) = self.variable(var, span)?;
+        let value = self.expression(from)?;
+        let value = self.convert(value, ty);
+        self.store(ty, &value, &var);
+
+        let (value, limit_type) = self.expression(to)?;
+        let limit = self.loop_variable("to", limit_type);
+        self.store(limit_type, &value, &limit);
+        let step = match step {
+            Some(step) => {
+                let (value, step_type) = self.expression(step)?;
+                let name = self.loop_variable("step", step_type);
+                self.store(step_type, &value, &name);
+                Some((name, step_type))
+            }
+            None => None,
+        };
+
+        let test = self.new_block("for");
+        let (body_block, end) = (self.new_block("loop"), self.new_block("next"));
+        self.start(&test);
+        let current = self.load(ty, &var);
+        let limit = (self.load(limit_type, &limit), limit_type);
+        let condition = match &step {
+            Some((step, step_type)) => {
+                let step = self.load(*step_type, step);
+                let up = self.compare(">=", (step, *step_type), (zero(*step_type), *step_type));
+                let below = self.compare("<=", (current.clone(), ty), limit.clone());
+                let above = self.compare(">=", (current, ty), limit);
+                self.value(&format!("select i1 {up}, i1 {below}, i1 {above}"))
+            }
+            None => self.compare("<=", (current, ty), limit),
+        };
+        self.terminate(&format!(
+            "br i1 {condition}, label %{body_block}, label %{end}"
+        ));
+
+        self.start(&body_block);
+        self.statement(body)?;
+        let current = (self.load(ty, &var), ty);
+        let step = match &step {
+            Some((step, step_type)) => (self.load(*step_type, step), *step_type),
+            None => ("1".to_string(), Type::Int),
+        };
+        if current.1 == Type::Int && step.1 == Type::Int {
+            // A step that overflows is past the limit.
+            let sum = self.value(&format!(
+                "call {{i64, i1}} @llvm.sadd.with.overflow.i64(i64 {}, i64 {})",
+                current.0, step.0
+            ));
+            let overflow = self.value(&format!("extractvalue {{i64, i1}} {sum}, 1"));
+            let next = self.value(&format!("extractvalue {{i64, i1}} {sum}, 0"));
+            let store = self.new_block("step");
+            self.terminate(&format!("br i1 {overflow}, label %{end}, label %{store}"));
+            self.start(&store);
+            self.store(ty, &next, &var);
+        } else {
+            let next = self.arithmetic("add", current, step);
+            let next = self.convert(next, ty);
+            self.store(ty, &next, &var);
+        }
+        self.terminate(&format!("br label %{test}"));
+        self.start(&end);
+        Ok(())
+    }
+
+    /// Branches to `then` if the condition holds and to `otherwise` if not.
+    /// AND and OR only evaluate their right side when it decides the result.
+    fn condition(&mut self, c: &'a Condition, then: &str, otherwise: &str) -> Result<()> {
+        match &c.kind {
+            ConditionKind::Comparison(comparison) => {
+                let holds = self.comparison(comparison)?;
+                self.terminate(&format!("br i1 {holds}, label %{then}, label %{otherwise}"));
+            }
+            ConditionKind::Not(condition) => self.condition(condition, otherwise, then)?,
+            ConditionKind::And(left, right) => {
+                let next = self.new_block("and");
+                self.condition(left, &next, otherwise)?;
+                self.start(&next);
+                self.condition(right, then, otherwise)?;
+            }
+            ConditionKind::Or(left, right) => {
+                let next = self.new_block("or");
+                self.condition(left, then, &next)?;
+                self.start(&next);
+                self.condition(right, then, otherwise)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// An `i1` telling whether the comparison holds. A chain compares the
+    /// result of each comparison with the next operand, like C.
+    fn comparison(&mut self, c: &'a Comparison) -> Result<String> {
+        let (first, rest) = c.chain();
+        let mut left = self.expression(first)?;
+        let Some(((op, last), links)) = rest.split_last() else {
+            let ty = left.1;
+            return Ok(self.compare("!=", left, (zero(ty), ty)));
+        };
+
+        for (op, expr) in links {
+            let right = self.expression(expr)?;
+            let holds = self.compare(op, left, right);
+            left = (self.value(&format!("zext i1 {holds} to i64")), Type::Int);
+        }
+        let right = self.expression(last)?;
+        Ok(self.compare(op, left, right))
+    }
+
+    /// Compares two numbers, converting an int compared with a float to a
+    /// float.
+    fn compare(&mut self, op: &str, left: (String, Type), right: (String, Type)) -> String {
+        if left.1 == Type::Int && right.1 == Type::Int {
+            let predicate = match op {
+                "==" => "eq",
+                "!=" => "ne",
+                "<" => "slt",
+                "<=" => "sle",
+                ">" => "sgt",
+                ">=" => "sge",
+                _ => unreachable!("unknown comparison operator `{op}`"),
+            };
+            return self.value(&format!("icmp {predicate} i64 {}, {}", left.0, right.0));
+        }
+
+        let predicate = match op {
+            "==" => "oeq",
+            "!=" => "une",
+            "<" => "olt",
+            "<=" => "ole",
+            ">" => "ogt",
+            ">=" => "oge",
+            _ => unreachable!("unknown comparison operator `{op}`"),
+        };
+        let left = self.convert(left, Type::Float);
+        let right = self.convert(right, Type::Float);
+        self.value(&format!("fcmp {predicate} double {left}, {right}"))
+    }
+
+    /// The value converted to `ty`, the way C converts on assignment.
+    fn convert(&mut self, (value, from): (String, Type), ty: Type) -> String {
+        match (from, ty) {
+            (Type::Int, Type::Float) => self.value(&format!("sitofp i64 {value} to double")),
+            (Type::Float, Type::Int) => self.value(&format!("fptosi double {value} to i64")),
+            _ => value,
+        }
+    }
+
+    /// Applies an integer instruction, or its float counterpart when either
+    /// side is a float.
+    fn arithmetic(
+        &mut self,
+        op: &str,
+        left: (String, Type),
+        right: (String, Type),
+    ) -> (String, Type) {
+        if left.1 == Type::Int && right.1 == Type::Int {
+            let value = self.value(&format!("{op} i64 {}, {}", left.0, right.0));
+            return (value, Type::Int);
+        }
+        let left = self.convert(left, Type::Float);
+        let right = self.convert(right, Type::Float);
+        let value = self.value(&format!("f{op} double {left}, {right}"));
+        (value, Type::Float)
+    }
+
+    fn expression(&mut self, e: &'a Expression) -> Result<(String, Type)> {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.term(term),
+            ExpressionKind::Add(expr, term) => {
+                let left = self.expression(expr)?;
+                let right = self.term(term)?;
+                Ok(self.arithmetic("add", left, right))
+            }
+            ExpressionKind::Sub(expr, term) => {
+                let left = self.expression(expr)?;
+                let right = self.term(term)?;
+                Ok(self.arithmetic("sub", left, right))
+            }
+        }
+    }
+
+    fn term(&mut self, t: &'a Term) -> Result<(String, Type)> {
+        match &t.kind {
+            TermKind::Unary(unary) => self.unary(unary),
+            TermKind::Mul(left, right) => {
+                let left = self.term(left)?;
+                let right = self.unary(right)?;
+                Ok(self.arithmetic("mul", left, right))
+            }
+            TermKind::Div(left, right) => {
+                let left = self.term(left)?;
+                let right = self.unary(right)?;
+                if left.1 == Type::Int && right.1 == Type::Int {
+                    return Ok((self.divide(&left.0, &right.0, t.span), Type::Int));
+                }
+                let left = self.convert(left, Type::Float);
+                let right = self.convert(right, Type::Float);
+                Ok((
+                    self.value(&format!("fdiv double {left}, {right}")),
+                    Type::Float,
+                ))
+            }
+        }
+    }
+
+    /// Integer division stops the program with an error on a zero divisor,
+    /// and dividing the most negative value by -1 wraps around like the
+    /// interpreter, where `sdiv` would be undefined.
+    fn divide(&mut self, left: &str, right: &str, span: Span) -> String {
+        let (fail, divide) = (self.new_block("divzero"), self.new_block("div"));
+        let is_zero = self.value(&format!("icmp eq i64 {right}, 0"));
+        self.terminate(&format!("br i1 {is_zero}, label %{fail}, label %{divide}"));
+
+        self.start(&fail);
+        let message = self.string(&format!(
+            "error: attempt to divide by zero on line {}\n",
+            span.line
+        ));
+        self.emit(&format!("call void @tb_error(ptr {message})"));
+        self.terminate("unreachable");
+
+        self.start(&divide);
+        let minus_one = self.value(&format!("icmp eq i64 {right}, -1"));
+        let divisor = self.value(&format!("select i1 {minus_one}, i64 1, i64 {right}"));
+        let quotient = self.value(&format!("sdiv i64 {left}, {divisor}"));
+        let negated = self.value(&format!("sub i64 0, {left}"));
+        self.value(&format!(
+            "select i1 {minus_one}, i64 {negated}, i64 {quotient}"
+        ))
+    }
+
+    fn unary(&mut self, u: &'a Unary) -> Result<(String, Type)> {
+        match &u.kind {
+            UnaryKind::Primary(primary) => self.primary(primary),
+            UnaryKind::Positive(unary) => self.unary(unary),
+            UnaryKind::Negative(unary) => {
+                let (value, ty) = self.unary(unary)?;
+                let value = match ty {
+                    Type::Float => self.value(&format!("fneg double {value}")),
+                    _ => self.value(&format!("sub i64 0, {value}")),
+                };
+                Ok((value, ty))
+            }
+        }
+    }
+
+    fn primary(&mut self, p: &'a Primary) -> Result<(String, Type)> {
+        Ok(match &p.kind {
+            PrimaryKind::Integer(v) => (v.to_string(), Type::Int),
+            PrimaryKind::Number(v) => (v.to_string(), Type::Int),
+            PrimaryKind::Float(v) => (float(*v), Type::Float),
+            PrimaryKind::String(_) => return Err(unsupported(BACKEND, "string values", p.span)),
+            PrimaryKind::Variable(var) => {
+                let (var, ty) = self.variable(var, p.span)?;
+                (self.load(ty, &var), ty)
+            }
+            PrimaryKind::Call(..) => {
+                return Err(unsupported(BACKEND, "built-in functions", p.span))
+            }
+            PrimaryKind::FunctionCall(name, args) => {
+                let value = self.call(name, args, p.span)?;
+                (value, self.procedures[name].result.unwrap_or(Type::Int))
+            }
+            PrimaryKind::Index(..) => return Err(unsupported(BACKEND, "arrays", p.span)),
+            PrimaryKind::Grouped(expr) => self.expression(expr)?,
+        })
+    }
+
+    /// Calls a procedure with the arguments converted to the types of its
+    /// parameters, returning the result of a FUNCTION.
+    fn call(&mut self, name: &str, args: &'a [Expression], span: Span) -> Result<String> {
+        let types = &self.procedures[name];
+        let (params, result) = (types.params.clone(), types.result);
+
+        let mut values = Vec::new();
+        for (arg, ty) in args.iter().zip(params) {
+            let value = self.expression(arg)?;
+            let value = self.convert(value, ty);
+            values.push(format!("{} {value}", llvm_type(ty, span)?));
+        }
+        let call = format!("call {{}} {}({})", fn_name(name), values.join(", "));
+        Ok(match result {
+            Some(ty) => self.value(&call.replace("{}", llvm_type(ty, span)?)),
+            None => {
+                self.emit(&call.replace("{}", "void"));
+                String::new()
+            }
+        })
+    }
+}
+
+/// LLVM type of the values of a type, strings being unsupported.
+fn llvm_type(ty: Type, span: Span) -> Result<&'static str> {
+    match ty {
+        Type::Int => Ok("i64"),
+        Type::Float => Ok("double"),
+        Type::Str => Err(unsupported(BACKEND, "string values", span)),
+    }
+}
+
+fn zero(ty: Type) -> String {
+    match ty {
+        Type::Float => float(0.0),
+        _ => "0".to_string(),
+    }
+}
+
+/// LLVM constant for a double, in the hexadecimal form that keeps every bit.
+fn float(v: f64) -> String {
+    format!("0x{:016X}", v.to_bits())
+}
+
+/// Symbol of a SUB or FUNCTION.
+fn fn_name(name: &str) -> String {
+    quoted_name('@', &backend::fn_name(name))
+}
+
+/// Local holding the value of a variable.
+fn var_name(var: &str) -> String {
+    quoted_name('%', &format!("v.{var}"))
+}
+
+/// Argument of a parameter, stored in its variable on entry.
+fn param_name(param: &str) -> String {
+    quoted_name('%', &format!("p.{param}"))
+}
+
+/// Name in quotes, which may hold any character, e.g. `%"v.é"`. Quotes,
+/// backslashes and control characters are written as hexadecimal escapes.
+fn quoted_name(sigil: char, name: &str) -> String {
+    let mut quoted = format!("{sigil}\"");
+    for c in name.chars() {
+        match c {
+            '"' | '\\' => quoted.push_str(&format!("\\{:02X}", c as u32)),
+            c if c.is_ascii_control() => quoted.push_str(&format!("\\{:02X}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// LLVM string constant holding `s` and a terminating NUL, everything but
+/// printable ASCII written as a hexadecimal escape.
+fn llvm_string(s: &str) -> String {
+    let contents = backend::escape_bytes(s, |byte| format!("\\{byte:02X}"));
+    format!("\"{contents}\\00\"")
+}
+
+#[cfg(test)]
+mod tests {
+    fn compile(source: &str) -> String {
+        let parser = crate::check(source).expect("the program is valid");
+        super::compile(&parser).expect("the program is supported")
+    }
+
+    #[test]
+    fn names_are_quoted() {
+        let ir = compile("LET é = 3\nPRINT é\n");
+        assert!(ir.contains("  %\"v.é\" = alloca i64\n"));
+        assert!(ir.contains("  store i64 3, ptr %\"v.é\"\n"));
+    }
+
+    #[test]
+    fn quotes_in_names_are_escaped() {
+        assert_eq!(super::quoted_name('%', "v.a\"b\\"), "%\"v.a\\22b\\5C\"");
+    }
+
+    #[test]
+    fn procedures_and_parameters_are_quoted() {
+        let ir = compile("FUNCTION dé(x)\nRETURN x\nENDFUNCTION\nPRINT dé(1)\n");
+        assert!(ir.contains("define internal i64 @\"fn_dé\"(i64 %\"p.x\")"));
+        assert!(ir.contains("store i64 %\"p.x\", ptr %\"v.x\""));
+        assert!(ir.contains("call i64 @\"fn_dé\"(i64 1)"));
+    }
+
+    #[test]
+    fn input_reads_a_checked_line() {
+        let ir = compile("LET f = 0.5\nINPUT n\nINPUT f\n");
+        assert!(ir.contains("call i64 @tb_input_int(i32 2)"));
+        assert!(ir.contains("call double @tb_input_float(i32 3)"));
+        assert!(!ir.contains("@scanf"));
+    }
+
+    #[test]
+    fn strings_are_rejected() {
+        let parser = crate::check("LET a$ = \"x\"\nPRINT a$\n").unwrap();
+        assert!(super::compile(&parser).is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use basic_compiler::Config;
+use basic_compiler::{Config, RunError, USAGE};
 use std::env;
 
 use basic_compiler::run;
@@ -30,12 +30,14 @@ fn main() {
     setup_tracing(enable_tracing);
 
     let config = Config::build(&args).unwrap_or_else(|err| {
-        println!("Problems parsing out the arguments: {err}!");
-        std::process::exit(1)
+        eprintln!("Problems parsing out the arguments: {err}\n\n{USAGE}");
+        std::process::exit(2)
     });
 
-    if let Err(e) = run(config) {
-        println!("Compilation error: {e}");
-        std::process::exit(1)
+    if let Err(err) = run(config) {
+        if matches!(err, RunError::Io(_) | RunError::Usage(_)) {
+            eprintln!("error: {err}");
+        }
+        std::process::exit(err.exit_code())
     }
 }
@@ -1,20 +1,31 @@
 use crate::ast;
-use crate::lexer::{Lexer, Token};
+use crate::error::{CompileError, CompileWarning};
+use crate::lexer::{Lexer, SpannedToken, Token};
+use crate::resolver;
+use crate::span::Span;
+use crate::types::{self, ProcedureTypes, Scope};
 
-use anyhow::{anyhow, Ok, Result};
 use tracing::{self, instrument};
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::iter::Peekable;
 use std::vec::IntoIter;
 
+type Result<T> = std::result::Result<T, CompileError>;
+
 #[derive(Default)]
 pub struct Parser {
     pub ast: ast::Ast,
     pub variables: HashSet<String>,
-    labels_declared: HashSet<String>,
-    labels_gotoed: HashSet<String>,
-    iter: Option<Peekable<IntoIter<Token>>>,
+    pub types: Scope,
+    pub procedures: HashMap<String, ProcedureTypes>,
+    pub errors: Vec<CompileError>,
+    pub warnings: Vec<CompileWarning>,
+    /// Number of dimensions of the arrays declared so far, and their DIM.
+    dims: HashMap<String, (usize, Span)>,
+    iter: Option<Peekable<IntoIter<SpannedToken>>>,
+    last_span: Span,
+    at_line_start: bool,
 }
 
 impl Parser {
@@ -26,91 +37,254 @@ impl Parser {
     }
 
     fn peek(&mut self) -> Option<Token> {
-        self.iter.as_mut().unwrap().peek().cloned()
+        self.iter.as_mut().unwrap().peek().map(|t| t.token.clone())
     }
 
     fn advance(&mut self) -> Option<Token> {
-        self.iter.as_mut().unwrap().next()
+        let next = self.iter.as_mut().unwrap().next()?;
+        self.last_span = next.span;
+        self.at_line_start = matches!(next.token, Token::NEWLINE);
+        Some(next.token)
+    }
+
+    /// Span of the next token, or an empty span at the end of input.
+    fn peek_span(&mut self) -> Span {
+        match self.iter.as_mut().unwrap().peek() {
+            Some(next) => next.span,
+            None => Span::new(
+                self.last_span.end,
+                self.last_span.end,
+                self.last_span.line,
+                self.last_span.column + (self.last_span.end - self.last_span.start),
+            ),
+        }
+    }
+
+    /// Span from `start` up to and including the last consumed token.
+    fn span_from(&self, start: Span) -> Span {
+        start.to(self.last_span)
+    }
+
+    fn skip_newlines(&mut self) {
+        while let Some(Token::NEWLINE) = self.peek() {
+            self.advance();
+        }
+    }
+
+    fn at_block_end(&mut self) -> bool {
+        matches!(
+            self.peek(),
+            Some(
+                Token::ENDIF
+                    | Token::ELSE
+                    | Token::ELSEIF
+                    | Token::ENDWHILE
+                    | Token::NEXT
+                    | Token::ENDSUB
+                    | Token::ENDFUNCTION
+            ) | None
+        )
     }
 
-    /// program ::= {statement}
-    pub fn check(&mut self, lexer: &Lexer) -> Result<()> {
-        self.iter = Some(lexer.tokens.clone().into_iter().peekable());
+    /// Records a failed statement and skips ahead to a point where parsing
+    /// can resume: past the end of the current line, or up to a block
+    /// terminator so the enclosing IF/WHILE/FOR can still be closed.
+    ///
+    /// An error about a token the lexer already rejected is not recorded
+    /// again.
+    fn recover(&mut self, err: CompileError) {
+        tracing::debug!("Recovering from: {err}");
+        match err {
+            CompileError::UnexpectedToken {
+                found: Some(Token::INVALID),
+                ..
+            }
+            | CompileError::InvalidStatement {
+                found: Some(Token::INVALID),
+                ..
+            } => (),
+            err => self.errors.push(err),
+        }
 
-        while self.peek().is_some() {
-            let statement = self.statement()?;
+        if self.at_line_start {
+            return;
+        }
 
-            self.ast.program.push(statement);
+        while !self.at_block_end() {
+            if let Some(Token::NEWLINE) = self.advance() {
+                break;
+            }
         }
+    }
+
+    /// program ::= {statement | procedure}
+    ///
+    /// Lexer errors are carried over, so a failed check reports everything
+    /// wrong with the source.
+    pub fn check(&mut self, lexer: Lexer) -> std::result::Result<(), Vec<CompileError>> {
+        self.iter = Some(lexer.tokens.into_iter().peekable());
+        self.errors.extend(lexer.errors);
+
+        loop {
+            self.skip_newlines();
+
+            let span = self.peek_span();
+            match self.peek() {
+                None => break,
+                Some(
+                    token @ (Token::ENDIF
+                    | Token::ELSE
+                    | Token::ELSEIF
+                    | Token::ENDWHILE
+                    | Token::NEXT
+                    | Token::ENDSUB
+                    | Token::ENDFUNCTION),
+                ) => {
+                    self.advance();
+                    self.errors
+                        .push(CompileError::UnmatchedBlockEnd { found: token, span });
+                    continue;
+                }
+                Some(Token::SUB | Token::FUNCTION) => {
+                    match self.procedure() {
+                        Ok(procedure) => self.ast.procedures.push(procedure),
+                        Err(err) => self.recover(err),
+                    }
+                    continue;
+                }
+                _ => (),
+            }
 
-        for label in &self.labels_gotoed {
-            if self.labels_declared.contains(label) {
-                return Err(anyhow!("Attemt to GOTO to undeclared label! {label}"));
+            match self.statement() {
+                Ok(statement) => self.ast.program.push(statement),
+                Err(err) => self.recover(err),
             }
         }
 
+        let (errors, warnings) = resolver::resolve(&self.ast);
+        self.errors.extend(errors);
+        self.warnings = warnings;
+
+        let (types, procedures, errors) = types::infer(&self.ast);
+        self.errors.extend(errors);
+        self.types = types;
+        self.procedures = procedures;
+
         tracing::debug!("{:#?}", self.ast);
 
+        if !self.errors.is_empty() {
+            self.errors
+                .sort_by_key(|err| err.span().map(|span| span.start));
+            return Err(std::mem::take(&mut self.errors));
+        }
+
         Ok(())
     }
 
-    /// statement ::= PRINT (expression | string) nl
-    ///               IF comparison "THEN" nl {statement} "ENDIF" nl
-    ///               WHILE comparison "REPEAT" nl {statement} "ENDWHILE" nl
+    /// statement ::= PRINT expression nl
+    ///               IF condition "THEN" nl {statement}
+    ///                  {"ELSEIF" condition "THEN" nl {statement}}
+    ///                  ["ELSE" nl {statement}] "ENDIF" nl
+    ///               WHILE condition "REPEAT" nl {statement} "ENDWHILE" nl
+    ///               FOR var "=" expression "TO" expression ["STEP" expression] nl
+    ///                  {statement} "NEXT" [var] nl
     ///               LABEL var nl
     ///               GOTO var nl
-    ///               LET var "=" expression nl
-    ///               INPUT var nl
+    ///               GOSUB var nl
+    ///               RETURN [expression] nl
+    ///               CALL var arguments nl
+    ///               DIM var "(" expression {"," expression} ")" nl
+    ///               LET target "=" expression nl
+    ///               INPUT target nl
     #[tracing::instrument(skip_all)]
     fn statement(&mut self) -> Result<ast::Statement> {
         tracing::debug!("Current token {:?}", self.peek());
         tracing::debug!("{:#?}", self.ast);
 
-        match self.peek() {
+        let start = self.peek_span();
+
+        let kind = match self.peek() {
             Some(Token::NEWLINE) => {
                 self.advance();
-                Ok(self.statement()?)
+                return self.statement();
             }
             Some(Token::PRINT) => {
                 self.advance();
-                Ok(self.statement_print()?)
+                self.statement_print()?
             }
             Some(Token::IF) => {
                 self.advance();
-                Ok(self.statement_if()?)
+                self.statement_if()?
             }
             Some(Token::WHILE) => {
                 self.advance();
-                Ok(self.statement_while()?)
+                self.statement_while()?
+            }
+            Some(Token::FOR) => {
+                self.advance();
+                self.statement_for(start)?
             }
             Some(Token::LABEL) => {
                 self.advance();
-                Ok(self.statement_label()?)
+                self.statement_label()?
             }
             Some(Token::GOTO) => {
                 self.advance();
-                Ok(self.statement_goto()?)
+                self.statement_goto()?
+            }
+            Some(Token::GOSUB) => {
+                self.advance();
+                self.statement_gosub()?
+            }
+            Some(Token::RETURN) => {
+                self.advance();
+                self.statement_return()?
+            }
+            Some(Token::CALL) => {
+                self.advance();
+                self.statement_call()?
+            }
+            // Procedures may only be defined at the top level.
+            Some(Token::SUB | Token::FUNCTION) => {
+                let procedure = self.procedure()?;
+                Err(CompileError::NestedProcedure {
+                    keyword: procedure.kind.keyword(),
+                    span: procedure.span,
+                })?
+            }
+            Some(Token::DIM) => {
+                self.advance();
+                self.statement_dim(start)?
             }
             Some(Token::LET) => {
                 self.advance();
-                Ok(self.statement_let()?)
+                self.statement_let()?
             }
             Some(Token::INPUT) => {
                 self.advance();
-                Ok(self.statement_input()?)
+                self.statement_input()?
             }
-            Some(token) => Err(anyhow!("Invalid statement at: {token}"))?,
-            None => panic!("None encountered!!!"),
-        }
+            found => {
+                self.advance();
+                Err(CompileError::InvalidStatement { found, span: start })?
+            }
+        };
+
+        Ok(ast::Statement {
+            kind,
+            span: self.span_from(start),
+        })
     }
 
     #[tracing::instrument(skip_all)]
     fn var(&mut self) -> Result<String> {
         tracing::debug!("Current token {:?}", self.peek());
 
+        let span = self.peek_span();
+
         match self.advance() {
             Some(Token::VARIABLE(content)) => Ok(content.clone()),
-            _ => Err(anyhow!("Invalid variable!"))?,
+            token => Err(unexpected("a variable name", token, span))?,
         }
     }
 
@@ -118,159 +292,512 @@ impl Parser {
     fn nl(&mut self) -> Result<()> {
         tracing::debug!("Current token {:?}", self.peek());
 
+        let span = self.peek_span();
+
         match self.advance() {
             Some(Token::NEWLINE) => Ok(()),
-            val => Err(anyhow!("Expected NEWLINE, got {:?}", val))?,
+            token => Err(unexpected("end of line", token, span))?,
         }
     }
 
-    /// statement ::= PRINT (expression | string) nl
+    /// statement ::= PRINT expression nl
+    ///
+    /// A lone string literal becomes `PrintStr`, which needs no evaluation.
     #[tracing::instrument(skip_all)]
-    fn statement_print(&mut self) -> Result<ast::Statement> {
+    fn statement_print(&mut self) -> Result<ast::StatementKind> {
         tracing::debug!("Current token {:?}", self.peek());
 
-        let return_value = match self.peek() {
-            Some(Token::STRING(value)) => {
-                self.advance();
-                Ok(ast::Statement::PrintStr(value.clone()))
-            }
-            _ => Ok(ast::Statement::Print(self.expression()?)),
-        };
+        let expression = self.expression()?;
 
         self.nl()?;
 
-        return_value
+        Ok(match expression.as_string_literal() {
+            Some(string) => ast::StatementKind::PrintStr(string.to_string()),
+            None => ast::StatementKind::Print(expression),
+        })
     }
 
-    /// statement ::= IF comparison "THEN" nl {statement} "ENDIF" nl
+    /// statement ::= IF condition "THEN" nl {statement}
+    ///                  {"ELSEIF" condition "THEN" nl {statement}}
+    ///                  ["ELSE" nl {statement}] "ENDIF" nl
+    ///
+    /// An ELSEIF is parsed as an IF nested in the ELSE branch, sharing the
+    /// outer ENDIF.
     #[tracing::instrument(skip_all)]
-    fn statement_if(&mut self) -> Result<ast::Statement> {
+    fn statement_if(&mut self) -> Result<ast::StatementKind> {
         tracing::debug!("Current token {:?}", self.peek());
-        let comparison = self.comparison()?;
+        let condition = self.condition()?;
 
+        let span = self.peek_span();
         match self.advance() {
             Some(Token::THEN) => (),
-            val => return Err(anyhow!("IF should be followed by THEN, got {:?}", val)),
+            token => Err(unexpected("THEN after IF condition", token, span))?,
         }
 
         self.nl()?;
 
-        let statement = Box::new(self.statement()?);
+        let statement = Box::new(self.chain_statements()?);
 
-        match self.advance() {
+        let start = self.peek_span();
+        let otherwise = match self.advance() {
             Some(Token::ENDIF) => {
                 self.nl()?;
-                Ok(ast::Statement::If(comparison, statement))
+                None
             }
-            _ => Err(anyhow!("IF should be followed by ENDIF")),
-        }
+            Some(Token::ELSEIF) => {
+                let kind = self.statement_if()?;
+                Some(Box::new(ast::Statement {
+                    kind,
+                    span: self.span_from(start),
+                }))
+            }
+            Some(Token::ELSE) => {
+                self.nl()?;
+                let otherwise = Box::new(self.chain_statements()?);
+
+                let span = self.peek_span();
+                match self.advance() {
+                    Some(Token::ENDIF) => self.nl()?,
+                    token => Err(unexpected("ENDIF", token, span))?,
+                }
+
+                Some(otherwise)
+            }
+            token => Err(unexpected("ELSEIF, ELSE or ENDIF", token, start))?,
+        };
+
+        Ok(ast::StatementKind::If(condition, statement, otherwise))
     }
 
-    /// statement ::= WHILE comparison "REPEAT" nl {statement} "ENDWHILE" nl
+    /// statement ::= WHILE condition "REPEAT" nl {statement} "ENDWHILE" nl
     #[tracing::instrument(skip_all)]
-    fn statement_while(&mut self) -> Result<ast::Statement> {
+    fn statement_while(&mut self) -> Result<ast::StatementKind> {
         tracing::debug!("Current token {:?}", self.peek());
 
-        let comparison = self.comparison()?;
+        let condition = self.condition()?;
 
+        let span = self.peek_span();
         match self.advance() {
             Some(Token::REPEAT) => (),
-            val => return Err(anyhow!("WHILE should be followed by REPEAT, got {:?}", val)),
+            token => Err(unexpected("REPEAT after WHILE condition", token, span))?,
         }
 
         self.nl()?;
 
         let statement = Box::new(self.chain_statements()?);
 
+        let span = self.peek_span();
         match self.advance() {
             Some(Token::ENDWHILE) => {
                 self.nl()?;
-                Ok(ast::Statement::While(comparison, statement))
+                Ok(ast::StatementKind::While(condition, statement))
+            }
+            token => Err(unexpected("ENDWHILE", token, span))?,
+        }
+    }
+
+    /// statement ::= FOR var "=" expression "TO" expression ["STEP" expression] nl
+    ///                  {statement} "NEXT" [var] nl
+    #[tracing::instrument(skip_all)]
+    fn statement_for(&mut self, start: Span) -> Result<ast::StatementKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let var = self.var()?;
+
+        self.variables.insert(var.clone());
+
+        let span = self.peek_span();
+        let from = match self.advance() {
+            Some(Token::EQ) => Box::new(self.expression()?),
+            token => Err(unexpected(&format!("`=` after FOR {var}"), token, span))?,
+        };
+
+        let span = self.peek_span();
+        let to = match self.advance() {
+            Some(Token::TO) => Box::new(self.expression()?),
+            token => Err(unexpected("TO after FOR start value", token, span))?,
+        };
+
+        let step = match self.peek() {
+            Some(Token::STEP) => {
+                self.advance();
+                Some(Box::new(self.expression()?))
+            }
+            _ => None,
+        };
+
+        self.nl()?;
+
+        let body = Box::new(self.chain_statements()?);
+
+        let span = self.peek_span();
+        match self.advance() {
+            Some(Token::NEXT) => (),
+            token => Err(unexpected("NEXT", token, span))?,
+        }
+
+        let span = self.peek_span();
+        if let Some(Token::VARIABLE(found)) = self.peek() {
+            self.advance();
+            if found != var {
+                self.errors.push(CompileError::MismatchedNext {
+                    expected: var.clone(),
+                    found,
+                    span,
+                    start,
+                });
             }
-            val => Err(anyhow!(
-                "WHILE should be followed by ENDWHILE, got: {:?}",
-                val
-            )),
         }
+
+        self.nl()?;
+
+        Ok(ast::StatementKind::For {
+            var,
+            from,
+            to,
+            step,
+            body,
+        })
     }
 
     #[tracing::instrument(skip_all)]
     fn chain_statements(&mut self) -> Result<ast::Statement> {
-        match self.statement() {
-            Result::Ok(statement) => Ok(ast::Statement::Statement(
+        self.skip_newlines();
+
+        let start = self.peek_span();
+
+        if self.at_block_end() {
+            tracing::debug!("Chain end.");
+            return Ok(ast::Statement {
+                kind: ast::StatementKind::End,
+                span: start,
+            });
+        }
+
+        let kind = match self.statement() {
+            Ok(statement) => ast::StatementKind::Statement(
                 Box::new(statement),
                 Box::new(self.chain_statements()?),
-            )),
+            ),
             Err(err) => {
-                tracing::debug!("Chain end.");
-                match self.peek().unwrap() {
-                    Token::ENDWHILE => Ok(ast::Statement::End),
-                    _ => Err(err),
-                }
+                self.recover(err);
+                return self.chain_statements();
             }
-        }
+        };
+
+        Ok(ast::Statement {
+            kind,
+            span: self.span_from(start),
+        })
     }
 
     /// statement ::= LABEL var nl
     #[tracing::instrument(skip_all)]
-    fn statement_label(&mut self) -> Result<ast::Statement> {
+    fn statement_label(&mut self) -> Result<ast::StatementKind> {
         tracing::debug!("Current token {:?}", self.peek());
 
         let var = self.var()?;
 
-        if !self.labels_declared.insert(var.clone()) {
-            return Err(anyhow!("Label aready exists! {var}"));
-        }
-
         self.nl()?;
-        Ok(ast::Statement::Label(var))
+        Ok(ast::StatementKind::Label(var))
     }
 
     /// statement ::= GOTO var nl
     #[tracing::instrument(skip_all)]
-    fn statement_goto(&mut self) -> Result<ast::Statement> {
+    fn statement_goto(&mut self) -> Result<ast::StatementKind> {
         tracing::debug!("Current token {:?}", self.peek());
 
         let var = self.var()?;
 
-        self.labels_gotoed.insert(var.clone());
+        self.nl()?;
+
+        Ok(ast::StatementKind::Goto(var))
+    }
+
+    /// statement ::= GOSUB var nl
+    #[tracing::instrument(skip_all)]
+    fn statement_gosub(&mut self) -> Result<ast::StatementKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let var = self.var()?;
 
         self.nl()?;
 
-        Ok(ast::Statement::Goto(var))
+        Ok(ast::StatementKind::Gosub(var))
     }
 
-    /// statement ::= LET var "=" expression nl
+    /// statement ::= RETURN [expression] nl
     #[tracing::instrument(skip_all)]
-    fn statement_let(&mut self) -> Result<ast::Statement> {
+    fn statement_return(&mut self) -> Result<ast::StatementKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let value = match self.peek() {
+            Some(Token::NEWLINE) | None => None,
+            _ => Some(self.expression()?),
+        };
+
+        self.nl()?;
+
+        Ok(ast::StatementKind::Return(value))
+    }
+
+    /// statement ::= CALL var arguments nl
+    #[tracing::instrument(skip_all)]
+    fn statement_call(&mut self) -> Result<ast::StatementKind> {
         tracing::debug!("Current token {:?}", self.peek());
 
         let var = self.var()?;
+        let args = self.arguments(&var)?;
 
-        self.variables.insert(var.clone());
+        self.nl()?;
+
+        Ok(ast::StatementKind::Call(var, args))
+    }
 
+    /// procedure ::= "SUB" var "(" [var {"," var}] ")" nl {statement} "ENDSUB" nl
+    ///               "FUNCTION" var "(" [var {"," var}] ")" nl {statement} "ENDFUNCTION" nl
+    ///
+    /// The body has variables of its own, of which only the parameters are
+    /// assigned when it starts.
+    #[tracing::instrument(skip_all)]
+    fn procedure(&mut self) -> Result<ast::Procedure> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let start = self.peek_span();
+        let kind = match self.advance() {
+            Some(Token::SUB) => ast::ProcedureKind::Sub,
+            _ => ast::ProcedureKind::Function,
+        };
+
+        let name = self.var()?;
+
+        let span = self.peek_span();
+        match self.advance() {
+            Some(Token::LPAREN) => (),
+            token => Err(unexpected(&format!("`(` after {name}"), token, span))?,
+        }
+
+        let mut params: Vec<(String, Span)> = Vec::new();
+        if !matches!(self.peek(), Some(Token::RPAREN)) {
+            loop {
+                let span = self.peek_span();
+                let param = self.var()?;
+                if let Some((_, first)) = params.iter().find(|(name, _)| *name == param) {
+                    self.errors.push(CompileError::DuplicateDefinition {
+                        name: param.clone(),
+                        span,
+                        first: *first,
+                    });
+                }
+                params.push((param, span));
+
+                match self.peek() {
+                    Some(Token::COMMA) => self.advance(),
+                    _ => break,
+                };
+            }
+        }
+        let params: Vec<String> = params.into_iter().map(|(name, _)| name).collect();
+
+        let span = self.peek_span();
+        match self.advance() {
+            Some(Token::RPAREN) => (),
+            token => Err(unexpected("`,` or `)`", token, span))?,
+        }
+        let header = self.span_from(start);
+
+        self.nl()?;
+
+        let globals = std::mem::replace(&mut self.variables, params.iter().cloned().collect());
+        let global_dims = std::mem::take(&mut self.dims);
+        let body = self.chain_statements();
+        self.variables = globals;
+        self.dims = global_dims;
+        let body = Box::new(body?);
+
+        let span = self.peek_span();
+        match (kind, self.advance()) {
+            (ast::ProcedureKind::Sub, Some(Token::ENDSUB))
+            | (ast::ProcedureKind::Function, Some(Token::ENDFUNCTION)) => self.nl()?,
+            (_, token) => Err(unexpected(&format!("END{}", kind.keyword()), token, span))?,
+        }
+
+        Ok(ast::Procedure {
+            kind,
+            name,
+            params,
+            body,
+            span: header,
+        })
+    }
+
+    /// statement ::= DIM var "(" expression {"," expression} ")" nl
+    #[tracing::instrument(skip_all)]
+    fn statement_dim(&mut self, start: Span) -> Result<ast::StatementKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let var = self.var()?;
+        let sizes = self.arguments(&var)?;
+        if sizes.is_empty() {
+            Err(unexpected(
+                "the size of the array",
+                Some(Token::RPAREN),
+                self.last_span,
+            ))?
+        }
+        let span = self.span_from(start);
+
+        self.nl()?;
+
+        match self.dims.get(&var) {
+            Some((_, first)) => self.errors.push(CompileError::DuplicateDefinition {
+                name: var.clone(),
+                span,
+                first: *first,
+            }),
+            None => {
+                self.dims.insert(var.clone(), (sizes.len(), span));
+            }
+        }
+
+        Ok(ast::StatementKind::Dim(var, sizes))
+    }
+
+    /// statement ::= LET target "=" expression nl
+    #[tracing::instrument(skip_all)]
+    fn statement_let(&mut self) -> Result<ast::StatementKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let target = self.target()?;
+
+        let span = self.peek_span();
         let result = match self.advance() {
             Some(Token::EQ) => self.expression()?,
-            val => return Err(anyhow!("LET should be followed by '=', got {:?}", val)),
+            token => Err(unexpected(
+                &format!("`=` after LET {}", target.name()),
+                token,
+                span,
+            ))?,
         };
 
         self.nl()?;
 
-        Ok(ast::Statement::Let(var, result))
+        Ok(ast::StatementKind::Let(target, result))
+    }
+
+    /// statement ::= INPUT target nl
+    #[instrument(skip_all)]
+    fn statement_input(&mut self) -> Result<ast::StatementKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let target = self.target()?;
+
+        self.nl()?;
+
+        Ok(ast::StatementKind::Input(target))
     }
 
-    /// statement ::= INPUT var nl
+    /// target ::= var | index
     #[instrument(skip_all)]
-    fn statement_input(&mut self) -> Result<ast::Statement> {
+    fn target(&mut self) -> Result<ast::Target> {
         tracing::debug!("Current token {:?}", self.peek());
 
+        let start = self.peek_span();
         let var = self.var()?;
 
-        self.variables.insert(var.clone());
+        if !matches!(self.peek(), Some(Token::LPAREN)) {
+            self.variables.insert(var.clone());
+            return Ok(ast::Target::Variable(var));
+        }
 
-        self.nl()?;
+        let indices = self.arguments(&var)?;
+        self.check_indices(&var, indices.len(), self.span_from(start));
+
+        Ok(ast::Target::Element(var, indices))
+    }
 
-        Ok(ast::Statement::Input(var))
+    /// Reports indexing an array that is not declared, or with the wrong
+    /// number of indices.
+    fn check_indices(&mut self, array: &str, found: usize, span: Span) {
+        match self.dims.get(array) {
+            None => self.errors.push(CompileError::UndeclaredArray {
+                name: array.to_string(),
+                span,
+            }),
+            Some(&(expected, dim)) if expected != found => {
+                self.errors.push(CompileError::DimensionMismatch {
+                    name: array.to_string(),
+                    expected,
+                    found,
+                    span,
+                    dim,
+                })
+            }
+            _ => (),
+        }
+    }
+
+    /// condition ::= and_condition {"OR" and_condition}
+    #[instrument(skip_all)]
+    fn condition(&mut self) -> Result<ast::Condition> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let start = self.peek_span();
+
+        let mut condition = self.and_condition()?;
+
+        while let Some(Token::OR) = self.peek() {
+            self.advance();
+            let right = self.and_condition()?;
+            condition = ast::Condition {
+                kind: ast::ConditionKind::Or(Box::new(condition), Box::new(right)),
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(condition)
+    }
+
+    /// and_condition ::= not_condition {"AND" not_condition}
+    #[instrument(skip_all)]
+    fn and_condition(&mut self) -> Result<ast::Condition> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let start = self.peek_span();
+
+        let mut condition = self.not_condition()?;
+
+        while let Some(Token::AND) = self.peek() {
+            self.advance();
+            let right = self.not_condition()?;
+            condition = ast::Condition {
+                kind: ast::ConditionKind::And(Box::new(condition), Box::new(right)),
+                span: self.span_from(start),
+            };
+        }
+
+        Ok(condition)
+    }
+
+    /// not_condition ::= "NOT" not_condition | comparison
+    #[instrument(skip_all)]
+    fn not_condition(&mut self) -> Result<ast::Condition> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let start = self.peek_span();
+
+        let kind = match self.peek() {
+            Some(Token::NOT) => {
+                self.advance();
+                ast::ConditionKind::Not(Box::new(self.not_condition()?))
+            }
+            _ => ast::ConditionKind::Comparison(Box::new(self.comparison()?)),
+        };
+
+        Ok(ast::Condition {
+            kind,
+            span: self.span_from(start),
+        })
     }
 
     /// comparison ::= expression (("==" | "!=" | ">" | ">=" | "<" | "<=") expression)+
@@ -278,82 +805,105 @@ impl Parser {
     fn comparison(&mut self) -> Result<ast::Comparison> {
         tracing::debug!("Current token {:?}", self.peek());
 
+        let start = self.peek_span();
+
         let left_expr = self.expression()?;
 
-        let right_expr = match self.peek() {
-            Some(Token::EQEQ) => {
-                self.advance();
-                ast::Comparison::Compare("==".to_string(), Box::new(self.comparison()?))
-            }
-            Some(Token::NOTEQ) => {
-                self.advance();
-                ast::Comparison::Compare("!=".to_string(), Box::new(self.comparison()?))
-            }
-            Some(Token::LT) => {
-                self.advance();
-                ast::Comparison::Compare("<".to_string(), Box::new(self.comparison()?))
-            }
-            Some(Token::LTEQ) => {
-                self.advance();
-                ast::Comparison::Compare("<=".to_string(), Box::new(self.comparison()?))
-            }
-            Some(Token::GT) => {
-                self.advance();
-                ast::Comparison::Compare(">".to_string(), Box::new(self.comparison()?))
-            }
-            Some(Token::GTEQ) => {
-                self.advance();
-                ast::Comparison::Compare(">=".to_string(), Box::new(self.comparison()?))
+        let op = match self.peek() {
+            Some(Token::EQEQ) => "==",
+            Some(Token::NOTEQ) => "!=",
+            Some(Token::LT) => "<",
+            Some(Token::LTEQ) => "<=",
+            Some(Token::GT) => ">",
+            Some(Token::GTEQ) => ">=",
+            _ => {
+                return Ok(ast::Comparison {
+                    kind: ast::ComparisonKind::Left(left_expr),
+                    span: self.span_from(start),
+                })
             }
-            _ => return Ok(ast::Comparison::Left(left_expr)),
         };
 
-        Ok(ast::Comparison::Right(left_expr, Box::new(right_expr)))
+        let op_start = self.peek_span();
+        self.advance();
+        let right_expr = ast::Comparison {
+            kind: ast::ComparisonKind::Compare(op.to_string(), Box::new(self.comparison()?)),
+            span: self.span_from(op_start),
+        };
+
+        Ok(ast::Comparison {
+            kind: ast::ComparisonKind::Right(left_expr, Box::new(right_expr)),
+            span: self.span_from(start),
+        })
     }
 
     /// expression ::= term {( "-" | "+" ) term}
+    ///
+    /// Operators are left associative, `a - b - c` is `(a - b) - c`.
     #[instrument(skip_all)]
     fn expression(&mut self) -> Result<ast::Expression> {
         tracing::debug!("Current token {:?}", self.peek());
 
+        let start = self.peek_span();
+
         let term = self.term()?;
+        let mut expression = ast::Expression {
+            kind: ast::ExpressionKind::Term(term),
+            span: self.span_from(start),
+        };
 
-        match self.peek() {
-            Some(Token::PLUS) => {
-                self.advance();
-                Ok(ast::Expression::Add(
-                    Box::new(term),
-                    Box::new(self.expression()?),
-                ))
-            }
-            Some(Token::MINUS) => {
-                self.advance();
-                Ok(ast::Expression::Sub(
-                    Box::new(term),
-                    Box::new(self.expression()?),
-                ))
-            }
-            _ => Ok(ast::Expression::Term(term)),
+        loop {
+            let kind = match self.peek() {
+                Some(Token::PLUS) => {
+                    self.advance();
+                    ast::ExpressionKind::Add(Box::new(expression), Box::new(self.term()?))
+                }
+                Some(Token::MINUS) => {
+                    self.advance();
+                    ast::ExpressionKind::Sub(Box::new(expression), Box::new(self.term()?))
+                }
+                _ => return Ok(expression),
+            };
+
+            expression = ast::Expression {
+                kind,
+                span: self.span_from(start),
+            };
         }
     }
 
     /// term ::= unary {( "/" | "*" ) unary}
+    ///
+    /// Operators are left associative, `a / b / c` is `(a / b) / c`.
     #[instrument(skip_all)]
     fn term(&mut self) -> Result<ast::Term> {
         tracing::debug!("Current token {:?}", self.peek());
 
+        let start = self.peek_span();
+
         let unary = self.unary()?;
+        let mut term = ast::Term {
+            kind: ast::TermKind::Unary(unary),
+            span: self.span_from(start),
+        };
 
-        match self.peek() {
-            Some(Token::ASTERISK) => {
-                self.advance();
-                Ok(ast::Term::Mul(Box::new(unary), Box::new(self.term()?)))
-            }
-            Some(Token::SLASH) => {
-                self.advance();
-                Ok(ast::Term::Div(Box::new(unary), Box::new(self.term()?)))
-            }
-            _ => Ok(ast::Term::Unary(unary)),
+        loop {
+            let kind = match self.peek() {
+                Some(Token::ASTERISK) => {
+                    self.advance();
+                    ast::TermKind::Mul(Box::new(term), Box::new(self.unary()?))
+                }
+                Some(Token::SLASH) => {
+                    self.advance();
+                    ast::TermKind::Div(Box::new(term), Box::new(self.unary()?))
+                }
+                _ => return Ok(term),
+            };
+
+            term = ast::Term {
+                kind,
+                span: self.span_from(start),
+            };
         }
     }
 
@@ -362,37 +912,315 @@ impl Parser {
     fn unary(&mut self) -> Result<ast::Unary> {
         tracing::debug!("Current token {:?}", self.peek());
 
-        match self.peek() {
+        let start = self.peek_span();
+
+        let kind = match self.peek() {
             Some(Token::PLUS) => {
                 self.advance();
-                Ok(ast::Unary::Positive(Box::new(self.unary()?)))
+                ast::UnaryKind::Positive(Box::new(self.unary()?))
             }
             Some(Token::MINUS) => {
                 self.advance();
-                Ok(ast::Unary::Negative(Box::new(self.unary()?)))
+                ast::UnaryKind::Negative(Box::new(self.unary()?))
             }
-            _ => Ok(ast::Unary::Primary(self.primary()?)),
-        }
+            _ => ast::UnaryKind::Primary(self.primary()?),
+        };
+
+        Ok(ast::Unary {
+            kind,
+            span: self.span_from(start),
+        })
     }
 
-    /// primary ::= number | var
+    /// primary ::= number | string | var | call | index | "(" expression ")"
+    /// index ::= var arguments
+    ///
+    /// `var(...)` indexes an array once it has been declared with DIM, and
+    /// calls a FUNCTION otherwise.
     #[instrument(skip_all)]
     fn primary(&mut self) -> Result<ast::Primary> {
         tracing::debug!("Current token {:?}", self.peek());
 
-        match self.advance() {
-            Some(Token::INTEGER(val)) => Ok(ast::Primary::Integer(val)),
-            Some(Token::FLOAT(val)) => Ok(ast::Primary::Float(val)),
+        let span = self.peek_span();
+
+        let kind = match self.advance() {
+            Some(Token::INTEGER(val)) => ast::PrimaryKind::Integer(val),
+            Some(Token::FLOAT(val)) => ast::PrimaryKind::Float(val),
+            Some(Token::STRING(val)) => ast::PrimaryKind::String(val),
+            Some(Token::LEN) => self.call(ast::Builtin::Len, span)?,
+            Some(Token::LEFT) => self.call(ast::Builtin::Left, span)?,
+            Some(Token::MID) => self.call(ast::Builtin::Mid, span)?,
+            Some(Token::STR) => self.call(ast::Builtin::Str, span)?,
+            Some(Token::VAL) => self.call(ast::Builtin::Val, span)?,
+            Some(Token::LPAREN) => {
+                let expression = self.expression()?;
+
+                let span = self.peek_span();
+                match self.advance() {
+                    Some(Token::RPAREN) => (),
+                    token => Err(unexpected("`)`", token, span))?,
+                }
+
+                ast::PrimaryKind::Grouped(Box::new(expression))
+            }
+            Some(Token::VARIABLE(val)) if matches!(self.peek(), Some(Token::LPAREN)) => {
+                let args = self.arguments(&val)?;
+                if self.dims.contains_key(&val) {
+                    self.check_indices(&val, args.len(), self.span_from(span));
+                    ast::PrimaryKind::Index(val, args)
+                } else {
+                    ast::PrimaryKind::FunctionCall(val, args)
+                }
+            }
             Some(Token::VARIABLE(val)) => {
                 if !self.variables.contains(val.as_str()) {
-                    Err(anyhow!("Variable referenced before assignment!"))?
+                    self.errors.push(CompileError::UseBeforeAssign {
+                        variable: val.clone(),
+                        span,
+                    });
                 }
-                Ok(ast::Primary::Variable(val.clone()))
+                ast::PrimaryKind::Variable(val.clone())
             }
-            token => Err(anyhow!(
-                "Unexpected token! Expecting VARIABLE, got {:?}",
-                token.unwrap()
-            ))?,
+            token => Err(unexpected("an expression", token, span))?,
+        };
+
+        Ok(ast::Primary {
+            kind,
+            span: self.span_from(span),
+        })
+    }
+
+    /// call ::= ("LEN" | "LEFT$" | "MID$" | "STR$" | "VAL" | var) arguments
+    ///
+    /// Only calls of built-in functions are checked here, user-defined ones
+    /// are checked once every procedure is known.
+    #[instrument(skip_all)]
+    fn call(&mut self, builtin: ast::Builtin, start: Span) -> Result<ast::PrimaryKind> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let args = self.arguments(builtin.name())?;
+
+        if !builtin.arity().contains(&args.len()) {
+            self.errors.push(CompileError::ArityMismatch {
+                function: builtin.name().to_string(),
+                expected: builtin.arity(),
+                found: args.len(),
+                span: self.span_from(start),
+            });
+        }
+
+        Ok(ast::PrimaryKind::Call(builtin, args))
+    }
+
+    /// arguments ::= "(" [expression {"," expression}] ")"
+    #[instrument(skip_all)]
+    fn arguments(&mut self, callee: &str) -> Result<Vec<ast::Expression>> {
+        tracing::debug!("Current token {:?}", self.peek());
+
+        let span = self.peek_span();
+        match self.advance() {
+            Some(Token::LPAREN) => (),
+            token => Err(unexpected(&format!("`(` after {callee}"), token, span))?,
+        }
+
+        let mut args = Vec::new();
+        if !matches!(self.peek(), Some(Token::RPAREN)) {
+            args.push(self.expression()?);
+            while let Some(Token::COMMA) = self.peek() {
+                self.advance();
+                args.push(self.expression()?);
+            }
+        }
+
+        let span = self.peek_span();
+        match self.advance() {
+            Some(Token::RPAREN) => (),
+            token => Err(unexpected("`,` or `)`", token, span))?,
+        }
+
+        Ok(args)
+    }
+}
+
+fn unexpected(expected: &str, found: Option<Token>, span: Span) -> CompileError {
+    CompileError::UnexpectedToken {
+        expected: expected.to_string(),
+        found,
+        span,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ast::{
+        Condition, ConditionKind, Expression, ExpressionKind, PrimaryKind, StatementKind, Term,
+        TermKind, Unary, UnaryKind,
+    };
+    use crate::error::CompileError;
+
+    /// The expression PRINTed by the last statement of `source`, written with
+    /// the names of its nodes to show how it is nested.
+    fn print_tree(source: &str) -> String {
+        let parser = crate::check(source).expect("the program is valid");
+        match &parser.ast.program.last().unwrap().kind {
+            StatementKind::Print(expr) => expression(expr),
+            statement => panic!("expected a PRINT, found {statement:?}"),
+        }
+    }
+
+    /// The condition of the IF ending `source`, written like [`print_tree`].
+    fn condition_tree(source: &str) -> String {
+        let parser = crate::check(source).expect("the program is valid");
+        match &parser.ast.program.last().unwrap().kind {
+            StatementKind::If(c, ..) => condition(c),
+            statement => panic!("expected an IF, found {statement:?}"),
+        }
+    }
+
+    fn condition(c: &Condition) -> String {
+        match &c.kind {
+            ConditionKind::Comparison(comparison) => {
+                let (first, rest) = comparison.chain();
+                let mut text = expression(first);
+                for (op, expr) in rest {
+                    text += &format!(" {op} {}", expression(expr));
+                }
+                text
+            }
+            ConditionKind::Not(c) => format!("Not({})", condition(c)),
+            ConditionKind::And(l, r) => format!("And({}, {})", condition(l), condition(r)),
+            ConditionKind::Or(l, r) => format!("Or({}, {})", condition(l), condition(r)),
+        }
+    }
+
+    fn expression(e: &Expression) -> String {
+        match &e.kind {
+            ExpressionKind::Term(t) => term(t),
+            ExpressionKind::Add(l, r) => format!("Add({}, {})", expression(l), term(r)),
+            ExpressionKind::Sub(l, r) => format!("Sub({}, {})", expression(l), term(r)),
+        }
+    }
+
+    fn term(t: &Term) -> String {
+        match &t.kind {
+            TermKind::Unary(u) => unary(u),
+            TermKind::Mul(l, r) => format!("Mul({}, {})", term(l), unary(r)),
+            TermKind::Div(l, r) => format!("Div({}, {})", term(l), unary(r)),
+        }
+    }
+
+    fn unary(u: &Unary) -> String {
+        match &u.kind {
+            UnaryKind::Primary(p) => match &p.kind {
+                PrimaryKind::Integer(v) => v.to_string(),
+                PrimaryKind::Variable(name) => name.clone(),
+                PrimaryKind::Grouped(e) => format!("Grouped({})", expression(e)),
+                primary => format!("{primary:?}"),
+            },
+            UnaryKind::Positive(u) => format!("Pos({})", unary(u)),
+            UnaryKind::Negative(u) => format!("Neg({})", unary(u)),
         }
     }
+
+    /// Codes and lines of the errors in `source`, in order.
+    fn errors(source: &str) -> Vec<(&'static str, usize)> {
+        let errors = crate::check(source).err().unwrap_or_default();
+        errors
+            .iter()
+            .map(|err: &CompileError| (err.code().unwrap(), err.span().unwrap().line))
+            .collect()
+    }
+
+    #[test]
+    fn lexer_errors_are_not_reported_again() {
+        assert_eq!(errors("LET y = $\n"), [("TB0001", 1)]);
+        assert_eq!(errors("PRINT 1e3\n"), [("TB0002", 1)]);
+        assert_eq!(
+            errors("LET x = 1 @ 2\n@ PRINT 1\n"),
+            [("TB0001", 1), ("TB0001", 2)]
+        );
+    }
+
+    #[test]
+    fn recovery_reports_every_error() {
+        let source = "PRINT 1 +\nLET y = $\nIF 1 == 1 THEN\nPRINT 2 2\nENDIF\n\
+                      WHILE 1 == 1 REPEAT\nLET = 3\nENDWHILE\nPRINT 1e\nPRINT 4 4\n";
+        assert_eq!(
+            errors(source),
+            [
+                ("TB0006", 1),
+                ("TB0001", 2),
+                ("TB0006", 4),
+                ("TB0006", 7),
+                ("TB0002", 9),
+                ("TB0006", 10),
+            ]
+        );
+    }
+
+    #[test]
+    fn operators_are_left_associative() {
+        assert_eq!(print_tree("PRINT 10 - 3 - 2\n"), "Sub(Sub(10, 3), 2)");
+        assert_eq!(print_tree("PRINT 12 / 3 / 2\n"), "Div(Div(12, 3), 2)");
+        assert_eq!(
+            print_tree("PRINT 1 + 2 - 3 + 4\n"),
+            "Add(Sub(Add(1, 2), 3), 4)"
+        );
+        assert_eq!(
+            print_tree("PRINT 8 * 2 / 4 * 3\n"),
+            "Mul(Div(Mul(8, 2), 4), 3)"
+        );
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        assert_eq!(print_tree("PRINT 1 + 2 * 3\n"), "Add(1, Mul(2, 3))");
+        assert_eq!(
+            print_tree("PRINT 1 * 2 - 6 / 3\n"),
+            "Sub(Mul(1, 2), Div(6, 3))"
+        );
+        assert_eq!(
+            print_tree("LET a = 1\nPRINT -a * -2\n"),
+            "Mul(Neg(a), Neg(2))"
+        );
+    }
+
+    #[test]
+    fn parentheses_are_kept_as_grouped_nodes() {
+        assert_eq!(
+            print_tree("PRINT 10 - (3 - 2)\n"),
+            "Sub(10, Grouped(Sub(3, 2)))"
+        );
+        assert_eq!(
+            print_tree("PRINT (1 + 2) * 3\n"),
+            "Mul(Grouped(Add(1, 2)), 3)"
+        );
+        assert_eq!(
+            print_tree("LET a = 1\nPRINT ((a))\n"),
+            "Grouped(Grouped(a))"
+        );
+        assert_eq!(print_tree("PRINT -(1 - 2)\n"), "Neg(Grouped(Sub(1, 2)))");
+    }
+
+    #[test]
+    fn not_binds_tighter_than_and_which_binds_tighter_than_or() {
+        let tree = |c: &str| condition_tree(&format!("LET a = 1\nIF {c} THEN\nPRINT 1\nENDIF\n"));
+        assert_eq!(
+            tree("a == 1 OR a == 2 AND a == 3"),
+            "Or(a == 1, And(a == 2, a == 3))"
+        );
+        assert_eq!(
+            tree("a == 1 AND a == 2 OR a == 3"),
+            "Or(And(a == 1, a == 2), a == 3)"
+        );
+        assert_eq!(tree("NOT a == 1 AND a == 2"), "And(Not(a == 1), a == 2)");
+        assert_eq!(
+            tree("NOT NOT a == 1 OR NOT a == 2"),
+            "Or(Not(Not(a == 1)), Not(a == 2))"
+        );
+        assert_eq!(
+            tree("a == 1 AND a == 2 AND a == 3"),
+            "And(And(a == 1, a == 2), a == 3)"
+        );
+    }
 }
@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use tracing::instrument;
+
+use crate::ast::{Ast, Procedure, ProcedureKind, Statement, StatementKind};
+use crate::error::{CompileError, CompileWarning};
+use crate::span::Span;
+
+struct Declaration {
+    span: Span,
+    scope: Vec<usize>,
+    used: bool,
+}
+
+struct Jump<'a> {
+    /// `GOTO` or `GOSUB`.
+    keyword: &'static str,
+    label: &'a str,
+    span: Span,
+    scope: Vec<usize>,
+}
+
+/// Label resolution pass, run on the AST once parsing is done.
+///
+/// Every IF branch and WHILE or FOR body is a block with its own id, and `scope` is
+/// the path of blocks a statement is nested in. A GOTO may only target a
+/// label in its own block or an enclosing one: jumping into a block from
+/// outside would skip its condition and, in the emitted C, the declarations
+/// made inside it. A GOSUB may only target a label outside of any block.
+///
+/// The main program and every SUB or FUNCTION body are resolved on their own,
+/// as a label can only be jumped to from the body declaring it.
+#[derive(Default)]
+struct Resolver<'a> {
+    procedure: Option<&'a Procedure>,
+    labels: HashMap<&'a str, Declaration>,
+    jumps: Vec<Jump<'a>>,
+    returns: Vec<Span>,
+    scope: Vec<usize>,
+    blocks: usize,
+    errors: Vec<CompileError>,
+}
+
+/// Checks that labels are unique and that every GOTO and GOSUB jumps to a
+/// declared label it can reach, and reports RETURNs that can never have a
+/// GOSUB to return to or do not match their procedure. Returns the errors
+/// found and warnings for labels that are never jumped to.
+#[instrument(skip_all)]
+pub fn resolve(ast: &Ast) -> (Vec<CompileError>, Vec<CompileWarning>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+
+    let mut defined: HashMap<&str, Span> = HashMap::new();
+    for procedure in &ast.procedures {
+        match defined.get(procedure.name.as_str()) {
+            Some(first) => errors.push(CompileError::DuplicateDefinition {
+                name: procedure.name.clone(),
+                span: procedure.span,
+                first: *first,
+            }),
+            None => {
+                defined.insert(&procedure.name, procedure.span);
+            }
+        }
+
+        let mut resolver = Resolver {
+            procedure: Some(procedure),
+            ..Default::default()
+        };
+        resolver.statement(&procedure.body);
+        resolver.finish(&mut errors, &mut warnings);
+    }
+
+    let mut resolver = Resolver::default();
+
+    // The main program runs from the start with no GOSUB to return to, up to
+    // the first statement that may jump or loop elsewhere.
+    let mut stray_returns = Vec::new();
+    let mut falls_through = true;
+    for statement in &ast.program {
+        if falls_through && matches!(statement.kind, StatementKind::Return(None)) {
+            stray_returns.push(statement.span);
+        }
+        falls_through &= !may_divert(statement);
+        resolver.statement(statement);
+    }
+
+    if !resolver.jumps.iter().any(|jump| jump.keyword == "GOSUB") {
+        stray_returns = std::mem::take(&mut resolver.returns);
+    }
+    for span in stray_returns {
+        resolver
+            .errors
+            .push(CompileError::ReturnWithoutGosub { span });
+    }
+    resolver.finish(&mut errors, &mut warnings);
+
+    warnings.sort_by_key(|warning| warning.span().start);
+
+    (errors, warnings)
+}
+
+impl<'a> Resolver<'a> {
+    /// Matches the jumps of the body to its labels, adding what was found to
+    /// `errors` and `warnings`.
+    fn finish(mut self, errors: &mut Vec<CompileError>, warnings: &mut Vec<CompileWarning>) {
+        for jump in &self.jumps {
+            let Some(declaration) = self.labels.get_mut(jump.label) else {
+                self.errors.push(CompileError::UndeclaredLabel {
+                    keyword: jump.keyword,
+                    label: jump.label.to_string(),
+                    span: jump.span,
+                });
+                continue;
+            };
+
+            declaration.used = true;
+            if jump.keyword == "GOSUB" && !declaration.scope.is_empty() {
+                self.errors.push(CompileError::SubroutineInBlock {
+                    label: jump.label.to_string(),
+                    span: jump.span,
+                    target: declaration.span,
+                });
+            } else if !jump.scope.starts_with(&declaration.scope) {
+                self.errors.push(CompileError::JumpIntoBlock {
+                    label: jump.label.to_string(),
+                    span: jump.span,
+                    target: declaration.span,
+                });
+            }
+        }
+
+        errors.append(&mut self.errors);
+        warnings.extend(
+            self.labels
+                .iter()
+                .filter(|(_, declaration)| !declaration.used)
+                .map(|(label, declaration)| CompileWarning::UnusedLabel {
+                    label: label.to_string(),
+                    span: declaration.span,
+                }),
+        );
+    }
+
+    fn statement(&mut self, statement: &'a Statement) {
+        match &statement.kind {
+            StatementKind::Label(label) => match self.labels.get(label.as_str()) {
+                Some(first) => self.errors.push(CompileError::DuplicateLabel {
+                    label: label.clone(),
+                    span: statement.span,
+                    first: first.span,
+                }),
+                None => {
+                    self.labels.insert(
+                        label,
+                        Declaration {
+                            span: statement.span,
+                            scope: self.scope.clone(),
+                            used: false,
+                        },
+                    );
+                }
+            },
+            StatementKind::Goto(label) => self.jumps.push(Jump {
+                keyword: "GOTO",
+                label,
+                span: statement.span,
+                scope: self.scope.clone(),
+            }),
+            StatementKind::Gosub(_) if self.procedure.is_some() => {
+                self.errors.push(CompileError::GosubInProcedure {
+                    span: statement.span,
+                })
+            }
+            StatementKind::Gosub(label) => self.jumps.push(Jump {
+                keyword: "GOSUB",
+                label,
+                span: statement.span,
+                scope: self.scope.clone(),
+            }),
+            StatementKind::Return(value) => match (self.procedure, value) {
+                (None, None) => self.returns.push(statement.span),
+                (Some(procedure), None) if procedure.kind == ProcedureKind::Function => {
+                    self.errors.push(CompileError::InvalidReturn {
+                        function: Some(procedure.name.clone()),
+                        span: statement.span,
+                    })
+                }
+                (
+                    None
+                    | Some(Procedure {
+                        kind: ProcedureKind::Sub,
+                        ..
+                    }),
+                    Some(_),
+                ) => self.errors.push(CompileError::InvalidReturn {
+                    function: None,
+                    span: statement.span,
+                }),
+                _ => (),
+            },
+            StatementKind::If(_, then, otherwise) => {
+                self.block(then);
+                if let Some(otherwise) = otherwise {
+                    self.block(otherwise);
+                }
+            }
+            StatementKind::While(_, body) | StatementKind::For { body, .. } => self.block(body),
+            StatementKind::Statement(first, rest) => {
+                self.statement(first);
+                self.statement(rest);
+            }
+            StatementKind::Print(_)
+            | StatementKind::PrintStr(_)
+            | StatementKind::Dim(..)
+            | StatementKind::Let(..)
+            | StatementKind::Input(_)
+            | StatementKind::Call(..)
+            | StatementKind::End => (),
+        }
+    }
+
+    fn block(&mut self, body: &'a Statement) {
+        self.blocks += 1;
+        self.scope.push(self.blocks);
+        self.statement(body);
+        self.scope.pop();
+    }
+}
+
+/// Whether control may continue somewhere other than the next statement once
+/// `statement` starts running. Loops count, as they may never end.
+fn may_divert(statement: &Statement) -> bool {
+    match &statement.kind {
+        StatementKind::Goto(_)
+        | StatementKind::Return(_)
+        | StatementKind::While(..)
+        | StatementKind::For { .. } => true,
+        StatementKind::If(_, then, otherwise) => {
+            may_divert(then) || otherwise.as_deref().is_some_and(may_divert)
+        }
+        StatementKind::Statement(first, rest) => may_divert(first) || may_divert(rest),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    fn resolve_source(source: &str) -> (Vec<CompileError>, Vec<CompileWarning>) {
+        let mut lexer = Lexer::new();
+        lexer.parse(source);
+        let mut parser = Parser::new();
+        let result = parser.check(lexer);
+        let resolved = resolve(&parser.ast);
+        // Anything else wrong with the program would hide what is tested.
+        if let Err(errors) = result {
+            assert_eq!(errors.len(), resolved.0.len(), "{errors:?}");
+        }
+        resolved
+    }
+
+    fn errors_in(source: &str) -> Vec<CompileError> {
+        resolve_source(source).0
+    }
+
+    #[test]
+    fn backward_and_forward_gotos_are_resolved() {
+        let (errors, warnings) = resolve_source(
+            "LABEL top\nLET a = a + 1\nIF a > 3 THEN\nGOTO done\nENDIF\nGOTO top\nLABEL done\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn gotos_out_of_nested_blocks_are_resolved() {
+        let errors = errors_in(
+            "LABEL top\nWHILE 1 == 1 REPEAT\nIF 1 == 1 THEN\nGOTO top\nENDIF\nENDWHILE\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+
+    #[test]
+    fn undeclared_labels_are_rejected() {
+        let errors = errors_in("GOTO nowhere\nGOSUB missing\n");
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                CompileError::UndeclaredLabel { keyword: "GOTO", label: goto, span },
+                CompileError::UndeclaredLabel { keyword: "GOSUB", label: gosub, .. },
+            ] if goto == "nowhere" && gosub == "missing" && span.line == 1
+        ));
+    }
+
+    #[test]
+    fn jumps_into_blocks_are_rejected() {
+        let sources = [
+            "GOTO inside\nWHILE 1 == 1 REPEAT\nLABEL inside\nENDWHILE\n",
+            "GOTO inside\nIF 1 == 1 THEN\nLABEL inside\nENDIF\n",
+            "GOTO inside\nIF 1 == 1 THEN\nPRINT 1\nELSE\nLABEL inside\nENDIF\n",
+            "IF 1 == 1 THEN\nGOTO inside\nELSE\nLABEL inside\nENDIF\n",
+            "FOR i = 1 TO 2\nLABEL inside\nNEXT\nGOTO inside\n",
+        ];
+        for source in sources {
+            assert!(
+                matches!(
+                    errors_in(source).as_slice(),
+                    [CompileError::JumpIntoBlock { label, .. }] if label == "inside"
+                ),
+                "{source:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn subroutines_must_be_outside_of_blocks() {
+        let errors = errors_in("GOSUB sub\nIF 1 == 1 THEN\nLABEL sub\nRETURN\nENDIF\n");
+        assert!(matches!(
+            errors.as_slice(),
+            [CompileError::SubroutineInBlock { label, .. }] if label == "sub"
+        ));
+    }
+
+    #[test]
+    fn unused_labels_are_warned_about() {
+        let (errors, warnings) = resolve_source("LABEL used\nLABEL unused\nGOTO used\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert!(matches!(
+            warnings.as_slice(),
+            [CompileWarning::UnusedLabel { label, span }] if label == "unused" && span.line == 2
+        ));
+    }
+
+    #[test]
+    fn duplicate_labels_are_rejected() {
+        let errors = errors_in("LABEL twice\nLABEL twice\nGOTO twice\n");
+        assert!(matches!(
+            errors.as_slice(),
+            [CompileError::DuplicateLabel { label, span, first }]
+                if label == "twice" && span.line == 2 && first.line == 1
+        ));
+    }
+
+    #[test]
+    fn return_without_gosub_is_rejected() {
+        let errors = errors_in("PRINT 1\nRETURN\n");
+        assert!(matches!(
+            errors.as_slice(),
+            [CompileError::ReturnWithoutGosub { span }] if span.line == 2
+        ));
+
+        // The program reaches the RETURN before any GOSUB could run.
+        let errors = errors_in("RETURN\nGOSUB sub\nGOTO done\nLABEL sub\nRETURN\nLABEL done\n");
+        assert!(matches!(
+            errors.as_slice(),
+            [CompileError::ReturnWithoutGosub { span }] if span.line == 1
+        ));
+    }
+
+    #[test]
+    fn return_after_gosub_is_resolved() {
+        let errors = errors_in("GOSUB sub\nGOTO done\nLABEL sub\nPRINT 1\nRETURN\nLABEL done\n");
+        assert!(errors.is_empty(), "{errors:?}");
+    }
+}
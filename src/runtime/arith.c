@@ -0,0 +1,50 @@
+#include <limits.h>
+#include <stdlib.h>
+
+/*
+ * Integer arithmetic runtime of the Tiny Basic compiler, included in the
+ * emitted C of programs doing arithmetic on integers.
+ *
+ * Overflow wraps around like in the interpreter, where it would be undefined
+ * behaviour on C's signed integers, by computing on unsigned ones.
+ */
+
+static long long tb_add(long long a, long long b) {
+    return (long long)((unsigned long long)a + (unsigned long long)b);
+}
+
+static long long tb_sub(long long a, long long b) {
+    return (long long)((unsigned long long)a - (unsigned long long)b);
+}
+
+static long long tb_mul(long long a, long long b) {
+    return (long long)((unsigned long long)a * (unsigned long long)b);
+}
+
+static long long tb_neg(long long a) {
+    return (long long)(0ULL - (unsigned long long)a);
+}
+
+/*
+ * Adds `step` to a FOR loop variable, or returns 0 and leaves it when the sum
+ * would wrap around, as it is then past any limit.
+ */
+static int tb_step(long long *var, long long step) {
+    if (step > 0 ? *var > LLONG_MAX - step : *var < LLONG_MIN - step) {
+        return 0;
+    }
+    *var += step;
+    return 1;
+}
+
+/* Stops the program on a zero divisor. Dividing LLONG_MIN by -1 wraps. */
+static long long tb_div(long long a, long long b, int line) {
+    if (b == 0) {
+        fprintf(stderr, "error: attempt to divide by zero on line %d\n", line);
+        exit(1);
+    }
+    if (b == -1) {
+        return tb_neg(a);
+    }
+    return a / b;
+}
@@ -0,0 +1,87 @@
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * Array runtime of the Tiny Basic compiler, included in the emitted C of
+ * programs that use DIM.
+ *
+ * Every array has a `size` array holding the number of elements of each
+ * dimension, all 0 until its DIM runs. Elements are stored flattened, row
+ * by row. String elements are owned heap strings, NULL standing for "".
+ */
+
+/* Sets the sizes of an array from the highest index of each dimension in
+ * `dims`, returns its number of elements. */
+static size_t tb_dim(long long *size, const long long *dims, int n, const char *name, int line) {
+    size_t count = 1;
+    for (int i = 0; i < n; i++) {
+        if (dims[i] < 0) {
+            fprintf(stderr, "error: negative size %lld in DIM of `%s` on line %d\n", dims[i], name,
+                    line);
+            exit(1);
+        }
+        if ((unsigned long long)dims[i] >= SIZE_MAX / 16 / count) {
+            fprintf(stderr, "error: array `%s` is too large on line %d\n", name, line);
+            exit(1);
+        }
+        count *= (size_t)dims[i] + 1;
+    }
+    for (int i = 0; i < n; i++) {
+        size[i] = dims[i] + 1;
+    }
+    return count;
+}
+
+static size_t tb_count(const long long *size, int n) {
+    size_t count = 1;
+    for (int i = 0; i < n; i++) {
+        count *= (size_t)size[i];
+    }
+    return count;
+}
+
+static void *tb_array_alloc(size_t count, size_t elem, const char *name, int line) {
+    void *memory = calloc(count, elem);
+    if (memory == NULL) {
+        fprintf(stderr, "error: array `%s` is too large on line %d\n", name, line);
+        exit(1);
+    }
+    return memory;
+}
+
+/* Position of the element at `index` in the flattened array. */
+static size_t tb_index(const long long *size, const long long *index, int n, const char *name,
+                       int line) {
+    if (size[0] == 0) {
+        fprintf(stderr, "error: array `%s` used before its DIM on line %d\n", name, line);
+        exit(1);
+    }
+
+    size_t flat = 0;
+    for (int i = 0; i < n; i++) {
+        if (index[i] < 0 || index[i] >= size[i]) {
+            fprintf(stderr, "error: index %lld out of bounds for ", index[i]);
+            if (n > 1) {
+                fprintf(stderr, "dimension %d of ", i + 1);
+            }
+            fprintf(stderr, "`%s`, which goes from 0 to %lld, on line %d\n", name, size[i] - 1,
+                    line);
+            exit(1);
+        }
+        flat = flat * (size_t)size[i] + (size_t)index[i];
+    }
+    return flat;
+}
+
+static char *tb_element(char *s) {
+    return s != NULL ? s : "";
+}
+
+/* Frees the elements of a string array, leaving it all "". */
+static void tb_clear(char **elements, size_t count) {
+    for (size_t i = 0; i < count; i++) {
+        free(elements[i]);
+        elements[i] = NULL;
+    }
+}
@@ -0,0 +1,22 @@
+#include <stdlib.h>
+
+/*
+ * Procedure call runtime of the Tiny Basic compiler, included in the emitted
+ * C of programs that define a SUB or FUNCTION.
+ *
+ * Every call counts itself in, so that runaway recursion stops with an error
+ * instead of overflowing the C stack.
+ */
+
+/* Same limit as the interpreter. */
+#define TB_MAX_CALL_DEPTH 10000
+
+static int tb_call_depth;
+
+static void tb_call(void) {
+    if (tb_call_depth == TB_MAX_CALL_DEPTH) {
+        fputs("error: procedure calls nested more than 10000 levels deep\n", stderr);
+        exit(1);
+    }
+    tb_call_depth++;
+}
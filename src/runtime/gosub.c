@@ -0,0 +1,32 @@
+#include <stdlib.h>
+
+/*
+ * GOSUB runtime of the Tiny Basic compiler, included in the emitted C of
+ * programs that use GOSUB.
+ *
+ * Every GOSUB in the program has a number. It is pushed before jumping to
+ * the subroutine, and RETURN pops it to pick the label to jump back to from
+ * a switch, so no computed goto is needed.
+ */
+
+/* Same limit as the interpreter. */
+#define TB_MAX_GOSUB_DEPTH 1000
+
+static int tb_gosub_stack[TB_MAX_GOSUB_DEPTH];
+static int tb_gosub_depth;
+
+static void tb_gosub(int site) {
+    if (tb_gosub_depth == TB_MAX_GOSUB_DEPTH) {
+        fputs("error: GOSUB nested more than 1000 levels deep\n", stderr);
+        exit(1);
+    }
+    tb_gosub_stack[tb_gosub_depth++] = site;
+}
+
+static int tb_return(void) {
+    if (tb_gosub_depth == 0) {
+        fputs("error: RETURN without GOSUB\n", stderr);
+        exit(1);
+    }
+    return tb_gosub_stack[--tb_gosub_depth];
+}
@@ -0,0 +1,101 @@
+#include <ctype.h>
+#include <errno.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * INPUT runtime of the Tiny Basic compiler, included in the emitted C of
+ * programs that read input.
+ *
+ * Each INPUT reads one line, like the interpreter. A number must take up the
+ * whole line but for surrounding whitespace, anything else stops the program
+ * with the interpreter's error, as does the end of input.
+ */
+
+static void *tb_grow(void *memory, size_t size) {
+    memory = realloc(memory, size);
+    if (memory == NULL) {
+        fputs("out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+/* Reads a line without its line break, stopping the program at end of input. */
+static char *tb_read_line(int line_number) {
+    size_t len = 0;
+    size_t cap = 64;
+    char *line = tb_grow(NULL, cap);
+    int c;
+
+    while ((c = getchar()) != EOF && c != '\n') {
+        if (len + 1 == cap) {
+            cap *= 2;
+            line = tb_grow(line, cap);
+        }
+        line[len++] = (char)c;
+    }
+
+    if (c == EOF && len == 0) {
+        fprintf(stderr, "error: unexpected end of input on line %d\n", line_number);
+        exit(1);
+    }
+    if (len > 0 && line[len - 1] == '\r') {
+        len--;
+    }
+    line[len] = '\0';
+    return line;
+}
+
+/* `text` without leading and trailing whitespace, trimmed in place. */
+static char *tb_trim(char *text) {
+    size_t len = strlen(text);
+    while (len > 0 && isspace((unsigned char)text[len - 1])) {
+        text[--len] = '\0';
+    }
+    while (isspace((unsigned char)*text)) {
+        text++;
+    }
+    return text;
+}
+
+static void tb_invalid_input(const char *expected, const char *text, int line_number) {
+    fprintf(stderr, "error: expected %s as input, got `%s` on line %d\n", expected, text,
+            line_number);
+    exit(1);
+}
+
+/* An integer with an optional sign, rejecting overflow rather than saturating. */
+static long long tb_input_int(int line_number) {
+    char *line = tb_read_line(line_number);
+    char *text = tb_trim(line);
+    char *end;
+
+    errno = 0;
+    long long value = strtoll(text, &end, 10);
+    if (end == text || *end != '\0' || errno == ERANGE) {
+        tb_invalid_input("an integer", text, line_number);
+    }
+    free(line);
+    return value;
+}
+
+/* Hexadecimal floats and NaN payloads are not numbers to the interpreter. */
+static double tb_input_float(int line_number) {
+    char *line = tb_read_line(line_number);
+    char *text = tb_trim(line);
+    char *end;
+
+    double value = strtod(text, &end);
+    if (end == text || *end != '\0' || strpbrk(text, "xX(") != NULL) {
+        tb_invalid_input("a number", text, line_number);
+    }
+    free(line);
+    return value;
+}
+
+static void tb_input_str(char **var, int line_number) {
+    char *line = tb_read_line(line_number);
+    free(*var);
+    *var = line;
+}
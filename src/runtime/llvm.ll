@@ -0,0 +1,212 @@
+; LLVM IR runtime of the Tiny Basic compiler, appended to the IR emitted for
+; every program. Printing and input go through libc like in the emitted C.
+
+@stdin = external global ptr
+@stderr = external global ptr
+
+declare i32 @printf(ptr, ...)
+declare i32 @fprintf(ptr, ptr, ...)
+declare i32 @fputs(ptr, ptr)
+declare i64 @getline(ptr, ptr, ptr)
+declare i64 @strlen(ptr)
+declare ptr @strpbrk(ptr, ptr)
+declare i32 @isspace(i32)
+declare i64 @strtoll(ptr, ptr, i32)
+declare double @strtod(ptr, ptr)
+declare ptr @__errno_location()
+declare void @free(ptr)
+declare void @exit(i32) noreturn
+declare {i64, i1} @llvm.sadd.with.overflow.i64(i64, i64)
+
+@tb.print.int = private unnamed_addr constant [6 x i8] c"%lld\0A\00"
+@tb.print.float = private unnamed_addr constant [4 x i8] c"%g\0A\00"
+@tb.print.str = private unnamed_addr constant [3 x i8] c"%s\00"
+@tb.input.int = private unnamed_addr constant [11 x i8] c"an integer\00"
+@tb.input.float = private unnamed_addr constant [9 x i8] c"a number\00"
+@tb.input.invalid = private unnamed_addr constant [50 x i8] c"error: expected %s as input, got `%s` on line %d\0A\00"
+@tb.input.end = private unnamed_addr constant [43 x i8] c"error: unexpected end of input on line %d\0A\00"
+@tb.input.hex = private unnamed_addr constant [4 x i8] c"xX(\00"
+
+; Prints the message on stderr and stops the program.
+define internal void @tb_error(ptr %message) noreturn {
+  %stderr = load ptr, ptr @stderr
+  call i32 @fputs(ptr %message, ptr %stderr)
+  call void @exit(i32 1)
+  unreachable
+}
+
+; Each INPUT reads one line, like the interpreter. A number must take up the
+; whole line but for surrounding whitespace, anything else stops the program
+; with the interpreter's error, as does the end of input.
+
+; Reads a line into a buffer to be freed, stopping at end of input.
+define internal ptr @tb_read_line(i32 %line) {
+  %buffer = alloca ptr
+  %size = alloca i64
+  store ptr null, ptr %buffer
+  store i64 0, ptr %size
+  %stdin = load ptr, ptr @stdin
+  %read = call i64 @getline(ptr %buffer, ptr %size, ptr %stdin)
+  %ended = icmp slt i64 %read, 0
+  br i1 %ended, label %end, label %done
+end:
+  %stderr = load ptr, ptr @stderr
+  call i32 (ptr, ptr, ...) @fprintf(ptr %stderr, ptr @tb.input.end, i32 %line)
+  call void @exit(i32 1)
+  unreachable
+done:
+  %text = load ptr, ptr %buffer
+  ret ptr %text
+}
+
+; `text` without leading and trailing whitespace, trimmed in place.
+define internal ptr @tb_trim(ptr %text) {
+entry:
+  %length = call i64 @strlen(ptr %text)
+  br label %end
+end:
+  %n = phi i64 [ %length, %entry ], [ %last, %strip ]
+  %empty = icmp eq i64 %n, 0
+  br i1 %empty, label %start, label %check
+check:
+  %last = sub i64 %n, 1
+  %at = getelementptr i8, ptr %text, i64 %last
+  %c = load i8, ptr %at
+  %code = zext i8 %c to i32
+  %space = call i32 @isspace(i32 %code)
+  %trailing = icmp ne i32 %space, 0
+  br i1 %trailing, label %strip, label %start
+strip:
+  store i8 0, ptr %at
+  br label %end
+start:
+  %s = phi ptr [ %text, %end ], [ %text, %check ], [ %next, %skip ]
+  %d = load i8, ptr %s
+  %lead_code = zext i8 %d to i32
+  %lead_space = call i32 @isspace(i32 %lead_code)
+  %leading = icmp ne i32 %lead_space, 0
+  br i1 %leading, label %skip, label %done
+skip:
+  %next = getelementptr i8, ptr %s, i64 1
+  br label %start
+done:
+  ret ptr %s
+}
+
+define internal void @tb_invalid_input(ptr %expected, ptr %text, i32 %line) noreturn {
+  %stderr = load ptr, ptr @stderr
+  call i32 (ptr, ptr, ...) @fprintf(ptr %stderr, ptr @tb.input.invalid, ptr %expected, ptr %text, i32 %line)
+  call void @exit(i32 1)
+  unreachable
+}
+
+; An integer with an optional sign, rejecting overflow rather than saturating.
+define internal i64 @tb_input_int(i32 %line) {
+  %end = alloca ptr
+  %buffer = call ptr @tb_read_line(i32 %line)
+  %text = call ptr @tb_trim(ptr %buffer)
+  %errno = call ptr @__errno_location()
+  store i32 0, ptr %errno
+  %value = call i64 @strtoll(ptr %text, ptr %end, i32 10)
+  %rest = load ptr, ptr %end
+  %none = icmp eq ptr %rest, %text
+  %next = load i8, ptr %rest
+  %trailing = icmp ne i8 %next, 0
+  %error = load i32, ptr %errno
+  %range = icmp eq i32 %error, 34
+  %partial = or i1 %none, %trailing
+  %invalid = or i1 %partial, %range
+  br i1 %invalid, label %reject, label %accept
+reject:
+  call void @tb_invalid_input(ptr @tb.input.int, ptr %text, i32 %line)
+  unreachable
+accept:
+  call void @free(ptr %buffer)
+  ret i64 %value
+}
+
+; Hexadecimal floats and NaN payloads are not numbers to the interpreter.
+define internal double @tb_input_float(i32 %line) {
+  %end = alloca ptr
+  %buffer = call ptr @tb_read_line(i32 %line)
+  %text = call ptr @tb_trim(ptr %buffer)
+  %value = call double @strtod(ptr %text, ptr %end)
+  %rest = load ptr, ptr %end
+  %none = icmp eq ptr %rest, %text
+  %next = load i8, ptr %rest
+  %trailing = icmp ne i8 %next, 0
+  %hex = call ptr @strpbrk(ptr %text, ptr @tb.input.hex)
+  %foreign = icmp ne ptr %hex, null
+  %partial = or i1 %none, %trailing
+  %invalid = or i1 %partial, %foreign
+  br i1 %invalid, label %reject, label %accept
+reject:
+  call void @tb_invalid_input(ptr @tb.input.float, ptr %text, i32 %line)
+  unreachable
+accept:
+  call void @free(ptr %buffer)
+  ret double %value
+}
+
+; Every GOSUB in the program has a number. It is pushed before jumping to
+; the subroutine, and RETURN pops it to pick the block to jump back to from a
+; switch, like the C runtime does. Same limit as the interpreter.
+@tb.gosub.stack = internal global [1000 x i32] zeroinitializer
+@tb.gosub.depth = internal global i32 0
+@tb.gosub.too_deep = private unnamed_addr constant [48 x i8] c"error: GOSUB nested more than 1000 levels deep\0A\00"
+@tb.gosub.empty = private unnamed_addr constant [29 x i8] c"error: RETURN without GOSUB\0A\00"
+
+define internal void @tb_gosub(i32 %site) {
+  %depth = load i32, ptr @tb.gosub.depth
+  %full = icmp eq i32 %depth, 1000
+  br i1 %full, label %error, label %push
+error:
+  call void @tb_error(ptr @tb.gosub.too_deep)
+  unreachable
+push:
+  %slot = getelementptr [1000 x i32], ptr @tb.gosub.stack, i32 0, i32 %depth
+  store i32 %site, ptr %slot
+  %next = add i32 %depth, 1
+  store i32 %next, ptr @tb.gosub.depth
+  ret void
+}
+
+define internal i32 @tb_return() {
+  %depth = load i32, ptr @tb.gosub.depth
+  %empty = icmp eq i32 %depth, 0
+  br i1 %empty, label %error, label %pop
+error:
+  call void @tb_error(ptr @tb.gosub.empty)
+  unreachable
+pop:
+  %top = sub i32 %depth, 1
+  store i32 %top, ptr @tb.gosub.depth
+  %slot = getelementptr [1000 x i32], ptr @tb.gosub.stack, i32 0, i32 %top
+  %site = load i32, ptr %slot
+  ret i32 %site
+}
+
+; Every procedure call counts itself in, so that runaway recursion stops with
+; an error instead of overflowing the stack, and out as it returns.
+@tb.call.depth = internal global i32 0
+@tb.call.too_deep = private unnamed_addr constant [59 x i8] c"error: procedure calls nested more than 10000 levels deep\0A\00"
+
+define internal void @tb_call() {
+  %depth = load i32, ptr @tb.call.depth
+  %full = icmp eq i32 %depth, 10000
+  br i1 %full, label %error, label %enter
+error:
+  call void @tb_error(ptr @tb.call.too_deep)
+  unreachable
+enter:
+  %next = add i32 %depth, 1
+  store i32 %next, ptr @tb.call.depth
+  ret void
+}
+
+define internal void @tb_call_done() {
+  %depth = load i32, ptr @tb.call.depth
+  %next = sub i32 %depth, 1
+  store i32 %next, ptr @tb.call.depth
+  ret void
+}
@@ -0,0 +1,183 @@
+#include <ctype.h>
+#include <limits.h>
+#include <stdlib.h>
+#include <string.h>
+
+/*
+ * String runtime of the Tiny Basic compiler, included in the emitted C of
+ * programs that use strings.
+ *
+ * Every variable owns a private heap copy of its value, replaced on each
+ * assignment. Strings produced by operations are temporaries registered in
+ * a pool that the emitted code empties with tb_collect() after each
+ * statement, so loops do not leak. A procedure call gets a frame of its own
+ * in the pool, so it never frees the temporaries of its caller.
+ */
+
+static char **tb_temps;
+static size_t tb_temps_len;
+static size_t tb_temps_cap;
+/* Temporaries below this index belong to the callers of the running procedure. */
+static size_t tb_frame;
+
+static void *tb_alloc(size_t size) {
+    void *memory = malloc(size);
+    if (memory == NULL) {
+        fputs("out of memory\n", stderr);
+        exit(1);
+    }
+    return memory;
+}
+
+/* Owned copy of `s`, for variables. */
+static char *tb_new(const char *s) {
+    size_t size = strlen(s) + 1;
+    char *copy = tb_alloc(size);
+    memcpy(copy, s, size);
+    return copy;
+}
+
+static void tb_assign(char **var, const char *value) {
+    char *copy = tb_new(value);
+    free(*var);
+    *var = copy;
+}
+
+static char *tb_temp(char *s) {
+    if (tb_temps_len == tb_temps_cap) {
+        size_t cap = tb_temps_cap ? tb_temps_cap * 2 : 16;
+        char **temps = realloc(tb_temps, cap * sizeof *temps);
+        if (temps == NULL) {
+            fputs("out of memory\n", stderr);
+            exit(1);
+        }
+        tb_temps = temps;
+        tb_temps_cap = cap;
+    }
+    tb_temps[tb_temps_len++] = s;
+    return s;
+}
+
+static void tb_collect(void) {
+    while (tb_temps_len > tb_frame) {
+        free(tb_temps[--tb_temps_len]);
+    }
+}
+
+/* Starts the frame of a procedure call, returns the one to restore. */
+static size_t tb_enter(void) {
+    size_t caller = tb_frame;
+    tb_frame = tb_temps_len;
+    return caller;
+}
+
+static void tb_leave(size_t caller) {
+    tb_collect();
+    tb_frame = caller;
+}
+
+static char *tb_concat(const char *a, const char *b) {
+    size_t a_len = strlen(a);
+    size_t b_len = strlen(b);
+    char *result = tb_alloc(a_len + b_len + 1);
+    memcpy(result, a, a_len);
+    memcpy(result + a_len, b, b_len + 1);
+    return tb_temp(result);
+}
+
+static long long tb_len(const char *s) {
+    return (long long)strlen(s);
+}
+
+/* `len` bytes of `s` from the 0-based `start`, clamped to the string. */
+static char *tb_substr(const char *s, long long start, long long len) {
+    long long s_len = (long long)strlen(s);
+    if (start < 0) {
+        start = 0;
+    }
+    if (start > s_len) {
+        start = s_len;
+    }
+    if (len < 0) {
+        len = 0;
+    }
+    if (len > s_len - start) {
+        len = s_len - start;
+    }
+    char *result = tb_alloc((size_t)len + 1);
+    memcpy(result, s + start, (size_t)len);
+    result[len] = '\0';
+    return tb_temp(result);
+}
+
+static char *tb_left(const char *s, long long len) {
+    return tb_substr(s, 0, len);
+}
+
+/* MID$ counts positions from 1. */
+static char *tb_mid(const char *s, long long start, long long len) {
+    return tb_substr(s, start > LLONG_MIN ? start - 1 : start, len);
+}
+
+static char *tb_str_int(long long value) {
+    char buffer[32];
+    snprintf(buffer, sizeof buffer, "%lld", value);
+    return tb_temp(tb_new(buffer));
+}
+
+static char *tb_str_float(double value) {
+    char buffer[32];
+    snprintf(buffer, sizeof buffer, "%g", value);
+    return tb_temp(tb_new(buffer));
+}
+
+/* Number of decimal digits at the start of `s`. */
+static size_t tb_digits(const char *s) {
+    size_t len = 0;
+    while (s[len] >= '0' && s[len] <= '9') {
+        len++;
+    }
+    return len;
+}
+
+/*
+ * The decimal number at the start of `s`, like the interpreter: strtod also
+ * reads hexadecimal numbers, infinity and NaN, so it only gets the prefix.
+ */
+static double tb_val(const char *s) {
+    while (isspace((unsigned char)*s)) {
+        s++;
+    }
+
+    size_t len = *s == '+' || *s == '-';
+    size_t digits = tb_digits(s + len);
+    len += digits;
+    if (s[len] == '.') {
+        size_t fraction = tb_digits(s + len + 1);
+        digits += fraction;
+        len += 1 + fraction;
+    }
+    if (digits == 0) {
+        return 0.0;
+    }
+
+    if (s[len] == 'e' || s[len] == 'E') {
+        size_t sign = s[len + 1] == '+' || s[len + 1] == '-';
+        size_t exponent = tb_digits(s + len + 1 + sign);
+        if (exponent > 0) {
+            len += 1 + sign + exponent;
+        }
+    }
+
+    char *number = tb_alloc(len + 1);
+    memcpy(number, s, len);
+    number[len] = '\0';
+    double value = strtod(number, NULL);
+    free(number);
+    return value;
+}
+
+static int tb_eq(const char *a, const char *b) {
+    return strcmp(a, b) == 0;
+}
+
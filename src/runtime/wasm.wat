@@ -0,0 +1,54 @@
+  ;; WebAssembly runtime of the Tiny Basic compiler, placed in the module
+  ;; emitted for every program. Its messages take up the first KiB of memory,
+  ;; the strings of the program come after it.
+
+  (data (i32.const 0) "error: GOSUB nested more than 1000 levels deep\0a")
+  (data (i32.const 47) "error: RETURN without GOSUB\0a")
+  (data (i32.const 75) "error: procedure calls nested more than 10000 levels deep\0a")
+
+  ;; Prints the message at `offset` and stops the program with a trap, the
+  ;; host having no other way to end it early.
+  (func $tb_error (param $offset i32) (param $length i32)
+    (call $print_str (local.get $offset) (local.get $length))
+    unreachable)
+
+  ;; Integer division, stopping with the message at `offset` for a zero
+  ;; divisor. Dividing the most negative value by -1 wraps around like the
+  ;; interpreter, where `i64.div_s` would trap.
+  (func $tb_div (param $a i64) (param $b i64) (param $offset i32) (param $length i32) (result i64)
+    (if (i64.eqz (local.get $b))
+      (then (call $tb_error (local.get $offset) (local.get $length))))
+    (if (i64.eq (local.get $b) (i64.const -1))
+      (then (return (i64.sub (i64.const 0) (local.get $a)))))
+    (i64.div_s (local.get $a) (local.get $b)))
+
+  ;; A GOSUB calls the main program from its label and RETURN returns from
+  ;; that call, so only the depth is kept here. Same limit as the interpreter.
+  (global $tb_gosub_depth (mut i32) (i32.const 0))
+
+  ;; Set once the main program runs off its end, to leave every GOSUB call in
+  ;; progress.
+  (global $tb_ended (mut i32) (i32.const 0))
+
+  (func $tb_gosub
+    (if (i32.eq (global.get $tb_gosub_depth) (i32.const 1000))
+      (then (call $tb_error (i32.const 0) (i32.const 47))))
+    (global.set $tb_gosub_depth (i32.add (global.get $tb_gosub_depth) (i32.const 1))))
+
+  (func $tb_return
+    (if (i32.eqz (global.get $tb_gosub_depth))
+      (then (call $tb_error (i32.const 47) (i32.const 28))))
+    (global.set $tb_gosub_depth (i32.sub (global.get $tb_gosub_depth) (i32.const 1))))
+
+  ;; Every procedure call counts itself in, so that runaway recursion stops
+  ;; with an error instead of exhausting the host's stack, and out as it
+  ;; returns.
+  (global $tb_call_depth (mut i32) (i32.const 0))
+
+  (func $tb_call
+    (if (i32.eq (global.get $tb_call_depth) (i32.const 10000))
+      (then (call $tb_error (i32.const 75) (i32.const 58))))
+    (global.set $tb_call_depth (i32.add (global.get $tb_call_depth) (i32.const 1))))
+
+  (func $tb_call_done
+    (global.set $tb_call_depth (i32.sub (global.get $tb_call_depth) (i32.const 1))))
@@ -0,0 +1,321 @@
+# x86-64 runtime of the Tiny Basic compiler, appended to the assembly emitted
+# for every program. It talks to Linux with raw system calls, so programs are
+# linked with `ld` alone, without libc.
+#
+# Emitted code keeps nothing in registers across calls into the runtime,
+# which may clobber any register but %rbx, %rbp, %rsp and %r12 to %r15.
+
+# Same limits as the interpreter.
+	.set TB_MAX_GOSUB_DEPTH, 1000
+	.set TB_MAX_CALL_DEPTH, 10000
+	.set TB_BUFFER_SIZE, 4096
+
+	.bss
+	.balign 8
+tb_out_len:	.skip 8
+tb_in_pos:	.skip 8
+tb_in_len:	.skip 8
+tb_gosub_depth:	.skip 8
+tb_call_depth:	.skip 8
+tb_gosub_stack:	.skip 8 * TB_MAX_GOSUB_DEPTH
+tb_out:	.skip TB_BUFFER_SIZE
+tb_in:	.skip TB_BUFFER_SIZE
+tb_line:	.skip TB_BUFFER_SIZE
+
+	.section .rodata
+tb_gosub_too_deep_message:
+	.ascii "error: GOSUB nested more than 1000 levels deep\n"
+	.set TB_GOSUB_TOO_DEEP_LEN, . - tb_gosub_too_deep_message
+tb_return_without_gosub_message:
+	.ascii "error: RETURN without GOSUB\n"
+	.set TB_RETURN_WITHOUT_GOSUB_LEN, . - tb_return_without_gosub_message
+tb_call_too_deep_message:
+	.ascii "error: procedure calls nested more than 10000 levels deep\n"
+	.set TB_CALL_TOO_DEEP_LEN, . - tb_call_too_deep_message
+tb_invalid_int_message:
+	.ascii "error: expected an integer as input, got `"
+	.set TB_INVALID_INT_LEN, . - tb_invalid_int_message
+tb_end_of_input_message:
+	.ascii "error: unexpected end of input"
+	.set TB_END_OF_INPUT_LEN, . - tb_end_of_input_message
+tb_backquote:
+	.ascii "`"
+
+	.text
+
+# Exits with the status in %rdi once the output is written.
+tb_exit:
+	push %rdi
+	call tb_flush
+	pop %rdi
+	mov $231, %eax			# exit_group
+	syscall
+
+# Writes the %rdx bytes at %rsi to stderr and exits with status 1.
+tb_error:
+	push %rsi
+	push %rdx
+	call tb_flush
+	pop %rdx
+	pop %rsi
+	call tb_eprint
+	mov $1, %edi
+	jmp tb_exit
+
+# Writes the %rdx bytes at %rsi to stderr.
+tb_eprint:
+	mov $1, %eax			# write
+	mov $2, %edi
+	syscall
+	ret
+
+# Writes out the buffered output.
+tb_flush:
+	lea tb_out(%rip), %rsi
+	mov tb_out_len(%rip), %rdx
+1:	test %rdx, %rdx
+	jz 2f
+	mov $1, %eax			# write
+	mov $1, %edi
+	syscall
+	test %rax, %rax
+	jle 2f				# nothing more can be written
+	add %rax, %rsi
+	sub %rax, %rdx
+	jmp 1b
+2:	movq $0, tb_out_len(%rip)
+	ret
+
+# Buffers the %rdx bytes at %rsi for stdout.
+tb_print_str:
+	test %rdx, %rdx
+	jz 3f
+1:	mov tb_out_len(%rip), %rcx
+	cmp $TB_BUFFER_SIZE, %rcx
+	jne 2f
+	push %rsi
+	push %rdx
+	call tb_flush
+	pop %rdx
+	pop %rsi
+	xor %ecx, %ecx
+2:	movzbl (%rsi), %eax
+	lea tb_out(%rip), %rdi
+	mov %al, (%rdi,%rcx)
+	inc %rcx
+	mov %rcx, tb_out_len(%rip)
+	inc %rsi
+	dec %rdx
+	jnz 1b
+3:	ret
+
+# Prints the integer in %rdi on a line of its own.
+tb_print_int:
+	sub $32, %rsp
+	lea 31(%rsp), %rsi
+	movb $'\n', (%rsi)
+	mov %rdi, %rax
+	test %rax, %rax
+	jns 1f
+	neg %rax			# the most negative value stays itself, as unsigned it is right
+1:	mov $10, %ecx
+2:	xor %edx, %edx
+	div %rcx
+	add $'0', %dl
+	dec %rsi
+	mov %dl, (%rsi)
+	test %rax, %rax
+	jnz 2b
+	test %rdi, %rdi
+	jns 3f
+	dec %rsi
+	movb $'-', (%rsi)
+3:	lea 32(%rsp), %rdx
+	sub %rsi, %rdx
+	call tb_print_str
+	add $32, %rsp
+	ret
+
+# Returns the next byte of input in %eax, or -1 once input has ended.
+tb_getc:
+	mov tb_in_pos(%rip), %rcx
+	cmp tb_in_len(%rip), %rcx
+	jb 1f
+	xor %eax, %eax			# read
+	xor %edi, %edi
+	lea tb_in(%rip), %rsi
+	mov $TB_BUFFER_SIZE, %edx
+	syscall
+	test %rax, %rax
+	jle 2f
+	mov %rax, tb_in_len(%rip)
+	xor %ecx, %ecx
+1:	lea tb_in(%rip), %rsi
+	movzbl (%rsi,%rcx), %eax
+	inc %rcx
+	mov %rcx, tb_in_pos(%rip)
+	ret
+2:	mov $-1, %eax
+	ret
+
+# Reads a line holding an integer the way the interpreter does: surrounding
+# whitespace is allowed, anything else, a number out of range or the end of
+# input stops the program with an error ending in the %rdx bytes at %rsi,
+# " on line N\n". Returns the number in %rax. A line longer than the buffer
+# is taken as invalid, and cut short in the error.
+tb_input_int:
+	push %rbx
+	push %r12
+	push %r13
+	push %r14
+	mov %rsi, %r12
+	mov %rdx, %r13
+	call tb_flush
+	xor %ebx, %ebx			# length of the line
+	call tb_getc
+	cmp $-1, %eax
+	je 9f
+1:	cmp $-1, %eax
+	je 2f
+	cmp $'\n', %eax
+	je 2f
+	cmp $TB_BUFFER_SIZE, %rbx
+	jae 11f
+	lea tb_line(%rip), %rcx
+	mov %al, (%rcx,%rbx)
+	inc %rbx
+	jmp 12f
+11:	mov $TB_BUFFER_SIZE + 1, %ebx	# too long
+12:	call tb_getc
+	jmp 1b
+2:	xor %r14d, %r14d		# whether the line is too long
+	cmp $TB_BUFFER_SIZE, %rbx
+	jbe 3f
+	mov $1, %r14d
+	mov $TB_BUFFER_SIZE, %ebx
+3:	lea tb_line(%rip), %rsi
+	lea (%rsi,%rbx), %rdi
+4:	cmp %rdi, %rsi			# trim the start
+	je 5f
+	movzbl (%rsi), %eax
+	call tb_is_space
+	jne 5f
+	inc %rsi
+	jmp 4b
+5:	cmp %rdi, %rsi			# trim the end
+	je 6f
+	movzbl -1(%rdi), %eax
+	call tb_is_space
+	jne 6f
+	dec %rdi
+	jmp 5b
+6:	mov %rsi, %rbx			# the trimmed line, for the error
+	test %r14, %r14
+	mov %rdi, %r14
+	jnz 8f
+	xor %r8d, %r8d			# whether the number is negative
+	cmp %rdi, %rsi
+	je 8f
+	movzbl (%rsi), %eax
+	cmp $'-', %eax
+	jne 61f
+	mov $1, %r8d
+	inc %rsi
+	jmp 62f
+61:	cmp $'+', %eax
+	jne 62f
+	inc %rsi
+62:	cmp %rdi, %rsi			# a sign alone is no number
+	je 8f
+	xor %eax, %eax			# magnitude
+	mov $10, %r9d
+7:	cmp %rdi, %rsi
+	je 71f
+	movzbl (%rsi), %ecx
+	sub $'0', %ecx
+	cmp $9, %ecx
+	ja 8f
+	mul %r9
+	jc 8f
+	add %rcx, %rax
+	jc 8f
+	inc %rsi
+	jmp 7b
+71:	mov $0x7fffffffffffffff, %rcx	# one more when negative
+	add %r8, %rcx
+	cmp %rcx, %rax
+	ja 8f
+	test %r8, %r8
+	jz 72f
+	neg %rax
+72:	pop %r14
+	pop %r13
+	pop %r12
+	pop %rbx
+	ret
+8:	lea tb_invalid_int_message(%rip), %rsi
+	mov $TB_INVALID_INT_LEN, %edx
+	call tb_eprint
+	mov %rbx, %rsi
+	mov %r14, %rdx
+	sub %rbx, %rdx
+	call tb_eprint
+	lea tb_backquote(%rip), %rsi
+	mov $1, %edx
+	call tb_eprint
+	jmp 10f
+9:	lea tb_end_of_input_message(%rip), %rsi
+	mov $TB_END_OF_INPUT_LEN, %edx
+	call tb_eprint
+10:	mov %r12, %rsi
+	mov %r13, %rdx
+	jmp tb_error
+
+# Sets ZF if the byte in %eax is whitespace: a space, \t, \n, \v, \f or \r.
+tb_is_space:
+	cmp $' ', %eax
+	je 1f
+	lea -9(%rax), %ecx
+	cmp $4, %ecx
+	ja 1f
+	cmp %ecx, %ecx
+1:	ret
+
+# Saves the address in %rax for the next RETURN to jump back to.
+tb_gosub:
+	mov tb_gosub_depth(%rip), %rcx
+	cmp $TB_MAX_GOSUB_DEPTH, %rcx
+	je 1f
+	lea tb_gosub_stack(%rip), %rdx
+	mov %rax, (%rdx,%rcx,8)
+	inc %rcx
+	mov %rcx, tb_gosub_depth(%rip)
+	ret
+1:	lea tb_gosub_too_deep_message(%rip), %rsi
+	mov $TB_GOSUB_TOO_DEEP_LEN, %edx
+	jmp tb_error
+
+# Returns the address saved by the last GOSUB in %rax.
+tb_return:
+	mov tb_gosub_depth(%rip), %rcx
+	test %rcx, %rcx
+	jz 1f
+	dec %rcx
+	mov %rcx, tb_gosub_depth(%rip)
+	lea tb_gosub_stack(%rip), %rdx
+	mov (%rdx,%rcx,8), %rax
+	ret
+1:	lea tb_return_without_gosub_message(%rip), %rsi
+	mov $TB_RETURN_WITHOUT_GOSUB_LEN, %edx
+	jmp tb_error
+
+# Counts a procedure call in, stopping runaway recursion before it overflows
+# the stack. Procedures count themselves out as they return.
+tb_call:
+	incq tb_call_depth(%rip)
+	cmpq $TB_MAX_CALL_DEPTH, tb_call_depth(%rip)
+	jg 1f
+	ret
+1:	lea tb_call_too_deep_message(%rip), %rsi
+	mov $TB_CALL_TOO_DEEP_LEN, %edx
+	jmp tb_error
@@ -0,0 +1,252 @@
+use std::fs::OpenOptions;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use tracing::{info, instrument};
+
+use crate::error::CompileError;
+
+/// Compilers tried in order when neither `--cc` nor `CC` name one.
+const CANDIDATES: &[&str] = &["cc", "gcc", "clang"];
+
+/// Flags passed to every invocation. Unused labels, variables and runtime
+/// functions are normal in emitted code, so they are not worth warning about.
+const WARNING_FLAGS: &[&str] = &[
+    "-Wall",
+    "-Wno-unused-label",
+    "-Wno-unused-variable",
+    "-Wno-unused-function",
+];
+
+/// System C compiler used to turn emitted C into a native executable.
+pub struct Toolchain {
+    pub compiler: String,
+    pub opt_level: u8,
+}
+
+impl Toolchain {
+    /// Picks the compiler named by `compiler`, then by the `CC` environment
+    /// variable, then the first of `cc`, `gcc` and `clang` found on `PATH`.
+    pub fn locate(compiler: Option<&str>, opt_level: u8) -> Result<Toolchain, CompileError> {
+        let requested = compiler
+            .map(str::to_string)
+            .or_else(|| std::env::var("CC").ok().filter(|cc| !cc.is_empty()));
+
+        let compiler = match requested {
+            Some(compiler) => compiler,
+            None => CANDIDATES
+                .iter()
+                .find(|candidate| find_in_path(candidate).is_some())
+                .ok_or(CompileError::NoCCompiler)?
+                .to_string(),
+        };
+
+        Ok(Toolchain {
+            compiler,
+            opt_level,
+        })
+    }
+
+    /// Compiles the C file at `source` into an executable at `output`.
+    #[instrument(skip_all)]
+    pub fn compile(&self, source: &Path, output: &Path) -> Result<(), CompileError> {
+        let mut command = Command::new(&self.compiler);
+        command
+            .arg(format!("-O{}", self.opt_level))
+            .args(WARNING_FLAGS)
+            .arg(source)
+            .arg("-o")
+            .arg(output);
+        info!("{command:?}");
+
+        let result = command.output().map_err(|err| {
+            std::io::Error::new(
+                err.kind(),
+                format!("couldn't run C compiler `{}`: {err}", self.compiler),
+            )
+        })?;
+
+        if !result.status.success() {
+            return Err(CompileError::CCompilerFailed {
+                compiler: self.compiler.clone(),
+                stderr: String::from_utf8_lossy(&result.stderr)
+                    .trim_end()
+                    .to_string(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Compiles the LLVM IR file at `source` into an executable at `output`,
+    /// with `clang` when it is on `PATH`. Otherwise `llc` compiles it to a
+    /// temporary object file that the C compiler links with libc.
+    #[instrument(skip_all)]
+    pub fn compile_ir(&self, source: &Path, output: &Path) -> Result<(), CompileError> {
+        let opt_level = format!("-O{}", self.opt_level);
+        if find_in_path("clang").is_some() {
+            return run_tool(
+                Command::new("clang")
+                    .arg(&opt_level)
+                    .arg("-Wno-override-module")
+                    .args(opaque_pointer_flags("clang", &["-mllvm"]))
+                    .arg(source)
+                    .arg("-o")
+                    .arg(output),
+            );
+        }
+
+        let object = TempFile::new("o")?;
+        run_tool(
+            Command::new("llc")
+                .arg(&opt_level)
+                .args(opaque_pointer_flags("llc", &[]))
+                .args(["-filetype=obj", "-relocation-model=pic"])
+                .arg(source)
+                .arg("-o")
+                .arg(object.path()),
+        )?;
+        run_tool(
+            Command::new(&self.compiler)
+                .arg(object.path())
+                .arg("-o")
+                .arg(output),
+        )
+    }
+}
+
+/// Assembles the x86-64 assembly at `source` with `as` and links it with `ld`
+/// into an executable at `output`. No C compiler or libc is involved, the
+/// object file in between is a temporary file.
+#[instrument(skip_all)]
+pub fn assemble(source: &Path, output: &Path) -> Result<(), CompileError> {
+    let object = TempFile::new("o")?;
+    run_tool(Command::new("as").arg(source).arg("-o").arg(object.path()))?;
+    run_tool(Command::new("ld").arg(object.path()).arg("-o").arg(output))
+}
+
+/// The emitted IR uses opaque pointers, `ptr`, which LLVM reads by default
+/// from version 15 on. Version 14 needs them turned on with a flag, passed
+/// after `prefix`.
+fn opaque_pointer_flags(tool: &str, prefix: &[&'static str]) -> Vec<&'static str> {
+    let version = Command::new(tool)
+        .arg("--version")
+        .output()
+        .ok()
+        .and_then(|output| llvm_version(&String::from_utf8_lossy(&output.stdout)));
+    match version {
+        Some(14) => prefix.iter().copied().chain(["-opaque-pointers"]).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Major version in the `--version` output of an LLVM tool, e.g. 14 from
+/// `Ubuntu LLVM version 14.0.0`.
+fn llvm_version(output: &str) -> Option<u32> {
+    let (_, rest) = output.split_once("version ")?;
+    rest.split(|c: char| !c.is_ascii_digit())
+        .next()?
+        .parse()
+        .ok()
+}
+
+/// Runs a tool of the assembly or LLVM backend, its errors on stderr.
+fn run_tool(command: &mut Command) -> Result<(), CompileError> {
+    info!("{command:?}");
+    let tool = command.get_program().to_string_lossy().into_owned();
+
+    let result = command
+        .output()
+        .map_err(|err| std::io::Error::new(err.kind(), format!("couldn't run `{tool}`: {err}")))?;
+
+    if !result.status.success() {
+        return Err(CompileError::ToolFailed {
+            tool,
+            stderr: String::from_utf8_lossy(&result.stderr)
+                .trim_end()
+                .to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// File in the system's temporary directory holding an intermediate of a
+/// build, so that nothing next to the executable is overwritten. It is
+/// removed when dropped.
+pub struct TempFile {
+    path: PathBuf,
+}
+
+impl TempFile {
+    /// Creates an empty file with a name no other file has, ending in
+    /// `extension`.
+    pub fn new(extension: &str) -> std::io::Result<TempFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let path = std::env::temp_dir().join(format!(
+                "tinybasic-{}-{}.{extension}",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(_) => return Ok(TempFile { path }),
+                Err(err) if err.kind() == ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(std::io::Error::new(
+                        err.kind(),
+                        format!("couldn't create `{}`: {err}", path.display()),
+                    ))
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn find_in_path(program: &str) -> Option<PathBuf> {
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(program))
+        .find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn temp_files_are_distinct_and_removed_on_drop() {
+        let first = TempFile::new("c").unwrap();
+        let second = TempFile::new("c").unwrap();
+        assert_ne!(first.path(), second.path());
+        assert!(first.path().starts_with(std::env::temp_dir()));
+
+        let path = first.path().to_path_buf();
+        assert!(path.is_file());
+        drop(first);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn llvm_version_is_read_from_llc_and_clang() {
+        let llc = "LLVM (http://llvm.org/):\n  LLVM version 14.0.0\n  Optimized build.\n";
+        assert_eq!(llvm_version(llc), Some(14));
+        assert_eq!(
+            llvm_version("Ubuntu clang version 17.0.6 (++20231209)\n"),
+            Some(17)
+        );
+        assert_eq!(llvm_version("no version here"), None);
+    }
+}
@@ -0,0 +1,904 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use tracing::instrument;
+
+use crate::ast::{
+    Ast, Builtin, Comparison, Condition, ConditionKind, Expression, ExpressionKind, Primary,
+    PrimaryKind, Procedure, ProcedureKind, Statement, StatementKind, Target, Term, TermKind, Unary,
+    UnaryKind,
+};
+use crate::error::CompileError;
+use crate::span::Span;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    Int,
+    Float,
+    Str,
+}
+
+impl Type {
+    /// Type a variable has because of its name: `name$` holds a string.
+    pub fn of_name(var: &str) -> Option<Type> {
+        var.ends_with('$').then_some(Type::Str)
+    }
+
+    pub(crate) fn is_numeric(self) -> bool {
+        matches!(self, Type::Int | Type::Float)
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Type::Int => "an integer",
+            Type::Float => "a float",
+            Type::Str => "a string",
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Int => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::Str => write!(f, "string"),
+        }
+    }
+}
+
+/// What a built-in function accepts as an argument.
+#[derive(Clone, Copy)]
+pub(crate) enum Param {
+    Str,
+    Int,
+    Number,
+}
+
+impl Param {
+    pub(crate) fn accepts(self, ty: Type) -> bool {
+        match self {
+            Param::Str => ty == Type::Str,
+            Param::Int => ty == Type::Int,
+            Param::Number => ty.is_numeric(),
+        }
+    }
+
+    fn describe(self) -> &'static str {
+        match self {
+            Param::Str => "a string",
+            Param::Int => "an integer",
+            Param::Number => "a number",
+        }
+    }
+}
+
+/// Parameters and result type of a built-in function.
+pub(crate) fn signature(builtin: Builtin) -> (&'static [Param], Type) {
+    match builtin {
+        Builtin::Len => (&[Param::Str], Type::Int),
+        Builtin::Left => (&[Param::Str, Param::Int], Type::Str),
+        Builtin::Mid => (&[Param::Str, Param::Int, Param::Int], Type::Str),
+        Builtin::Str => (&[Param::Number], Type::Str),
+        Builtin::Val => (&[Param::Str], Type::Float),
+    }
+}
+
+/// Types of the variables and array elements of the main program or of a
+/// procedure.
+#[derive(Debug, Clone, Default)]
+pub struct Scope {
+    pub variables: HashMap<String, Type>,
+    pub arrays: HashMap<String, Type>,
+}
+
+/// Types of the parameters, result and variables of a SUB or FUNCTION.
+#[derive(Debug, Clone, Default)]
+pub struct ProcedureTypes {
+    pub params: Vec<Type>,
+    /// `None` for a SUB.
+    pub result: Option<Type>,
+    /// Parameters, local variables and local arrays.
+    pub scope: Scope,
+}
+
+/// Limit on the passes over the program, see [`infer`].
+const MAX_PASSES: usize = 3;
+
+/// Type inference pass, run on the AST once parsing is done.
+///
+/// The first assignment of a variable in the source decides its type unless
+/// its name ends in `$`, INPUT into a fresh variable makes it an int. An int
+/// may later be assigned to a float variable, but not the other way around,
+/// as that would silently truncate. The elements of an array share a type,
+/// decided the same way by the first assignment to any of them.
+///
+/// Procedures work the same way: the first call decides the types of the
+/// parameters and the first RETURN the type of a FUNCTION. A procedure is
+/// inferred when the first call to it is reached, with its own variables.
+#[derive(Default)]
+struct Inference<'a> {
+    definitions: HashMap<&'a str, &'a Procedure>,
+    procedures: HashMap<String, ProcedureTypes>,
+    /// Result types FUNCTIONs are assumed to have until their first RETURN.
+    assumed: HashMap<String, Type>,
+    returned: HashSet<String>,
+    function: Option<&'a Procedure>,
+    types: Scope,
+    first: HashMap<String, Span>,
+    first_element: HashMap<String, Span>,
+    errors: Vec<CompileError>,
+}
+
+/// Infers the type of every variable and procedure, reporting assignments
+/// that conflict with it and operations on values of the wrong type.
+///
+/// A recursive FUNCTION can be called before its first RETURN is reached.
+/// If the type assumed for it then turns out wrong, the program is inferred
+/// again assuming the type found.
+#[instrument(skip_all)]
+pub fn infer(ast: &Ast) -> (Scope, HashMap<String, ProcedureTypes>, Vec<CompileError>) {
+    let mut assumed = HashMap::new();
+    for pass in 1.. {
+        let mut inference = Inference {
+            assumed,
+            ..Default::default()
+        };
+        for procedure in &ast.procedures {
+            inference
+                .definitions
+                .entry(&procedure.name)
+                .or_insert(procedure);
+        }
+        inference.program(ast);
+
+        let results: HashMap<String, Type> = inference
+            .procedures
+            .iter()
+            .filter_map(|(name, procedure)| Some((name.clone(), procedure.result?)))
+            .collect();
+        if results == inference.assumed || pass == MAX_PASSES {
+            return (inference.types, inference.procedures, inference.errors);
+        }
+        assumed = results;
+    }
+
+    unreachable!()
+}
+
+impl<'a> Inference<'a> {
+    fn checker(&mut self) -> Checker<'_> {
+        Checker {
+            types: &self.types,
+            procedures: &self.procedures,
+            errors: &mut self.errors,
+        }
+    }
+
+    fn program(&mut self, ast: &'a Ast) {
+        for statement in &ast.program {
+            self.statement(statement);
+        }
+
+        // Procedures that are never called are still checked, with integer
+        // parameters unless their names end in `$`.
+        for procedure in &ast.procedures {
+            if !self.procedures.contains_key(&procedure.name) {
+                self.procedure(procedure, &[]);
+            }
+        }
+    }
+
+    /// Infers the procedures called in `e` that are not yet, calls made in
+    /// the arguments of another call first.
+    fn calls(&mut self, e: &Expression) {
+        for (name, args) in e.function_calls() {
+            self.callee(name, args);
+        }
+    }
+
+    fn condition_calls(&mut self, c: &Condition) {
+        match &c.kind {
+            ConditionKind::Comparison(comp) => {
+                let (first, rest) = comp.chain();
+                self.calls(first);
+                for (_, expr) in rest {
+                    self.calls(expr);
+                }
+            }
+            ConditionKind::Not(cond) => self.condition_calls(cond),
+            ConditionKind::And(left, right) | ConditionKind::Or(left, right) => {
+                self.condition_calls(left);
+                self.condition_calls(right);
+            }
+        }
+    }
+
+    fn callee(&mut self, name: &str, args: &[Expression]) {
+        if self.procedures.contains_key(name) {
+            return;
+        }
+        let Some(&procedure) = self.definitions.get(name) else {
+            return;
+        };
+
+        let args: Vec<Type> = args
+            .iter()
+            .map(|arg| expression_type(arg, &self.types, &self.procedures))
+            .collect();
+        self.procedure(procedure, &args);
+    }
+
+    /// Infers the body of `procedure` called with arguments of type `args`.
+    fn procedure(&mut self, procedure: &'a Procedure, args: &[Type]) {
+        let params: Vec<Type> = procedure
+            .params
+            .iter()
+            .enumerate()
+            .map(|(i, param)| {
+                Type::of_name(param)
+                    .or(args.get(i).copied())
+                    .unwrap_or(Type::Int)
+            })
+            .collect();
+        let result = (procedure.kind == ProcedureKind::Function).then(|| {
+            let assumed = self
+                .assumed
+                .get(&procedure.name)
+                .copied()
+                .or(Type::of_name(&procedure.name))
+                .unwrap_or(Type::Int);
+            self.assumed.insert(procedure.name.clone(), assumed);
+            assumed
+        });
+        self.procedures.insert(
+            procedure.name.clone(),
+            ProcedureTypes {
+                params: params.clone(),
+                result,
+                scope: Scope::default(),
+            },
+        );
+
+        let types = Scope {
+            variables: procedure.params.iter().cloned().zip(params).collect(),
+            arrays: HashMap::new(),
+        };
+        let first = procedure
+            .params
+            .iter()
+            .map(|param| (param.clone(), procedure.span))
+            .collect();
+        let types = std::mem::replace(&mut self.types, types);
+        let first = std::mem::replace(&mut self.first, first);
+        let first_element = std::mem::take(&mut self.first_element);
+        let function = self.function.replace(procedure);
+
+        self.statement(&procedure.body);
+
+        self.function = function;
+        self.first = first;
+        self.first_element = first_element;
+        let scope = std::mem::replace(&mut self.types, types);
+        if let Some(types) = self.procedures.get_mut(&procedure.name) {
+            types.scope = scope;
+        }
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Print(expr) => {
+                self.calls(expr);
+                self.checker().expression(expr);
+            }
+            StatementKind::Dim(array, sizes) => {
+                for size in sizes {
+                    self.calls(size);
+                    let found = self.checker().expression(size);
+                    self.checker().expect(Param::Int, found, size.span);
+                }
+                self.types
+                    .arrays
+                    .entry(array.clone())
+                    .or_insert(Type::of_name(array).unwrap_or(Type::Int));
+            }
+            StatementKind::Let(target, expr) => {
+                self.calls(expr);
+                let found = self.checker().expression(expr);
+                self.assign_to(target, found, expr.span, statement.span);
+            }
+            StatementKind::Input(target) => {
+                let types = match target {
+                    Target::Variable(_) => &self.types.variables,
+                    Target::Element(..) => &self.types.arrays,
+                };
+                let found = types
+                    .get(target.name())
+                    .copied()
+                    .or(Type::of_name(target.name()))
+                    .unwrap_or(Type::Int);
+                self.assign_to(target, found, statement.span, statement.span);
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                self.condition_calls(condition);
+                self.checker().condition(condition);
+                self.statement(then);
+                if let Some(otherwise) = otherwise {
+                    self.statement(otherwise);
+                }
+            }
+            StatementKind::While(condition, body) => {
+                self.condition_calls(condition);
+                self.checker().condition(condition);
+                self.statement(body);
+            }
+            // The loop variable is a float if the start or the step is.
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => {
+                for expr in [Some(from), Some(to), step.as_ref()].into_iter().flatten() {
+                    self.calls(expr);
+                }
+                let mut checker = self.checker();
+                let (mut found, mut span) = (checker.number(from), from.span);
+                checker.number(to);
+                if let Some(step) = step {
+                    if checker.number(step) == Type::Float {
+                        (found, span) = (Type::Float, step.span);
+                    }
+                }
+                self.assign(var, found, span, statement.span);
+                self.statement(body);
+            }
+            StatementKind::Statement(first, rest) => {
+                self.statement(first);
+                self.statement(rest);
+            }
+            StatementKind::Return(Some(expr)) => {
+                self.calls(expr);
+                let found = self.checker().expression(expr);
+                // A RETURN with a value outside of a FUNCTION is reported by
+                // the resolver.
+                let Some(function) = self.function else {
+                    return;
+                };
+                let Some(ProcedureTypes {
+                    result: Some(result),
+                    ..
+                }) = self.procedures.get_mut(&function.name)
+                else {
+                    return;
+                };
+
+                if self.returned.insert(function.name.clone()) {
+                    *result = Type::of_name(&function.name).unwrap_or(found);
+                }
+                if !assignable(found, *result) {
+                    self.errors.push(CompileError::ExpectedType {
+                        expected: result.describe().to_string(),
+                        found,
+                        span: expr.span,
+                    });
+                }
+            }
+            StatementKind::Call(name, args) => {
+                for arg in args {
+                    self.calls(arg);
+                }
+                self.callee(name, args);
+                self.checker().call(name, args, statement.span);
+            }
+            StatementKind::PrintStr(_)
+            | StatementKind::Label(_)
+            | StatementKind::Goto(_)
+            | StatementKind::Gosub(_)
+            | StatementKind::Return(None)
+            | StatementKind::End => (),
+        }
+    }
+
+    fn assign_to(&mut self, target: &Target, found: Type, span: Span, statement: Span) {
+        match target {
+            Target::Variable(var) => self.assign(var, found, span, statement),
+            Target::Element(array, indices) => {
+                for index in indices {
+                    self.calls(index);
+                    let ty = self.checker().expression(index);
+                    self.checker().expect(Param::Int, ty, index.span);
+                }
+                self.assign_element(array, found, span, statement);
+            }
+        }
+    }
+
+    /// Arrays get a type when declared, so that reading elements before any
+    /// assignment works, but it is only final after the first assignment.
+    fn assign_element(&mut self, array: &str, found: Type, span: Span, statement: Span) {
+        let first = self.first_element.get(array).copied();
+        let expected = match first {
+            Some(_) => self.types.arrays.get(array).copied(),
+            None => Type::of_name(array),
+        };
+
+        match expected {
+            Some(expected) if !assignable(found, expected) => {
+                self.errors.push(CompileError::MismatchedTypes {
+                    variable: array.to_string(),
+                    expected,
+                    found,
+                    span,
+                    first,
+                });
+            }
+            _ if first.is_none() => {
+                self.first_element.insert(array.to_string(), statement);
+                self.types
+                    .arrays
+                    .insert(array.to_string(), expected.unwrap_or(found));
+            }
+            _ => (),
+        }
+    }
+
+    fn assign(&mut self, var: &str, found: Type, span: Span, statement: Span) {
+        let expected = self
+            .types
+            .variables
+            .get(var)
+            .copied()
+            .or(Type::of_name(var));
+
+        match expected {
+            Some(expected) if !assignable(found, expected) => {
+                self.errors.push(CompileError::MismatchedTypes {
+                    variable: var.to_string(),
+                    expected,
+                    found,
+                    span,
+                    first: self.first.get(var).copied(),
+                });
+            }
+            _ => {
+                self.first.entry(var.to_string()).or_insert(statement);
+            }
+        }
+
+        self.types
+            .variables
+            .entry(var.to_string())
+            .or_insert(expected.unwrap_or(found));
+    }
+}
+
+fn assignable(found: Type, expected: Type) -> bool {
+    found == expected || (found == Type::Int && expected == Type::Float)
+}
+
+/// Type of an expression given the types of the variables, arrays and
+/// functions in it. Arithmetic on an int and a float gives a float.
+pub fn expression_type(
+    e: &Expression,
+    types: &Scope,
+    procedures: &HashMap<String, ProcedureTypes>,
+) -> Type {
+    Checker {
+        types,
+        procedures,
+        errors: &mut Vec::new(),
+    }
+    .expression(e)
+}
+
+/// Type of a term, like [`expression_type`].
+pub fn term_type(t: &Term, types: &Scope, procedures: &HashMap<String, ProcedureTypes>) -> Type {
+    Checker {
+        types,
+        procedures,
+        errors: &mut Vec::new(),
+    }
+    .term(t)
+}
+
+/// Type of a signed operand, like [`expression_type`].
+pub fn unary_type(u: &Unary, types: &Scope, procedures: &HashMap<String, ProcedureTypes>) -> Type {
+    Checker {
+        types,
+        procedures,
+        errors: &mut Vec::new(),
+    }
+    .unary(u)
+}
+
+/// Computes the types of expressions, recording operations on values of the
+/// wrong type in `errors`.
+struct Checker<'a> {
+    types: &'a Scope,
+    procedures: &'a HashMap<String, ProcedureTypes>,
+    errors: &'a mut Vec<CompileError>,
+}
+
+impl Checker<'_> {
+    /// Checks a CALL, which may run a SUB or a FUNCTION.
+    fn call(&mut self, name: &str, args: &[Expression], span: Span) {
+        match self.procedures.get(name) {
+            Some(procedure) => self.arguments(name, &procedure.params, args, span),
+            None => {
+                for arg in args {
+                    self.expression(arg);
+                }
+                self.errors.push(CompileError::UndefinedProcedure {
+                    keyword: "SUB or FUNCTION",
+                    name: name.to_string(),
+                    span,
+                });
+            }
+        }
+    }
+
+    fn arguments(&mut self, name: &str, params: &[Type], args: &[Expression], span: Span) {
+        if params.len() != args.len() {
+            self.errors.push(CompileError::ArityMismatch {
+                function: name.to_string(),
+                expected: params.len()..=params.len(),
+                found: args.len(),
+                span,
+            });
+        }
+
+        for (i, arg) in args.iter().enumerate() {
+            let found = self.expression(arg);
+            match params.get(i) {
+                Some(&param) if !assignable(found, param) => {
+                    self.errors.push(CompileError::ExpectedType {
+                        expected: param.describe().to_string(),
+                        found,
+                        span: arg.span,
+                    });
+                }
+                _ => (),
+            }
+        }
+    }
+
+    fn condition(&mut self, c: &Condition) {
+        match &c.kind {
+            ConditionKind::Comparison(comp) => self.comparison(comp),
+            ConditionKind::Not(cond) => self.condition(cond),
+            ConditionKind::And(left, right) | ConditionKind::Or(left, right) => {
+                self.condition(left);
+                self.condition(right);
+            }
+        }
+    }
+
+    /// Strings may only be compared for (in)equality with other strings. The
+    /// result of a comparison is an int, so `a$ == b$ == 1` is fine.
+    fn comparison(&mut self, c: &Comparison) {
+        let (first, rest) = c.chain();
+
+        let mut left = self.expression(first);
+        if rest.is_empty() {
+            self.expect(Param::Number, left, first.span);
+        }
+
+        for (op, expr) in rest {
+            let right = self.expression(expr);
+            let valid = match (left, right) {
+                (Type::Str, Type::Str) => matches!(op, "==" | "!="),
+                _ => left.is_numeric() && right.is_numeric(),
+            };
+            if !valid {
+                self.errors.push(CompileError::InvalidOperands {
+                    op: op.to_string(),
+                    left,
+                    right,
+                    span: first.span.to(expr.span),
+                });
+            }
+            left = Type::Int;
+        }
+    }
+
+    fn expression(&mut self, e: &Expression) -> Type {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.term(term),
+            ExpressionKind::Add(expr, term) => {
+                let (left, right) = (self.expression(expr), self.term(term));
+                self.binary("+", left, right, e.span)
+            }
+            ExpressionKind::Sub(expr, term) => {
+                let (left, right) = (self.expression(expr), self.term(term));
+                self.binary("-", left, right, e.span)
+            }
+        }
+    }
+
+    fn term(&mut self, t: &Term) -> Type {
+        match &t.kind {
+            TermKind::Unary(u) => self.unary(u),
+            TermKind::Mul(term, unary) => {
+                let (left, right) = (self.term(term), self.unary(unary));
+                self.binary("*", left, right, t.span)
+            }
+            TermKind::Div(term, unary) => {
+                let (left, right) = (self.term(term), self.unary(unary));
+                self.binary("/", left, right, t.span)
+            }
+        }
+    }
+
+    /// `+` joins two strings, everything else is arithmetic on numbers.
+    fn binary(&mut self, op: &str, left: Type, right: Type, span: Span) -> Type {
+        match (left, right) {
+            (Type::Str, Type::Str) if op == "+" => Type::Str,
+            (Type::Int, Type::Int) => Type::Int,
+            (Type::Float, Type::Int | Type::Float) | (Type::Int, Type::Float) => Type::Float,
+            _ => {
+                self.errors.push(CompileError::InvalidOperands {
+                    op: op.to_string(),
+                    left,
+                    right,
+                    span,
+                });
+                left
+            }
+        }
+    }
+
+    fn unary(&mut self, u: &Unary) -> Type {
+        match &u.kind {
+            UnaryKind::Primary(p) => self.primary(p),
+            UnaryKind::Positive(operand) | UnaryKind::Negative(operand) => {
+                let ty = self.unary(operand);
+                self.expect(Param::Number, ty, operand.span);
+                ty
+            }
+        }
+    }
+
+    fn primary(&mut self, p: &Primary) -> Type {
+        match &p.kind {
+            PrimaryKind::Integer(_) | PrimaryKind::Number(_) => Type::Int,
+            PrimaryKind::Float(_) => Type::Float,
+            PrimaryKind::String(_) => Type::Str,
+            // Unassigned variables are reported by the parser already.
+            PrimaryKind::Variable(v) => self
+                .types
+                .variables
+                .get(v)
+                .copied()
+                .or(Type::of_name(v))
+                .unwrap_or(Type::Int),
+            PrimaryKind::Call(builtin, args) => {
+                let (params, result) = signature(*builtin);
+                // The parser reports calls with the wrong number of arguments.
+                for (param, arg) in params.iter().zip(args) {
+                    let ty = self.expression(arg);
+                    self.expect(*param, ty, arg.span);
+                }
+                result
+            }
+            PrimaryKind::FunctionCall(name, args) => {
+                let procedures = self.procedures;
+                match procedures.get(name) {
+                    Some(ProcedureTypes {
+                        params,
+                        result: Some(result),
+                        ..
+                    }) => {
+                        self.arguments(name, params, args, p.span);
+                        *result
+                    }
+                    sub => {
+                        for arg in args {
+                            self.expression(arg);
+                        }
+                        self.errors.push(match sub {
+                            Some(_) => CompileError::SubWithoutValue {
+                                name: name.clone(),
+                                span: p.span,
+                            },
+                            None => CompileError::UndefinedProcedure {
+                                keyword: "FUNCTION",
+                                name: name.clone(),
+                                span: p.span,
+                            },
+                        });
+                        Type::Int
+                    }
+                }
+            }
+            // Undeclared arrays are reported by the parser already.
+            PrimaryKind::Index(array, indices) => {
+                for index in indices {
+                    let ty = self.expression(index);
+                    self.expect(Param::Int, ty, index.span);
+                }
+                self.types
+                    .arrays
+                    .get(array)
+                    .copied()
+                    .or(Type::of_name(array))
+                    .unwrap_or(Type::Int)
+            }
+            PrimaryKind::Grouped(expr) => self.expression(expr),
+        }
+    }
+
+    /// Type of an expression that must be a number, an int if it is not.
+    fn number(&mut self, e: &Expression) -> Type {
+        let ty = self.expression(e);
+        self.expect(Param::Number, ty, e.span);
+        match ty {
+            Type::Str => Type::Int,
+            ty => ty,
+        }
+    }
+
+    fn expect(&mut self, param: Param, found: Type, span: Span) {
+        if !param.accepts(found) {
+            self.errors.push(CompileError::ExpectedType {
+                expected: param.describe().to_string(),
+                found,
+                span,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::Lexer;
+    use crate::parser::Parser;
+
+    type Inferred = (Scope, HashMap<String, ProcedureTypes>, Vec<CompileError>);
+
+    fn infer_source(source: &str) -> Inferred {
+        let mut lexer = Lexer::new();
+        lexer.parse(source);
+        let mut parser = Parser::new();
+        let result = parser.check(lexer);
+        let inferred = infer(&parser.ast);
+        // Anything else wrong with the program would hide what is tested.
+        if let Err(errors) = result {
+            assert_eq!(errors.len(), inferred.2.len(), "{errors:?}");
+        }
+        inferred
+    }
+
+    fn variable(scope: &Scope, name: &str) -> Type {
+        scope.variables[name]
+    }
+
+    #[test]
+    fn the_first_assignment_decides_the_type() {
+        let (types, _, errors) = infer_source(
+            "LET a = 1\nLET b = 2.5\nINPUT c\nLET s$ = \"x\"\nFOR i = 1 TO 2 STEP 0.5\nNEXT\n\
+             DIM d(3)\nLET d(0) = 1.5\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(variable(&types, "a"), Type::Int);
+        assert_eq!(variable(&types, "b"), Type::Float);
+        assert_eq!(variable(&types, "c"), Type::Int);
+        assert_eq!(variable(&types, "s$"), Type::Str);
+        assert_eq!(variable(&types, "i"), Type::Float);
+        assert_eq!(types.arrays["d"], Type::Float);
+    }
+
+    #[test]
+    fn ints_widen_to_floats() {
+        let (types, _, errors) = infer_source("LET a = 2.5\nLET a = 1\nLET b = a + 1\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(variable(&types, "a"), Type::Float);
+        assert_eq!(variable(&types, "b"), Type::Float);
+    }
+
+    #[test]
+    fn narrowing_and_mixing_strings_are_rejected() {
+        let (types, _, errors) =
+            infer_source("LET a = 1\nLET a = 2.5\nLET s$ = 1\nLET n = \"x\"\n");
+        assert_eq!(variable(&types, "a"), Type::Int);
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [
+                    CompileError::MismatchedTypes {
+                        variable: a,
+                        expected: Type::Int,
+                        found: Type::Float,
+                        first: Some(first),
+                        ..
+                    },
+                    CompileError::MismatchedTypes {
+                        variable: s,
+                        expected: Type::Str,
+                        found: Type::Int,
+                        first: None,
+                        ..
+                    },
+                ] if a == "a" && first.line == 1 && s == "s$"
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn operands_of_the_wrong_type_are_rejected() {
+        let (_, _, errors) = infer_source("LET s$ = \"a\"\nPRINT s$ - \"b\"\nPRINT s$ * 2\n");
+        assert!(
+            matches!(
+                errors.as_slice(),
+                [
+                    CompileError::InvalidOperands {
+                        op: minus,
+                        left: Type::Str,
+                        right: Type::Str,
+                        ..
+                    },
+                    CompileError::InvalidOperands {
+                        op: times,
+                        left: Type::Str,
+                        right: Type::Int,
+                        ..
+                    },
+                ] if minus == "-" && times == "*"
+            ),
+            "{errors:?}"
+        );
+    }
+
+    #[test]
+    fn procedures_are_inferred_from_their_first_call_and_return() {
+        let (_, procedures, errors) = infer_source(
+            "PRINT half(3)\nFUNCTION half(n)\nLET h = n / 2\nRETURN h\nENDFUNCTION\n\
+             SUB show(x, label$)\nPRINT x\nENDSUB\nCALL show(1.5, \"x\")\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(procedures["half"].params, [Type::Int]);
+        assert_eq!(procedures["half"].result, Some(Type::Int));
+        assert_eq!(procedures["show"].params, [Type::Float, Type::Str]);
+        assert_eq!(procedures["show"].result, None);
+    }
+
+    #[test]
+    fn recursive_functions_are_inferred_again_with_the_type_found() {
+        // `f` is assumed to give an int when it calls itself, until its first
+        // RETURN shows that it gives a float.
+        let (_, procedures, errors) = infer_source(
+            "PRINT f(3)\nFUNCTION f(n)\nIF n > 0 THEN\nLET x = f(n - 1)\nRETURN x * 1.5\nENDIF\n\
+             RETURN 0.5\nENDFUNCTION\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(procedures["f"].result, Some(Type::Float));
+        assert_eq!(variable(&procedures["f"].scope, "x"), Type::Float);
+    }
+
+    #[test]
+    fn mutually_recursive_functions_are_inferred_again() {
+        // The first pass finds that `a` gives a float, but `c` called it
+        // assuming an int, and so did `b` through `c`.
+        let (_, procedures, errors) = infer_source(
+            "PRINT a(3)\nFUNCTION a(n)\nRETURN b(n) * 1.5\nENDFUNCTION\n\
+             FUNCTION b(n)\nRETURN c(n)\nENDFUNCTION\n\
+             FUNCTION c(n)\nLET x = a(n - 1)\nRETURN x\nENDFUNCTION\n",
+        );
+        assert!(errors.is_empty(), "{errors:?}");
+        for function in ["a", "b", "c"] {
+            assert_eq!(procedures[function].result, Some(Type::Float), "{function}");
+        }
+        assert_eq!(variable(&procedures["c"].scope, "x"), Type::Float);
+    }
+
+    #[test]
+    fn functions_giving_only_their_own_value_are_ints() {
+        let (_, procedures, errors) =
+            infer_source("PRINT f(1)\nFUNCTION f(n)\nRETURN f(n)\nENDFUNCTION\n");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(procedures["f"].result, Some(Type::Int));
+    }
+}
@@ -0,0 +1,345 @@
+use std::io::{BufRead, Write};
+
+use tracing::{info, instrument};
+
+use crate::bytecode::{Instruction, Locals, Program};
+use crate::interpreter::{
+    arithmetic, call_builtin, compare_values, for_step, print, read_input, Array, RuntimeError,
+    Value, MAX_CALL_DEPTH, MAX_GOSUB_DEPTH,
+};
+use crate::types::Type;
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Variables and arrays of the main program or of a procedure call.
+struct Frame<'p> {
+    locals: &'p Locals,
+    variables: Vec<Value>,
+    arrays: Vec<Array>,
+    /// Address to continue at once the call returns.
+    return_to: usize,
+    /// Type of the result of a FUNCTION.
+    result: Option<Type>,
+}
+
+impl<'p> Frame<'p> {
+    fn new(locals: &'p Locals, return_to: usize, result: Option<Type>) -> Frame<'p> {
+        Frame {
+            locals,
+            variables: locals
+                .variables
+                .iter()
+                .map(|(_, ty)| Value::zero(*ty))
+                .collect(),
+            arrays: locals
+                .arrays
+                .iter()
+                .map(|(_, ty)| Array::new(*ty))
+                .collect(),
+            return_to,
+            result,
+        }
+    }
+}
+
+/// Runs programs compiled to bytecode, with the same behaviour and runtime
+/// errors as the tree-walking interpreter.
+///
+/// Every instruction counts as one step. A budget limits the steps a program
+/// may take, so that untrusted programs cannot run forever.
+pub struct Vm<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+    steps: u64,
+    budget: Option<u64>,
+}
+
+impl<R: BufRead, W: Write> Vm<R, W> {
+    pub fn new(input: R, output: W) -> Vm<R, W> {
+        Vm {
+            input,
+            output,
+            steps: 0,
+            budget: None,
+        }
+    }
+
+    /// Stops programs with an error once they have run `budget` instructions.
+    pub fn with_budget(mut self, budget: Option<u64>) -> Vm<R, W> {
+        self.budget = budget;
+        self
+    }
+
+    /// Number of instructions run so far.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    #[instrument(skip_all)]
+    pub fn run(&mut self, program: &Program) -> Result<()> {
+        let result = self.execute(program);
+        info!("ran {} instructions", self.steps);
+        self.output.flush()?;
+        result
+    }
+
+    fn execute(&mut self, program: &Program) -> Result<()> {
+        let mut stack: Vec<Value> = Vec::new();
+        let mut frames = vec![Frame::new(&program.main, 0, None)];
+        let mut gosubs: Vec<usize> = Vec::new();
+        let mut pc = 0;
+
+        loop {
+            let span = program.spans[pc];
+            if let Some(limit) = self.budget.filter(|limit| self.steps >= *limit) {
+                return Err(RuntimeError::StepLimit { limit, span });
+            }
+            self.steps += 1;
+
+            let instruction = program.code[pc];
+            pc += 1;
+            let frame = frames.last_mut().expect("the main program has a frame");
+
+            match instruction {
+                Instruction::Const(index) => stack.push(program.constants[index as usize].clone()),
+                Instruction::Load(var) => stack.push(frame.variables[var as usize].clone()),
+                Instruction::Store(var) => {
+                    let variable = &mut frame.variables[var as usize];
+                    *variable = pop(&mut stack).cast_like(variable);
+                }
+                Instruction::Step(var) => {
+                    let variable = &mut frame.variables[var as usize];
+                    let next = for_step(variable.clone(), pop(&mut stack));
+                    if let Some(next) = &next {
+                        *variable = next.clone().cast_like(variable);
+                    }
+                    stack.push(Value::Int(next.is_some().into()));
+                }
+                Instruction::Dim { array, dimensions } => {
+                    let sizes = pop_ints(&mut stack, dimensions);
+                    let name = &frame.locals.arrays[array as usize].0;
+                    frame.arrays[array as usize].dim(name, &sizes, span)?;
+                }
+                Instruction::LoadElement { array, indices } => {
+                    let indices = pop_ints(&mut stack, indices);
+                    let name = &frame.locals.arrays[array as usize].0;
+                    let array = &frame.arrays[array as usize];
+                    let position = array.position(name, &indices, span)?;
+                    stack.push(array.elements[position].clone());
+                }
+                Instruction::StoreElement { array, indices } => {
+                    let indices = pop_ints(&mut stack, indices);
+                    let name = &frame.locals.arrays[array as usize].0;
+                    let array = &mut frame.arrays[array as usize];
+                    let position = array.position(name, &indices, span)?;
+                    array.elements[position] = pop(&mut stack).cast_like(&array.zero);
+                }
+                Instruction::Pop => {
+                    pop(&mut stack);
+                }
+                Instruction::Add => {
+                    let (left, right) = pop_pair(&mut stack);
+                    stack.push(match (left, right) {
+                        (Value::Str(l), Value::Str(r)) => Value::Str([l, r].concat()),
+                        (l, r) => arithmetic(l, r, i64::wrapping_add, |l, r| l + r),
+                    });
+                }
+                Instruction::Sub => {
+                    let (left, right) = pop_pair(&mut stack);
+                    stack.push(arithmetic(left, right, i64::wrapping_sub, |l, r| l - r));
+                }
+                Instruction::Mul => {
+                    let (left, right) = pop_pair(&mut stack);
+                    stack.push(arithmetic(left, right, i64::wrapping_mul, |l, r| l * r));
+                }
+                Instruction::Div => {
+                    let (left, right) = pop_pair(&mut stack);
+                    if let (Value::Int(_), Value::Int(0)) = (&left, &right) {
+                        return Err(RuntimeError::DivisionByZero { span });
+                    }
+                    stack.push(arithmetic(left, right, i64::wrapping_div, |l, r| l / r));
+                }
+                Instruction::Neg => {
+                    let value = match pop(&mut stack) {
+                        Value::Int(v) => Value::Int(v.wrapping_neg()),
+                        Value::Float(v) => Value::Float(-v),
+                        Value::Str(_) => unreachable!("strings are not used as numbers"),
+                    };
+                    stack.push(value);
+                }
+                Instruction::Compare(relation) => {
+                    let (left, right) = pop_pair(&mut stack);
+                    let holds = compare_values(relation.operator(), left, right);
+                    stack.push(Value::Int(holds as i64));
+                }
+                Instruction::Builtin { builtin, args } => {
+                    let args = stack.split_off(stack.len() - args as usize);
+                    stack.push(call_builtin(builtin, &args));
+                }
+                Instruction::Jump(target) => pc = target as usize,
+                Instruction::JumpIfFalse(target) => {
+                    if !pop(&mut stack).is_true() {
+                        pc = target as usize;
+                    }
+                }
+                Instruction::JumpIfTrue(target) => {
+                    if pop(&mut stack).is_true() {
+                        pc = target as usize;
+                    }
+                }
+                Instruction::Gosub(target) => {
+                    if gosubs.len() == MAX_GOSUB_DEPTH {
+                        return Err(RuntimeError::GosubTooDeep { span });
+                    }
+                    gosubs.push(pc);
+                    pc = target as usize;
+                }
+                Instruction::ReturnSub => match gosubs.pop() {
+                    Some(address) => pc = address,
+                    None => return Err(RuntimeError::ReturnWithoutGosub { span }),
+                },
+                Instruction::Call(index) => {
+                    let procedure = &program.procedures[index as usize];
+                    let args = stack.split_off(stack.len() - procedure.params as usize);
+                    if frames.len() > MAX_CALL_DEPTH {
+                        return Err(RuntimeError::CallTooDeep { span });
+                    }
+
+                    let mut frame = Frame::new(&procedure.locals, pc, procedure.result);
+                    for (variable, arg) in frame.variables.iter_mut().zip(args) {
+                        *variable = arg.cast_like(variable);
+                    }
+                    frames.push(frame);
+                    pc = procedure.entry as usize;
+                }
+                Instruction::Return => {
+                    pc = frame.return_to;
+                    frames.pop();
+                }
+                Instruction::ReturnValue => {
+                    let zero = Value::zero(frame.result.expect("only a FUNCTION has a value"));
+                    let result = pop(&mut stack).cast_like(&zero);
+                    stack.push(result);
+                    pc = frame.return_to;
+                    frames.pop();
+                }
+                Instruction::Print => {
+                    print(&mut self.output, &pop(&mut stack))?;
+                }
+                Instruction::Input(ty) => {
+                    self.output.flush()?;
+                    stack.push(read_input(&mut self.input, &Value::zero(ty), span)?);
+                }
+                Instruction::Halt => return Ok(()),
+            }
+        }
+    }
+}
+
+fn pop(stack: &mut Vec<Value>) -> Value {
+    stack.pop().expect("the compiler balances the stack")
+}
+
+/// Pops the operands of a binary operator, the right one being on top.
+fn pop_pair(stack: &mut Vec<Value>) -> (Value, Value) {
+    let right = pop(stack);
+    (pop(stack), right)
+}
+
+/// Pops `count` type checked integers, in the order they were pushed.
+fn pop_ints(stack: &mut Vec<Value>, count: u32) -> Vec<i64> {
+    stack
+        .split_off(stack.len() - count as usize)
+        .into_iter()
+        .map(|value| match value {
+            Value::Int(value) => value,
+            _ => unreachable!("sizes and indices are type checked"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Interpreter;
+
+    fn run(source: &str, input: &str, budget: Option<u64>) -> (String, Result<()>) {
+        let parser = crate::check(source).expect("the program is valid");
+        let program = crate::bytecode::compile(&parser);
+        let mut output = Vec::new();
+        let result = Vm::new(input.as_bytes(), &mut output)
+            .with_budget(budget)
+            .run(&program);
+        (String::from_utf8(output).unwrap(), result)
+    }
+
+    /// Output of the tree-walking interpreter, which the VM must match.
+    fn interpret(source: &str, input: &str) -> String {
+        let parser = crate::check(source).unwrap();
+        let mut output = Vec::new();
+        let _ = Interpreter::new(input.as_bytes(), &mut output).run(&parser);
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        let programs = [
+            include_str!("../examples/control.tb"),
+            include_str!("../examples/primes.tb"),
+            include_str!("../examples/procedures.tb"),
+            "DIM a(3, 2)\nLET a(3, 2) = 7\nLET s$ = STR$(a(3, 2)) + \"!\"\n\
+             PRINT s$\nPRINT LEN(s$)\nPRINT 1.5 * 2\n",
+            "FOR i = 9223372036854775806 TO 9223372036854775807\nPRINT i\nNEXT\nPRINT i\n\
+             FOR f = 0.5 TO 2 STEP 0.75\nPRINT f\nNEXT\n",
+        ];
+        for program in programs {
+            let (output, result) = run(program, "", None);
+            result.unwrap();
+            assert_eq!(output, interpret(program, ""));
+        }
+    }
+
+    #[test]
+    fn input_is_checked_like_the_interpreter() {
+        let program = "INPUT n\nPRINT n\n";
+        let (output, result) = run(program, " +10 \n", None);
+        result.unwrap();
+        assert_eq!(output, "10\n");
+
+        let (_, result) = run(program, "10 apples\n", None);
+        assert!(matches!(result, Err(RuntimeError::InvalidInput { .. })));
+        let (_, result) = run(program, "", None);
+        assert!(matches!(result, Err(RuntimeError::EndOfInput { .. })));
+    }
+
+    #[test]
+    fn division_by_zero_points_at_the_line() {
+        let (output, result) = run("PRINT 1\nLET a = 0\nPRINT 7 / a\n", "", None);
+        assert_eq!(output, "1\n");
+        assert!(matches!(
+            result,
+            Err(RuntimeError::DivisionByZero { span }) if span.line == 3
+        ));
+    }
+
+    #[test]
+    fn budget_stops_endless_loops() {
+        let (_, result) = run("LABEL top\nGOTO top\n", "", Some(1000));
+        assert!(matches!(
+            result,
+            Err(RuntimeError::StepLimit { limit: 1000, .. })
+        ));
+    }
+
+    #[test]
+    fn steps_are_counted() {
+        let parser = crate::check("PRINT 1\n").unwrap();
+        let program = crate::bytecode::compile(&parser);
+        let mut vm = Vm::new(&b""[..], Vec::new());
+        vm.run(&program).unwrap();
+        assert!(vm.steps() > 0);
+
+        let mut limited = Vm::new(&b""[..], Vec::new()).with_budget(Some(vm.steps()));
+        limited.run(&program).unwrap();
+    }
+}
@@ -0,0 +1,804 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use tracing::instrument;
+
+use crate::ast::{
+    Comparison, Condition, ConditionKind, Expression, ExpressionKind, Primary, PrimaryKind,
+    Statement, StatementKind, Target, Term, TermKind, Unary, UnaryKind,
+};
+use crate::backend::{self, supported, unsupported};
+use crate::error::CompileError;
+use crate::parser::Parser;
+use crate::span::Span;
+use crate::types::{ProcedureTypes, Scope};
+
+/// Error messages and the GOSUB and call depth runtime, placed in every
+/// module.
+const RUNTIME: &str = include_str!("runtime/wasm.wat");
+
+/// Start of the strings of the program in memory, after the messages of the
+/// runtime.
+const STRINGS_START: usize = 1024;
+
+const PAGE_SIZE: usize = 65536;
+
+/// Name of the backend in errors.
+const BACKEND: &str = "WebAssembly";
+
+type Result<T> = std::result::Result<T, CompileError>;
+
+/// Compiles a checked program to a WebAssembly text module, to be run by a
+/// host such as a browser.
+///
+/// The module imports `print_i64`, printing a number on a line of its own,
+/// `print_str`, printing the UTF-8 bytes at an offset in the exported
+/// `memory` and of a length, and `input_i64`, returning the next number of
+/// input, from `env`. It exports `main`, which runs the program. Runtime
+/// errors print their message and trap.
+///
+/// WebAssembly only has structured control flow, so every statement list
+/// holding labels becomes a loop around a `br_table` dispatching on the
+/// segment to run, the statements from a label up to the next one. A GOTO
+/// sets the segment and branches back to the loop of the list holding its
+/// label, which encloses it. A GOSUB calls the main program from its label
+/// and RETURN returns from that call.
+///
+/// Like the assembly backend, only integers are supported: programs using
+/// floats, string values, built-in functions or arrays are rejected, PRINT
+/// of a string literal being the one use of strings allowed.
+#[instrument(skip_all)]
+pub fn compile(parser: &Parser) -> Result<String> {
+    let mut compiler = Compiler {
+        code: String::new(),
+        procedures: &parser.procedures,
+        types: &parser.types,
+        in_procedure: false,
+        locals: Vec::new(),
+        dispatchers: Vec::new(),
+        strings: HashMap::new(),
+        data: String::new(),
+        data_len: 0,
+        blocks: 0,
+        indent: 2,
+    };
+
+    let program: Vec<&Statement> = parser
+        .ast
+        .program
+        .iter()
+        .flat_map(Statement::block)
+        .collect();
+    compiler.statements(program, Some("$pc"))?;
+    compiler.line("i32.const 1");
+    compiler.line("global.set $tb_ended");
+    let main = compiler.function("$tb_main (param $pc i32)", &[]);
+
+    let mut procedures = Vec::new();
+    for procedure in &parser.ast.procedures {
+        procedures.push(compiler.procedure(
+            &procedure.name,
+            &procedure.params,
+            &procedure.body,
+            procedure.span,
+        )?);
+    }
+
+    let mut out = String::from(";; Generated by basic_compiler\n(module\n");
+    out += "  (import \"env\" \"print_i64\" (func $print_i64 (param i64)))\n";
+    out += "  (import \"env\" \"print_str\" (func $print_str (param i32 i32)))\n";
+    out += "  (import \"env\" \"input_i64\" (func $input_i64 (result i64)))\n";
+    let pages = (STRINGS_START + compiler.data_len).div_ceil(PAGE_SIZE);
+    let _ = writeln!(out, "  (memory (export \"memory\") {pages})");
+    if compiler.data_len > 0 {
+        let _ = writeln!(
+            out,
+            "  (data (i32.const {STRINGS_START}) \"{}\")",
+            compiler.data
+        );
+    }
+
+    let mut variables: Vec<&String> = parser.types.variables.keys().collect();
+    variables.sort();
+    for var in variables {
+        let _ = writeln!(out, "  (global {} (mut i64) (i64.const 0))", var_name(var));
+    }
+
+    out += "\n  (func (export \"main\")\n    i32.const 0\n    call $tb_main)\n";
+    out += "\n";
+    out += &main;
+    for procedure in procedures {
+        out += "\n";
+        out += &procedure;
+    }
+    out += "\n";
+    out += RUNTIME;
+    out += ")\n";
+    Ok(out)
+}
+
+/// Statement list holding labels, with the segment every label starts.
+struct Dispatcher<'a> {
+    /// Local holding the segment to run.
+    pc: String,
+    /// Loop branched to after setting `pc`.
+    name: String,
+    labels: HashMap<&'a str, usize>,
+}
+
+struct Compiler<'a> {
+    code: String,
+    procedures: &'a HashMap<String, ProcedureTypes>,
+    /// Types of the function being compiled.
+    types: &'a Scope,
+    /// Whether a procedure is being compiled, whose variables are locals
+    /// rather than globals.
+    in_procedure: bool,
+    /// Variables and hidden locals of the function being compiled, with
+    /// their types. Parameters among them are not declared again.
+    locals: Vec<(String, &'static str)>,
+    /// Statement lists with labels that enclose the statement being
+    /// compiled, innermost last.
+    dispatchers: Vec<Dispatcher<'a>>,
+    /// Offset and length in memory of every string.
+    strings: HashMap<String, (usize, usize)>,
+    /// Contents of the data segment holding the strings, escaped.
+    data: String,
+    data_len: usize,
+    blocks: usize,
+    indent: usize,
+}
+
+impl<'a> Compiler<'a> {
+    fn line(&mut self, instruction: &str) {
+        let _ = writeln!(self.code, "{:1$}{instruction}", "", 2 * self.indent);
+    }
+
+    fn new_block(&mut self) -> usize {
+        self.blocks += 1;
+        self.blocks
+    }
+
+    /// Wraps the code compiled so far in a function, declaring its locals.
+    fn function(&mut self, signature: &str, params: &[String]) -> String {
+        let mut function = format!("  (func {signature}\n");
+        for (local, ty) in self.locals.drain(..) {
+            if !params.contains(&local) {
+                let _ = writeln!(function, "    (local {local} {ty})");
+            }
+        }
+        function += &std::mem::take(&mut self.code);
+        function.truncate(function.trim_end().len());
+        function += ")\n";
+        function
+    }
+
+    /// Each procedure is a function taking its arguments as `i64`
+    /// parameters, and giving its result as an `i64`. Its variables start
+    /// out as zero.
+    fn procedure(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &'a Statement,
+        span: Span,
+    ) -> Result<String> {
+        let procedures = self.procedures;
+        let types = &procedures[name];
+        for ty in types.params.iter().chain(&types.result) {
+            supported(BACKEND, *ty, span)?;
+        }
+
+        let globals = std::mem::replace(&mut self.types, &types.scope);
+        self.in_procedure = true;
+        let mut variables: Vec<&String> = types.scope.variables.keys().collect();
+        variables.sort();
+        self.locals = variables
+            .into_iter()
+            .map(|var| (var_name(var), "i64"))
+            .collect();
+
+        self.line("call $tb_call");
+        self.statements(body.block(), None)?;
+        self.line("call $tb_call_done");
+        // Running off the end of a FUNCTION gives zero.
+        if types.result.is_some() {
+            self.line("i64.const 0");
+        }
+
+        let mut signature = fn_name(name);
+        for param in params {
+            let _ = write!(signature, " (param {} i64)", var_name(param));
+        }
+        if types.result.is_some() {
+            signature += " (result i64)";
+        }
+        let params: Vec<String> = params.iter().map(|param| var_name(param)).collect();
+        let function = self.function(&signature, &params);
+
+        self.types = globals;
+        self.in_procedure = false;
+        Ok(function)
+    }
+
+    /// Compiles a statement list. If it holds labels, `pc` is the local
+    /// holding the segment to start at, a new one starting at the first
+    /// statement if `None`.
+    fn statements(&mut self, statements: Vec<&'a Statement>, pc: Option<&str>) -> Result<()> {
+        let mut segments = vec![Vec::new()];
+        let mut labels = HashMap::new();
+        for statement in statements {
+            match &statement.kind {
+                StatementKind::Label(label) => {
+                    labels.insert(label.as_str(), segments.len());
+                    segments.push(Vec::new());
+                }
+                _ => segments
+                    .last_mut()
+                    .expect("there is a segment")
+                    .push(statement),
+            }
+        }
+        if labels.is_empty() {
+            for statement in &segments[0] {
+                self.statement(statement)?;
+            }
+            return Ok(());
+        }
+
+        let id = self.new_block();
+        let pc = match pc {
+            Some(pc) => pc.to_string(),
+            None => {
+                let pc = format!("$pc.{id}");
+                self.locals.push((pc.clone(), "i32"));
+                self.line("i32.const 0");
+                self.line(&format!("local.set {pc}"));
+                pc
+            }
+        };
+        let name = format!("$dispatch.{id}");
+        self.dispatchers.push(Dispatcher {
+            pc: pc.clone(),
+            name: name.clone(),
+            labels,
+        });
+
+        self.line(&format!("loop {name}"));
+        self.indent += 1;
+        let blocks: Vec<String> = (0..segments.len())
+            .map(|segment| format!("$segment.{id}.{segment}"))
+            .collect();
+        for block in blocks.iter().rev() {
+            self.line(&format!("block {block}"));
+        }
+        self.line(&format!("local.get {pc}"));
+        self.line(&format!("br_table {}", blocks.join(" ")));
+        for segment in segments {
+            self.line("end");
+            for statement in segment {
+                self.statement(statement)?;
+            }
+        }
+        self.indent -= 1;
+        self.line("end");
+
+        self.dispatchers.pop();
+        Ok(())
+    }
+
+    /// Offset and length of a string in memory, added the first time.
+    fn string(&mut self, s: &str) -> (usize, usize) {
+        if let Some(string) = self.strings.get(s) {
+            return *string;
+        }
+        let string = (STRINGS_START + self.data_len, s.len());
+        self.data += &wat_bytes(s);
+        self.data_len += s.len();
+        self.strings.insert(s.to_string(), string);
+        string
+    }
+
+    fn push_string(&mut self, s: &str) {
+        let (offset, length) = self.string(s);
+        self.line(&format!("i32.const {offset}"));
+        self.line(&format!("i32.const {length}"));
+    }
+
+    /// Instruction suffix and name of a variable, a local of a procedure or
+    /// a global of the main program.
+    fn variable(&self, var: &str, span: Span) -> Result<String> {
+        if let Some(ty) = self.types.variables.get(var) {
+            supported(BACKEND, *ty, span)?;
+        }
+        let scope = if self.in_procedure { "local" } else { "global" };
+        Ok(format!("{scope}.{{}} {}", var_name(var)))
+    }
+
+    fn get(&mut self, var: &str) {
+        self.line(&var.replace("{}", "get"));
+    }
+
+    fn set(&mut self, var: &str) {
+        self.line(&var.replace("{}", "set"));
+    }
+
+    fn statement(&mut self, s: &'a Statement) -> Result<()> {
+        match &s.kind {
+            StatementKind::Print(expr) => {
+                self.expression(expr)?;
+                self.line("call $print_i64");
+            }
+            StatementKind::PrintStr(string) => {
+                self.push_string(&format!("{string}\n"));
+                self.line("call $print_str");
+            }
+            StatementKind::Dim(..) => return Err(unsupported(BACKEND, "arrays", s.span)),
+            StatementKind::Let(target, expr) => {
+                self.expression(expr)?;
+                let var = self.target(target, s.span)?;
+                self.set(&var);
+            }
+            StatementKind::Input(target) => {
+                let var = self.target(target, s.span)?;
+                self.line("call $input_i64");
+                self.set(&var);
+            }
+            StatementKind::If(condition, then, otherwise) => {
+                self.condition(condition)?;
+                self.line("if");
+                self.indent += 1;
+                self.statements(then.block(), None)?;
+                if let Some(otherwise) = otherwise {
+                    self.indent -= 1;
+                    self.line("else");
+                    self.indent += 1;
+                    self.statements(otherwise.block(), None)?;
+                }
+                self.indent -= 1;
+                self.line("end");
+            }
+            StatementKind::While(condition, body) => {
+                let id = self.new_block();
+                self.line(&format!("block $endwhile.{id}"));
+                self.indent += 1;
+                self.line(&format!("loop $while.{id}"));
+                self.indent += 1;
+                self.condition(condition)?;
+                self.line("i32.eqz");
+                self.line(&format!("br_if $endwhile.{id}"));
+                self.statements(body.block(), None)?;
+                self.line(&format!("br $while.{id}"));
+                self.indent -= 1;
+                self.line("end");
+                self.indent -= 1;
+                self.line("end");
+            }
+            StatementKind::For {
+                var,
+                from,
+                to,
+                step,
+                body,
+            } => self.for_loop(var, from, to, step.as_deref(), body, s.span)?,
+            // Labels split the statement lists holding them into segments.
+            StatementKind::Label(_) => unreachable!("labels start segments"),
+            StatementKind::Goto(label) => {
+                let dispatcher = self
+                    .dispatchers
+                    .iter()
+                    .rev()
+                    .find(|dispatcher| dispatcher.labels.contains_key(label.as_str()))
+                    .expect("GOTOs target a label of an enclosing list");
+                let segment = dispatcher.labels[label.as_str()];
+                let (pc, name) = (dispatcher.pc.clone(), dispatcher.name.clone());
+                self.line(&format!("i32.const {segment}"));
+                self.line(&format!("local.set {pc}"));
+                self.line(&format!("br {name}"));
+            }
+            // GOSUBs only target labels of the main program outside of any
+            // block, which the outermost dispatcher holds.
+            StatementKind::Gosub(label) => {
+                let segment = self.dispatchers[0].labels[label.as_str()];
+                self.line("call $tb_gosub");
+                self.line(&format!("i32.const {segment}"));
+                self.line("call $tb_main");
+                self.line("global.get $tb_ended");
+                self.line("if");
+                self.line("  return");
+                self.line("end");
+            }
+            StatementKind::Return(value) => {
+                match (value, self.in_procedure) {
+                    (_, false) => self.line("call $tb_return"),
+                    (Some(expr), true) => {
+                        self.expression(expr)?;
+                        self.line("call $tb_call_done");
+                    }
+                    (None, true) => self.line("call $tb_call_done"),
+                }
+                self.line("return");
+            }
+            StatementKind::Call(name, args) => {
+                self.call(name, args)?;
+                if self.procedures[name].result.is_some() {
+                    self.line("drop");
+                }
+            }
+            StatementKind::Statement(first, rest) => {
+                self.statement(first)?;
+                self.statement(rest)?;
+            }
+            StatementKind::End => (),
+        }
+        Ok(())
+    }
+
+    fn target(&self, target: &Target, span: Span) -> Result<String> {
+        match target {
+            Target::Variable(var) => self.variable(var, span),
+            Target::Element(..) => Err(unsupported(BACKEND, "arrays", span)),
+        }
+    }
+
+    /// Hidden local holding the limit or step of a FOR loop, evaluated once
+    /// before the loop starts.
+    fn loop_variable(&mut self, part: &str, id: usize) -> String {
+        let name = format!("$for.{part}.{id}");
+        self.locals.push((name.clone(), "i64"));
+        format!("local.{{}} {name}")
+    }
+
+    /// Runs the same tests as the C backend, see [`crate::backend`].
+    fn for_loop(
+        &mut self,
+        var: &str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+        body: &'a Statement,
+        span: Span,
+    ) -> Result<()> {
+        let var = self.variable(var, span)?;
+        let id = self.new_block();
+        self.expression(from)?;
+        self.set(&var);
+        let limit = self.loop_variable("to", id);
+        self.expression(to)?;
+        self.set(&limit);
+        let step = match step {
+            Some(step) => {
+                let name = self.loop_variable("step", id);
+                self.expression(step)?;
+                self.set(&name);
+                Some(name)
+            }
+            None => None,
+        };
+
+        self.line(&format!("block $next.{id}"));
+        self.indent += 1;
+        self.line(&format!("loop $for.{id}"));
+        self.indent += 1;
+        self.get(&var);
+        self.get(&limit);
+        self.line("i64.le_s");
+        if let Some(step) = &step {
+            self.get(&var);
+            self.get(&limit);
+            self.line("i64.ge_s");
+            self.get(step);
+            self.line("i64.const 0");
+            self.line("i64.ge_s");
+            self.line("select");
+        }
+        self.line("i32.eqz");
+        self.line(&format!("br_if $next.{id}"));
+        self.statements(body.block(), None)?;
+        // A step that overflows is past the limit: one up must leave the
+        // variable at most the largest integer minus it, one down at least
+        // the smallest minus it.
+        match &step {
+            Some(step) => {
+                self.get(&var);
+                self.line(&format!("i64.const {}", i64::MAX));
+                self.get(step);
+                self.line("i64.sub");
+                self.line("i64.gt_s");
+                self.get(&var);
+                self.line(&format!("i64.const {}", i64::MIN));
+                self.get(step);
+                self.line("i64.sub");
+                self.line("i64.lt_s");
+                self.get(step);
+                self.line("i64.const 0");
+                self.line("i64.ge_s");
+                self.line("select");
+            }
+            None => {
+                self.get(&var);
+                self.line(&format!("i64.const {}", i64::MAX));
+                self.line("i64.eq");
+            }
+        }
+        self.line(&format!("br_if $next.{id}"));
+        self.get(&var);
+        match &step {
+            Some(step) => self.get(step),
+            None => self.line("i64.const 1"),
+        }
+        self.line("i64.add");
+        self.set(&var);
+        self.line(&format!("br $for.{id}"));
+        self.indent -= 1;
+        self.line("end");
+        self.indent -= 1;
+        self.line("end");
+        Ok(())
+    }
+
+    /// Pushes an `i32` telling whether the condition holds. AND and OR only
+    /// evaluate their right side when it decides the result.
+    fn condition(&mut self, c: &'a Condition) -> Result<()> {
+        match &c.kind {
+            ConditionKind::Comparison(comparison) => self.comparison(comparison)?,
+            ConditionKind::Not(condition) => {
+                self.condition(condition)?;
+                self.line("i32.eqz");
+            }
+            ConditionKind::And(left, right) => {
+                self.condition(left)?;
+                self.line("if (result i32)");
+                self.indent += 1;
+                self.condition(right)?;
+                self.indent -= 1;
+                self.line("else");
+                self.line("  i32.const 0");
+                self.line("end");
+            }
+            ConditionKind::Or(left, right) => {
+                self.condition(left)?;
+                self.line("if (result i32)");
+                self.line("  i32.const 1");
+                self.line("else");
+                self.indent += 1;
+                self.condition(right)?;
+                self.indent -= 1;
+                self.line("end");
+            }
+        }
+        Ok(())
+    }
+
+    /// A chain compares the result of each comparison with the next operand,
+    /// like C.
+    fn comparison(&mut self, c: &'a Comparison) -> Result<()> {
+        let (first, rest) = c.chain();
+        self.expression(first)?;
+        if rest.is_empty() {
+            self.line("i64.const 0");
+            self.line("i64.ne");
+            return Ok(());
+        }
+
+        for (i, (op, expr)) in rest.iter().enumerate() {
+            if i > 0 {
+                self.line("i64.extend_i32_u");
+            }
+            self.expression(expr)?;
+            let instruction = match *op {
+                "==" => "i64.eq",
+                "!=" => "i64.ne",
+                "<" => "i64.lt_s",
+                "<=" => "i64.le_s",
+                ">" => "i64.gt_s",
+                ">=" => "i64.ge_s",
+                _ => unreachable!("unknown comparison operator `{op}`"),
+            };
+            self.line(instruction);
+        }
+        Ok(())
+    }
+
+    fn expression(&mut self, e: &'a Expression) -> Result<()> {
+        match &e.kind {
+            ExpressionKind::Term(term) => self.term(term)?,
+            ExpressionKind::Add(expr, term) => {
+                self.expression(expr)?;
+                self.term(term)?;
+                self.line("i64.add");
+            }
+            ExpressionKind::Sub(expr, term) => {
+                self.expression(expr)?;
+                self.term(term)?;
+                self.line("i64.sub");
+            }
+        }
+        Ok(())
+    }
+
+    fn term(&mut self, t: &'a Term) -> Result<()> {
+        match &t.kind {
+            TermKind::Unary(unary) => self.unary(unary)?,
+            TermKind::Mul(left, right) => {
+                self.term(left)?;
+                self.unary(right)?;
+                self.line("i64.mul");
+            }
+            TermKind::Div(left, right) => {
+                self.term(left)?;
+                self.unary(right)?;
+                self.push_string(&format!(
+                    "error: attempt to divide by zero on line {}\n",
+                    t.span.line
+                ));
+                self.line("call $tb_div");
+            }
+        }
+        Ok(())
+    }
+
+    fn unary(&mut self, u: &'a Unary) -> Result<()> {
+        match &u.kind {
+            UnaryKind::Primary(primary) => self.primary(primary)?,
+            UnaryKind::Positive(unary) => self.unary(unary)?,
+            UnaryKind::Negative(unary) => {
+                self.line("i64.const 0");
+                self.unary(unary)?;
+                self.line("i64.sub");
+            }
+        }
+        Ok(())
+    }
+
+    fn primary(&mut self, p: &'a Primary) -> Result<()> {
+        match &p.kind {
+            PrimaryKind::Integer(v) => self.line(&format!("i64.const {v}")),
+            PrimaryKind::Number(v) => self.line(&format!("i64.const {v}")),
+            PrimaryKind::Float(_) => return Err(unsupported(BACKEND, "floats", p.span)),
+            PrimaryKind::String(_) => return Err(unsupported(BACKEND, "string values", p.span)),
+            PrimaryKind::Variable(var) => {
+                let var = self.variable(var, p.span)?;
+                self.get(&var);
+            }
+            PrimaryKind::Call(..) => {
+                return Err(unsupported(BACKEND, "built-in functions", p.span))
+            }
+            PrimaryKind::FunctionCall(name, args) => self.call(name, args)?,
+            PrimaryKind::Index(..) => return Err(unsupported(BACKEND, "arrays", p.span)),
+            PrimaryKind::Grouped(expr) => self.expression(expr)?,
+        }
+        Ok(())
+    }
+
+    fn call(&mut self, name: &str, args: &'a [Expression]) -> Result<()> {
+        for arg in args {
+            self.expression(arg)?;
+        }
+        self.line(&format!("call {}", fn_name(name)));
+        Ok(())
+    }
+}
+
+/// Name of a variable, a global of the main program or a local of a
+/// procedure.
+fn var_name(var: &str) -> String {
+    wat_id(&format!("v_{var}"))
+}
+
+/// Name of the function of a SUB or FUNCTION.
+fn fn_name(name: &str) -> String {
+    wat_id(&backend::fn_name(name))
+}
+
+/// WAT identifier for `name`. Only ASCII letters, digits and `_` are kept,
+/// every other character is written as its code point in hexadecimal
+/// between dots, which no name holds otherwise: `é` becomes `$v_.e9.`.
+fn wat_id(name: &str) -> String {
+    let mut id = String::from('$');
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '_' => id.push(c),
+            _ => id.push_str(&format!(".{:x}.", c as u32)),
+        }
+    }
+    id
+}
+
+/// Contents of a WAT string holding `s`, everything but printable ASCII
+/// written as a hexadecimal escape.
+fn wat_bytes(s: &str) -> String {
+    backend::escape_bytes(s, |byte| format!("\\{byte:02x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Whether the lines of `wat`, without their indentation, hold `lines`
+    /// one after the other.
+    fn has_lines(wat: &str, lines: &[&str]) -> bool {
+        let wat: Vec<&str> = wat.lines().map(str::trim).collect();
+        wat.windows(lines.len()).any(|window| window == lines)
+    }
+
+    #[test]
+    fn labels_become_segments_of_a_dispatcher() {
+        let parser = crate::check(
+            "LET i = 0\nLABEL top\nLET i = i + 1\nIF i < 3 THEN\nGOTO top\nENDIF\n\
+             GOTO done\nPRINT 99\nLABEL done\nPRINT i\n",
+        )
+        .unwrap();
+        let wat = compile(&parser).unwrap();
+        assert!(has_lines(
+            &wat,
+            &[
+                "loop $dispatch.1",
+                "block $segment.1.2",
+                "block $segment.1.1",
+                "block $segment.1.0",
+                "local.get $pc",
+                "br_table $segment.1.0 $segment.1.1 $segment.1.2",
+                "end",
+            ]
+        ));
+        // Backward to `top`, then forward to `done`.
+        assert!(has_lines(
+            &wat,
+            &["i32.const 1", "local.set $pc", "br $dispatch.1"]
+        ));
+        assert!(has_lines(
+            &wat,
+            &["i32.const 2", "local.set $pc", "br $dispatch.1"]
+        ));
+    }
+
+    #[test]
+    fn procedures_dispatch_on_a_local() {
+        let parser = crate::check(
+            "CALL s(2)\nSUB s(n)\nLABEL again\nLET n = n - 1\nIF n > 0 THEN\n\
+             GOTO again\nENDIF\nENDSUB\n",
+        )
+        .unwrap();
+        let wat = compile(&parser).unwrap();
+        assert!(has_lines(
+            &wat,
+            &[
+                "(local $pc.1 i32)",
+                "call $tb_call",
+                "i32.const 0",
+                "local.set $pc.1",
+                "loop $dispatch.1",
+            ]
+        ));
+        assert!(has_lines(
+            &wat,
+            &["local.get $pc.1", "br_table $segment.1.0 $segment.1.1"]
+        ));
+        assert!(has_lines(
+            &wat,
+            &["i32.const 1", "local.set $pc.1", "br $dispatch.1"]
+        ));
+    }
+
+    #[test]
+    fn names_are_valid_ids() {
+        let parser = crate::check("LET é = 3\nPRINT é\n").unwrap();
+        let wat = compile(&parser).unwrap();
+        assert!(wat.contains("(global $v_.e9. (mut i64) (i64.const 0))"));
+        assert!(!wat.contains('é'));
+    }
+
+    #[test]
+    fn escaped_names_cannot_clash() {
+        assert_eq!(var_name("é"), "$v_.e9.");
+        assert_eq!(var_name("_e9_"), "$v__e9_");
+        assert_eq!(var_name("a.b"), "$v_a.2e.b");
+        assert_eq!(fn_name("dé"), "$fn_d.e9.");
+    }
+
+    #[test]
+    fn strings_are_escaped() {
+        assert_eq!(wat_bytes("a\"\\é"), "a\\22\\5c\\c3\\a9");
+    }
+}
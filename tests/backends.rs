@@ -0,0 +1,344 @@
+//! Runs programs with the interpreter, the bytecode VM and every backend whose
+//! tools are installed, and checks that they all print the same output and
+//! stop with the same errors. Backends whose tools are missing are skipped.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+const COMPILER: &str = env!("CARGO_BIN_EXE_basic_compiler");
+
+/// Runs the WebAssembly module given as argument under node, with the imports
+/// the README describes. Input is checked like the interpreter does. A
+/// runtime error prints its message then traps, so the last string printed
+/// before a trap goes to stderr.
+const RUN_WASM: &str = r#"
+import fs from "node:fs";
+const lines = fs.readFileSync(0, "utf8").split(/(?<=\n)/);
+let stdout = "", message = null, memory;
+function fail(error) {
+  process.stdout.write(stdout);
+  process.stderr.write(error + "\n");
+  process.exit(1);
+}
+const env = {
+  print_i64: (value) => { stdout += value + "\n"; message = null; },
+  print_str: (offset, length) => {
+    message = Buffer.from(memory.buffer, offset, length).toString();
+    stdout += message;
+  },
+  input_i64: () => {
+    if (lines.length === 0 || lines[0] === "") fail("error: unexpected end of input");
+    const line = lines.shift().trim();
+    const value = /^[+-]?[0-9]+$/.test(line) ? BigInt(line) : null;
+    if (value === null || value !== BigInt.asIntN(64, value)) {
+      fail(`error: expected an integer as input, got \`${line}\``);
+    }
+    return value;
+  },
+};
+const { instance } = await WebAssembly.instantiate(fs.readFileSync(process.argv[1]), { env });
+memory = instance.exports.memory;
+try {
+  instance.exports.main();
+} catch (error) {
+  if (message === null) throw error;
+  stdout = stdout.slice(0, -message.length);
+  fail(message.trimEnd());
+}
+process.stdout.write(stdout);
+"#;
+
+/// What a run of a program printed, and the first line of its error if it
+/// failed.
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    stdout: String,
+    error: Option<String>,
+}
+
+fn run(command: &mut Command, input: &str) -> Outcome {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("the program starts");
+    // A program may stop before reading all of its input.
+    let _ = child.stdin.take().unwrap().write_all(input.as_bytes());
+    let output = child.wait_with_output().unwrap();
+
+    Outcome {
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        error: (!output.status.success()).then(|| {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            stderr.lines().next().unwrap_or_default().to_string()
+        }),
+    }
+}
+
+fn find_in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// Backends whose tools are installed.
+fn backends() -> Vec<&'static str> {
+    let cc = std::env::var("CC").is_ok_and(|cc| !cc.is_empty())
+        || ["cc", "gcc", "clang"].iter().any(|cc| find_in_path(cc));
+    let asm = cfg!(all(target_arch = "x86_64", target_os = "linux"))
+        && find_in_path("as")
+        && find_in_path("ld");
+    let llvm = find_in_path("clang") || (find_in_path("llc") && cc);
+    let wasm = find_in_path("wat2wasm") && find_in_path("node");
+
+    let mut backends = Vec::new();
+    for (backend, available) in [("c", cc), ("asm", asm), ("llvm", llvm), ("wasm", wasm)] {
+        match available {
+            true => backends.push(backend),
+            false => eprintln!("skipping the {backend} backend, its tools are not installed"),
+        }
+    }
+    backends
+}
+
+/// Directory of its own for the executables of a test, removed when dropped.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir =
+            std::env::temp_dir().join(format!("tinybasic-test-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Builds `program` with `backend` into `executable`, the module run by
+/// [`RUN_WASM`] for WebAssembly. `None` when the backend rejects a feature
+/// the program uses.
+fn build(program: &Path, backend: &str, executable: &Path) -> Option<()> {
+    let name = program.file_stem().unwrap().to_string_lossy();
+    let wat = executable.with_extension("wat");
+    let mut command = Command::new(COMPILER);
+    command.arg("build").arg(program);
+    match backend {
+        "wasm" => command.arg("--emit=wat").arg("-o").arg(&wat),
+        _ => command
+            .arg(format!("--backend={backend}"))
+            .arg("-o")
+            .arg(executable),
+    };
+    let output = command.output().unwrap();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    if stderr.contains("TB0027") {
+        return None;
+    }
+    assert!(
+        output.status.success(),
+        "{name} fails to build with {backend}:\n{stderr}"
+    );
+
+    if backend == "wasm" {
+        let output = Command::new("wat2wasm")
+            .arg(&wat)
+            .arg("-o")
+            .arg(executable)
+            .output()
+            .unwrap();
+        assert!(
+            output.status.success(),
+            "{name} is not valid WebAssembly:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Some(())
+}
+
+/// Command running what [`build`] made of a program with `backend`.
+fn executable(backend: &str, executable: &Path) -> Command {
+    match backend {
+        "wasm" => {
+            let mut command = Command::new("node");
+            command
+                .arg("--input-type=module")
+                .arg("-e")
+                .arg(RUN_WASM)
+                .arg(executable);
+            command
+        }
+        _ => Command::new(executable),
+    }
+}
+
+/// Checks that `program` behaves the same everywhere on each of `inputs`.
+/// A backend rejecting a feature the program uses is skipped for it.
+fn check(program: &Path, inputs: &[&str], dir: &Path) {
+    let name = program.file_stem().unwrap().to_string_lossy();
+    let mut executables = Vec::new();
+    for backend in backends() {
+        let executable = dir.join(format!("{name}-{backend}"));
+        if build(program, backend, &executable).is_some() {
+            executables.push((backend, executable));
+        }
+    }
+
+    for input in inputs {
+        let expected = run(Command::new(COMPILER).arg("run").arg(program), input);
+        let vm = run(
+            Command::new(COMPILER).arg("run").arg("--vm").arg(program),
+            input,
+        );
+        assert_eq!(
+            vm, expected,
+            "{name} differs on the VM with input {input:?}"
+        );
+
+        for (backend, executable) in &executables {
+            let outcome = run(&mut self::executable(backend, executable), input);
+            assert_eq!(
+                outcome.stdout, expected.stdout,
+                "{name} prints differently with {backend} with input {input:?}"
+            );
+            // Compiled programs add the line to the error, that the
+            // interpreter shows in a snippet instead.
+            match (&outcome.error, &expected.error) {
+                (None, None) => (),
+                (Some(error), Some(expected)) if error.starts_with(expected.as_str()) => (),
+                (error, expected) => panic!(
+                    "{name} stops with {error:?} with {backend} rather than {expected:?} \
+                     with input {input:?}"
+                ),
+            }
+        }
+    }
+}
+
+fn write_program(dir: &Path, name: &str, source: &str) -> PathBuf {
+    let path = dir.join(format!("{name}.tb"));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+#[test]
+fn examples_behave_the_same_everywhere() {
+    let dir = TempDir::new("examples");
+    let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut programs: Vec<PathBuf> = std::fs::read_dir(&examples)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "tb"))
+        .collect();
+    programs.sort();
+    assert!(!programs.is_empty());
+
+    for program in programs {
+        let input = std::fs::read_to_string(program.with_extension("in")).unwrap_or_default();
+        check(&program, &[&input], &dir.0);
+    }
+}
+
+#[test]
+fn input_is_checked_the_same_everywhere() {
+    let dir = TempDir::new("input");
+    let program = write_program(
+        &dir.0,
+        "input",
+        "LET total = 0\nLET n = 1\nWHILE n != 0 REPEAT\nINPUT n\n\
+         LET total = total + n\nENDWHILE\nPRINT total\n",
+    );
+    let inputs = [
+        "5\n  -3\n+10\n0\n",
+        "\t7\r\n0",
+        "9223372036854775807\n-9223372036854775808\n0\n",
+        "5\n\n",
+        "5\n+10 apples\n",
+        "99999999999999999999\n",
+        "-9223372036854775809\n",
+        "-\n",
+        "5\n",
+        "",
+    ];
+    check(&program, &inputs, &dir.0);
+}
+
+#[test]
+fn float_input_is_checked_the_same_everywhere() {
+    let dir = TempDir::new("float-input");
+    let program = write_program(&dir.0, "floats", "LET f = 0.5\nINPUT f\nPRINT f * 2\n");
+    let inputs = [" 2.5e1 \n", ".5\n", "inf\n", "0x10\n", "1.5x\n", "\n", ""];
+    check(&program, &inputs, &dir.0);
+}
+
+#[test]
+fn arithmetic_wraps_the_same_everywhere() {
+    let dir = TempDir::new("arithmetic");
+    let program = write_program(
+        &dir.0,
+        "arithmetic",
+        "LET m = 0 - 1\nLET a = 9223372036854775807\nPRINT a + 1\nPRINT a * 3\n\
+         LET b = m - a\nPRINT b / m\nPRINT -b\nPRINT 7 / (a - a)\nPRINT 1\n",
+    );
+    check(&program, &[""], &dir.0);
+}
+
+#[test]
+fn names_cannot_clash_with_generated_ones() {
+    let dir = TempDir::new("names");
+    let program = write_program(
+        &dir.0,
+        "names",
+        "LET int = 4\nLET printf = 1\nLET main = 5\nLET tb_to_0 = 2\nLET é = 3\n\
+         FOR i = 1 TO 2\nPRINT int + printf + main + tb_to_0 + é\nNEXT\n",
+    );
+    check(&program, &[""], &dir.0);
+}
+
+#[test]
+fn string_builtins_behave_the_same_everywhere() {
+    let dir = TempDir::new("strings");
+    let program = write_program(
+        &dir.0,
+        "strings",
+        "LET s$ = \"aéb\"\nPRINT LEN(s$)\nPRINT LEN(LEFT$(s$, 2))\nPRINT LEFT$(s$, 2)\n\
+         PRINT MID$(s$, 3)\nPRINT MID$(s$, 2, 2) + \"!\"\nPRINT VAL(\"0x1A\")\n\
+         PRINT VAL(\"inf\")\nPRINT VAL(\"nan\")\nPRINT VAL(\" \t-12.5e1x\")\nPRINT VAL(\"1e\")\n\
+         PRINT VAL(\"+.5\")\nPRINT VAL(\".\")\nINPUT s$\nPRINT LEN(s$)\nPRINT VAL(s$)\n",
+    );
+    check(&program, &["é 3\r\n", " 2.5E-1é\n"], &dir.0);
+}
+
+#[test]
+fn for_loops_stop_at_the_end_of_the_integers() {
+    let dir = TempDir::new("for");
+    let program = write_program(
+        &dir.0,
+        "for",
+        "FOR i = 9223372036854775806 TO 9223372036854775807\nPRINT i\nNEXT\nPRINT i\n\
+         FOR j = 0 - 9223372036854775807 TO 0 - 9223372036854775807 - 1 STEP 0 - 1\n\
+         PRINT j\nNEXT\nPRINT j\nFOR k = 1 TO 9223372036854775807 STEP 4611686018427387904\n\
+         PRINT k\nNEXT\nPRINT k\n",
+    );
+    check(&program, &[""], &dir.0);
+}
+
+#[test]
+fn conditions_short_circuit_the_same_everywhere() {
+    let dir = TempDir::new("conditions");
+    let program = write_program(
+        &dir.0,
+        "conditions",
+        "LET x = 0\nIF x != 0 AND 10 / x > 1 THEN\nPRINT 1\nENDIF\n\
+         IF x == 0 OR 10 / x > 1 THEN\nPRINT 2\nENDIF\n\
+         IF NOT x == 0 AND x == 0 OR x == 0 THEN\nPRINT 3\nENDIF\n\
+         WHILE x < 3 AND 6 / (3 - x) > 1 REPEAT\nLET x = x + 1\nENDWHILE\nPRINT x\n\
+         IF x == 3 AND 1 / (x - 3) == 0 THEN\nPRINT 4\nENDIF\n",
+    );
+    check(&program, &[""], &dir.0);
+}
@@ -0,0 +1,63 @@
+//! Runs programs through the library functions that embed the interpreter and
+//! the bytecode VM.
+
+use basic_compiler::error::RunError;
+use basic_compiler::interpreter::RuntimeError;
+use basic_compiler::{interpret, run_bytecode};
+
+const SUM: &str = include_str!("../examples/sum.tb");
+
+#[test]
+fn interpret_reads_input_and_writes_output() {
+    let mut output = Vec::new();
+    interpret(SUM, &b"5\n  -3\n+10\n0\n"[..], &mut output).unwrap();
+    assert_eq!(output, b"12\n");
+}
+
+#[test]
+fn run_bytecode_matches_interpret() {
+    let mut output = Vec::new();
+    let steps = run_bytecode(SUM, &b"5\n  -3\n+10\n0\n"[..], &mut output, None).unwrap();
+    assert_eq!(output, b"12\n");
+    assert!(steps > 0);
+}
+
+#[test]
+fn run_bytecode_stops_at_the_budget() {
+    let result = run_bytecode("LABEL top\nGOTO top\n", &b""[..], Vec::new(), Some(100));
+    assert!(matches!(
+        result,
+        Err(RunError::Runtime(RuntimeError::StepLimit {
+            limit: 100,
+            ..
+        }))
+    ));
+}
+
+#[test]
+fn runtime_errors_are_returned() {
+    let result = interpret(SUM, &b"5\n+10 apples\n"[..], Vec::new());
+    assert!(matches!(
+        result,
+        Err(RunError::Runtime(RuntimeError::InvalidInput { .. }))
+    ));
+
+    let result = run_bytecode(SUM, &b"5\n"[..], Vec::new(), None);
+    assert!(matches!(
+        result,
+        Err(RunError::Runtime(RuntimeError::EndOfInput { .. }))
+    ));
+}
+
+#[test]
+fn compile_errors_are_returned() {
+    let source = "GOTO nowhere\n";
+    assert!(matches!(
+        interpret(source, &b""[..], Vec::new()),
+        Err(RunError::Compile(errors)) if !errors.is_empty()
+    ));
+    assert!(matches!(
+        run_bytecode(source, &b""[..], Vec::new(), None),
+        Err(RunError::Compile(errors)) if !errors.is_empty()
+    ));
+}